@@ -8,21 +8,118 @@
 //! historical entries is detectable via formal verification.
 //!
 //! INTEGRITY MODEL:
-//! Each `LogEntry` carries the SHA-256 hash of the preceding entry.
-//! The chain starts with a fixed `GENESIS_HASH`.
+//! Each `LogEntry` carries the hash of the preceding entry (SHA-256 unless
+//! the log says otherwise) and an explicit sequence number starting at 0.
+//! The chain starts with a fixed `GENESIS_HASH`. Hashes are computed over a
+//! canonical, versioned byte encoding rather than over the JSON line, so
+//! serialisation library upgrades cannot invalidate existing logs.
+//!
+//! CRASH RECOVERY:
+//! A crash mid-`append` can leave a partial final line (a "torn tail").
+//! `AuditLog::open` refuses such a log by default; opening it with
+//! `RecoveryMode::Quarantine` moves the torn bytes aside, truncates the log
+//! to its last complete entry and records `Operation::RecoveredFromCrash`.
+//! An unparseable *complete* line is corruption, never a crash artefact,
+//! and is always reported as an error.
+//!
+//! SUBMODULES:
+//! Each feature is documented in its own module:
+//! - `canonical`: the byte encoding entries are hashed over.
+//! - `header`: file headers and switching hash algorithms.
+//! - `format`: NDJSON and binary storage, and converting between them.
+//! - `index`: the sidecar index behind fast open and `AuditLog::entry`.
+//! - `durability`: when appends are synced to disk.
+//! - `clock`: where timestamps come from and what a backwards clock does.
+//! - `context`: who, where and which run each entry came from.
+//! - `outcome`: outcomes and content or git changes attached to entries.
+//! - `schema`: validating the details of custom operations.
+//! - `handle`: sharing one log between threads.
+//! - `layer`: auditing through `tracing`.
+//! - `encryption`: sealed entries on a public chain.
+//! - `redact`: redacted copies with a proof linking them to the original.
+//! - `mirror`: extra copies of the log and reconciling them.
+//! - `segment`: rotation into chained segments.
+//! - `compact`: replacing a prefix with a signed checkpoint.
+//! - `anchor`: pinning the head outside the log.
+//! - `report`: listing every problem in a damaged log.
+//! - `follow`: reading a log as it grows.
+//! - `export`: syslog, CSV and JSON Lines for other tools.
+//! - `query` and `timeline`: runs, transactions and per-path history.
+//! - `migrate`: converting logs written before sequence numbers.
 //!
 //! AUDITED OPERATIONS:
 //! - **Filesystem**: Reads, Writes, Moves, Deletes.
 //! - **Capabilities**: Creation and path resolution events.
 //! - **Git**: Repository status checks and commit actions.
+//! - **Transactions**: Begin, commit and rollback of `fs_ops` transactions,
+//!   with each applied operation tagged by the transaction id.
+//! - **Custom**: Events defined by other components, with a namespaced kind
+//!   and JSON details that `AuditLog::verify_with` can check against a
+//!   `SchemaRegistry`.
 
-use std::fs::{File, OpenOptions};
-use std::io::{self, BufRead, BufReader, Write};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
-use ring::digest::{Context, SHA256};
+use std::sync::Arc;
+use ring::digest::Context;
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
 
+mod anchor;
+mod canonical;
+mod compact;
+mod clock;
+mod context;
+mod durability;
+mod encryption;
+mod export;
+mod follow;
+mod format;
+mod handle;
+mod header;
+mod index;
+mod layer;
+mod migrate;
+mod mirror;
+mod outcome;
+mod query;
+mod redact;
+mod report;
+mod schema;
+mod segment;
+mod timeline;
+
+pub use anchor::LogAnchor;
+pub use compact::{Checkpoint, CheckpointKey, CHECKPOINT_VERSION};
+pub use clock::{Clock, ClockRegression, ClockSkew, ManualClock, SystemClock};
+pub use context::{AuditContext, ScopedContext};
+pub use durability::{AppendTicket, Durability};
+pub use encryption::{Cipher, EncryptionError, EncryptionKey, Keyring};
+pub use export::{ExportFormat, Exporter, EXPORT_COLUMNS, EXPORT_SCHEMA};
+pub use follow::{FollowEvent, Follower};
+pub use format::{convert, LogFormat, BINARY_MAGIC};
+pub use handle::AuditHandle;
+pub use header::{HashAlgorithm, LogHeader, LOG_HEADER_VERSION};
+pub use layer::{AuditLayer, AUDIT_TARGET};
+pub use migrate::migrate_legacy;
+pub use mirror::{reconcile, CopyReport, CopyState, ReconcileReport};
+pub use outcome::{content_hash, Change, EntryMeta, Outcome};
+pub use query::{correlated_entries, group_by_correlation, incomplete_transactions, IncompleteTransaction};
+pub use redact::{redact_log, FieldPolicy, ProofLink, RedactionProof, Redactor};
+pub use report::{ConsistentRange, Finding, Problem, VerifyReport};
+pub use schema::{is_valid_kind, SchemaError, SchemaRegistry};
+pub use segment::{Rotation, SegmentInfo, SegmentManifest};
+pub use timeline::{PathAction, PathEvent, Timeline, TxState};
+
+use durability::Syncer;
+use format::{Record, RecordReader};
+use index::{Index, IndexRecord};
+use mirror::Mirror;
+
+/// Current on-disk entry format version (the first byte of the hash input).
+pub const ENTRY_VERSION: u8 = 1;
+
 /// The sentinel hash used as the `prev_hash` of the very first log entry.
 const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
 
@@ -45,26 +142,146 @@ pub enum Operation {
     GitStatusChecked { repo_path: PathBuf },
     /// A git commit was created.
     GitCommitCreated { repo_path: PathBuf, message: String },
+    /// A torn final record was moved out of the log on open.
+    RecoveredFromCrash { quarantine: PathBuf, offset: u64, bytes: u64 },
+    /// This log was converted from an unversioned legacy log.
+    LegacyMigrated { source: PathBuf, legacy_head: String, entries: u64 },
+    /// An event defined by another component. `kind` is namespaced by the
+    /// component (`elixir.repo_fixed`); see [`is_valid_kind`].
+    Custom {
+        kind: String,
+        #[serde(with = "json_details")]
+        details: serde_json::Value,
+    },
+    /// A directory (and any missing parents) was created.
+    DirCreate { path: PathBuf },
+    /// A filesystem transaction started applying `planned_ops`. The entries
+    /// for the individual operations carry the same `tx_id`.
+    TransactionBegin { tx_id: String, planned_ops: Vec<Operation> },
+    /// Every operation of the transaction was applied.
+    TransactionCommitted { tx_id: String, applied: u64 },
+    /// The transaction stopped after `applied` operations because of `error`.
+    TransactionRolledBack { tx_id: String, applied: u64, error: String },
+    /// The entry's operation and metadata, sealed with key `key_id`; open
+    /// it with [`Keyring::open`]. `nonce` and `ciphertext` are hex.
+    Encrypted { cipher: Cipher, key_id: String, nonce: String, ciphertext: String },
+    /// Entries after this one are hashed with `to` instead of `from`; see
+    /// [`AuditLog::change_hash_algorithm`].
+    HashAlgorithmChanged { from: HashAlgorithm, to: HashAlgorithm },
+}
+
+/// Serde adapter for `Operation::Custom` details: a JSON value in
+/// self-describing formats, a JSON string in the binary format (which
+/// cannot decode arbitrary values).
+mod json_details {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use serde_json::Value;
+
+    pub fn serialize<S: Serializer>(details: &Value, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            details.serialize(serializer)
+        } else {
+            serializer.serialize_str(&details.to_string())
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Value, D::Error> {
+        if deserializer.is_human_readable() {
+            Value::deserialize(deserializer)
+        } else {
+            let text = String::deserialize(deserializer)?;
+            serde_json::from_str(&text).map_err(serde::de::Error::custom)
+        }
+    }
 }
 
 /// A single record in the hash-chained audit log.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogEntry {
+    /// Entry format version; see [`ENTRY_VERSION`].
+    pub version: u8,
+    /// Zero-based position of this entry in the chain.
+    pub seq: u64,
     /// Wall-clock timestamp of the operation (UTC).
     pub timestamp: DateTime<Utc>,
-    /// SHA-256 hex digest of the preceding entry (or `GENESIS_HASH` for entry 0).
+    /// Hex digest of the preceding entry in that entry's hash algorithm (or
+    /// `GENESIS_HASH` for entry 0).
     pub prev_hash: String,
     /// The operation that was performed.
     pub operation: Operation,
+    /// Who, where and as part of which run the operation was performed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<AuditContext>,
+    /// Whether the operation succeeded, failed or was rolled back.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<Outcome>,
+    /// Content hashes or object ids before and after the operation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub change: Option<Change>,
+    /// Transaction the operation was applied as part of.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_id: Option<String>,
+    /// What the clock read when it was behind the previous entry and
+    /// `timestamp` was clamped to keep the log in order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clamped_from: Option<DateTime<Utc>>,
+    /// Algorithm this entry is hashed with, when it is not SHA-256.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_alg: Option<HashAlgorithm>,
 }
 
 impl LogEntry {
-    /// Compute the SHA-256 hash of this entry's serialised form.
-    pub fn hash(&self) -> String {
-        let serialised = serde_json::to_string(self)
+    /// The exact bytes hashed by [`LogEntry::hash`].
+    ///
+    /// The layout is documented in the `canonical` module: version byte,
+    /// sequence number, timestamp, previous hash, then every remaining field
+    /// as a canonically ordered value.
+    pub fn hash_input(&self) -> Vec<u8> {
+        let mut body = serde_json::to_value(self)
             .expect("LogEntry must be serialisable to compute hash");
-        let mut ctx = Context::new(&SHA256);
-        ctx.update(serialised.as_bytes());
+        if let serde_json::Value::Object(map) = &mut body {
+            for header in ["version", "seq", "timestamp", "prev_hash"] {
+                map.remove(header);
+            }
+            if let Some(clamped_from) = self.clamped_from {
+                let secs_nanos = serde_json::json!([clamped_from.timestamp(), clamped_from.timestamp_subsec_nanos()]);
+                map.insert("clamped_from".into(), secs_nanos);
+            }
+        }
+
+        let mut enc = canonical::Encoder::default();
+        enc.u8(self.version)
+            .u64(self.seq)
+            .i64(self.timestamp.timestamp())
+            .u32(self.timestamp.timestamp_subsec_nanos())
+            .str(&self.prev_hash)
+            .value(&body);
+        enc.finish()
+    }
+
+    /// Correlation id from the entry's context, if any.
+    pub fn correlation_id(&self) -> Option<&str> {
+        self.context.as_ref()?.correlation_id.as_deref()
+    }
+
+    /// Algorithm this entry is hashed with.
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.hash_alg.unwrap_or_default()
+    }
+
+    /// Algorithm the entry after this one is hashed with.
+    pub(crate) fn next_hash_algorithm(&self) -> HashAlgorithm {
+        match self.operation {
+            Operation::HashAlgorithmChanged { to, .. } => to,
+            _ => self.hash_algorithm(),
+        }
+    }
+
+    /// Compute the hash of this entry's canonical encoding with its
+    /// [`hash_algorithm`](Self::hash_algorithm).
+    pub fn hash(&self) -> String {
+        let mut ctx = Context::new(self.hash_algorithm().digest());
+        ctx.update(&self.hash_input());
         let digest = ctx.finish();
         hex::encode(digest.as_ref())
     }
@@ -81,106 +298,1059 @@ pub enum IntegrityError {
     #[error("failed to read audit log: {0}")]
     Io(#[from] io::Error),
 
-    /// A log entry could not be deserialised.
+    /// A log entry could not be deserialised (`line` is the record number
+    /// in binary logs).
     #[error("failed to deserialise log entry at line {line}: {cause}")]
     Deserialisation { line: usize, cause: String },
+
+    /// An entry's sequence number is not the next one in the chain.
+    #[error("sequence mismatch at entry {index}: expected seq {expected}, found {found}")]
+    SequenceMismatch { index: usize, expected: u64, found: u64 },
+
+    /// An entry was written in a format version this build cannot hash.
+    #[error("unsupported entry version {version} at line {line}")]
+    UnsupportedVersion { line: usize, version: u8 },
+
+    /// The log predates sequence numbers and must be migrated first.
+    #[error("line {line} is a legacy (unversioned) entry; convert the log with migrate_legacy")]
+    LegacyEntry { line: usize },
+
+    /// A binary record's checksum does not match its payload.
+    #[error("checksum mismatch in record {record} at offset {offset}")]
+    ChecksumMismatch { record: usize, offset: u64 },
+
+    /// A custom operation's details do not satisfy its registered schema.
+    #[error("custom operation {kind} at entry {index} is invalid: {reason}")]
+    InvalidCustomDetails { index: usize, kind: String, reason: String },
+
+    /// An entry's timestamp is earlier than the previous entry's.
+    #[error("timestamp of entry {index} ({found}) is earlier than the previous entry's ({previous})")]
+    TimestampRegression { index: usize, previous: DateTime<Utc>, found: DateTime<Utc> },
+
+    /// A segment does not match what the segment manifest records for it.
+    #[error("segment {segment} does not match its manifest record ({field})")]
+    ManifestMismatch { segment: u32, field: &'static str },
+
+    /// The log no longer matches an anchor taken when it held `entries`
+    /// entries; `found` is the hash of entry `entries - 1` now, if any.
+    #[error("anchor at {entries} entries expected head {expected}, found {found:?}")]
+    AnchorMismatch { entries: u64, expected: String, found: Option<String> },
+
+    /// A log does not match what a redaction proof records for it.
+    #[error("redaction proof does not match at entry {seq} ({field})")]
+    ProofMismatch { seq: u64, field: &'static str },
+
+    /// A compaction checkpoint is malformed, or its signature does not
+    /// verify or comes from an untrusted key.
+    #[error("compaction checkpoint is invalid: {reason}")]
+    InvalidCheckpoint { reason: String },
+
+    /// An archive or log does not match its compaction checkpoint.
+    #[error("compaction checkpoint does not match ({field})")]
+    CheckpointMismatch { field: &'static str },
+
+    /// An entry is hashed with another algorithm than the one its file's
+    /// header and the migration entries before it call for.
+    #[error("entry {index} is hashed with {found}, expected {expected}")]
+    HashAlgorithmMismatch { index: usize, expected: HashAlgorithm, found: HashAlgorithm },
+
+    /// A sealed entry could not be opened.
+    #[error(transparent)]
+    Encryption(#[from] EncryptionError),
+
+    /// The final record is incomplete, most likely from a crash mid-append.
+    #[error("audit log has a torn {bytes}-byte tail at offset {offset}; reopen with RecoveryMode::Quarantine")]
+    TornTail { offset: u64, bytes: u64 },
+}
+
+/// How [`AuditLog::open_with`] treats a torn final record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecoveryMode {
+    /// Refuse to open the log and return [`IntegrityError::TornTail`].
+    #[default]
+    Strict,
+    /// Move the torn bytes into a quarantine file next to the log, truncate
+    /// the log to its last complete entry and record the recovery.
+    Quarantine,
 }
 
-/// An append-only, hash-chained audit log backed by a flat NDJSON file.
+/// Options controlling how an [`AuditLog`] is opened.
+#[derive(Debug, Clone, Default)]
+pub struct AuditLogOptions {
+    /// Behaviour when the log ends with a partially written record.
+    pub recovery: RecoveryMode,
+    /// When appended entries are `fsync`-ed.
+    pub durability: Durability,
+    /// Storage format for a newly created log. Existing logs keep the
+    /// format they were written in.
+    pub format: LogFormat,
+    /// Hash algorithm for a newly created log. Existing logs keep theirs
+    /// until [`AuditLog::change_hash_algorithm`].
+    pub hash_algorithm: HashAlgorithm,
+    /// Log id written to a newly created log's header; random if `None`.
+    pub log_id: Option<String>,
+    /// When to seal the active file into a numbered segment.
+    pub rotation: Rotation,
+    /// Context stamped on every entry; see [`AuditLog::set_context`].
+    pub context: Option<AuditContext>,
+    /// Source of entry timestamps; the system clock if `None`.
+    pub clock: Option<Arc<dyn Clock>>,
+    /// What to do when the clock reads earlier than the last entry.
+    pub clock_skew: ClockSkew,
+    /// Seal every appended entry with the keyring's active key.
+    pub encryption: Option<Keyring>,
+    /// Redact the paths and messages of every appended operation.
+    pub redaction: Option<Redactor>,
+    /// Also append every entry to these files; see [`reconcile`].
+    pub mirrors: Vec<PathBuf>,
+}
+
+/// Optional checks made by [`AuditLog::verify_with`] beyond the chain.
+#[derive(Debug, Clone, Default)]
+pub struct VerifyOptions {
+    /// Validate the details of custom operations against these schemas.
+    pub schemas: Option<SchemaRegistry>,
+    /// With `schemas`, also reject custom operations whose kind has no
+    /// registered schema.
+    pub require_schemas: bool,
+    /// Public keys (hex) trusted to sign compaction checkpoints. Empty
+    /// accepts any checkpoint whose signature verifies.
+    pub checkpoint_keys: Vec<String>,
+}
+
+impl VerifyOptions {
+    /// Kind and reason if `operation` is a custom operation that fails the
+    /// configured schema checks.
+    pub(crate) fn custom_violation(&self, operation: &Operation) -> Option<(String, String)> {
+        let (Operation::Custom { kind, details }, Some(schemas)) = (operation, &self.schemas) else { return None };
+        match schemas.validate(kind, details) {
+            Ok(true) => None,
+            Ok(false) if self.require_schemas => Some((kind.clone(), "no schema registered".into())),
+            Ok(false) => None,
+            Err(reason) => Some((kind.clone(), reason)),
+        }
+    }
+}
+
+/// What opening a log file learned about its contents.
+struct Scan {
+    /// Hash of the last complete entry (or `GENESIS_HASH`).
+    last_hash: String,
+    /// Sequence number the next appended entry will carry.
+    next_seq: u64,
+    /// Byte length of the complete-entry prefix of the file.
+    valid_len: u64,
+    /// Byte length of any trailing partial record.
+    torn_len: u64,
+    /// Storage format of the scanned file.
+    format: LogFormat,
+    /// Sequence number, `prev_hash` and timestamp of the first entry.
+    first: Option<(u64, String, DateTime<Utc>)>,
+    /// Timestamp of the last complete entry.
+    last_timestamp: Option<DateTime<Utc>>,
+    /// Header of the scanned file, if it has one.
+    header: Option<LogHeader>,
+    /// Hash algorithm of the next entry.
+    next_alg: HashAlgorithm,
+}
+
+impl Scan {
+    fn empty(format: LogFormat) -> Self {
+        Scan {
+            last_hash: GENESIS_HASH.to_owned(),
+            next_seq: 0,
+            valid_len: 0,
+            torn_len: 0,
+            format,
+            first: None,
+            last_timestamp: None,
+            header: None,
+            next_alg: HashAlgorithm::default(),
+        }
+    }
+
+    /// The state of `reader`'s file before any entry is read.
+    fn start(reader: &RecordReader) -> Self {
+        Scan {
+            valid_len: reader.start,
+            header: reader.header().cloned(),
+            next_alg: reader.header().map(|h| h.hash_algorithm).unwrap_or_default(),
+            ..Scan::empty(reader.format())
+        }
+    }
+
+    /// Scan the log file at `path` from the beginning.
+    fn read(path: &Path) -> Result<Self, IntegrityError> {
+        let reader = RecordReader::open(path)?;
+        let scan = Scan::start(&reader);
+        scan.read_rest(reader, None)
+    }
+
+    /// Learn the state of the log at `path` from its index, reading only the
+    /// entries the index does not yet cover. A stale index is rebuilt.
+    fn read_indexed(path: &Path, index: &mut Index) -> Result<Self, IntegrityError> {
+        let mut reader = RecordReader::open(path)?;
+        if let Some(scan) = Scan::resume(&mut reader, index)? {
+            return scan.read_rest(reader, Some(index));
+        }
+
+        index.clear()?;
+        reader.seek_to(reader.start, 0)?;
+        let scan = Scan::start(&reader);
+        scan.read_rest(reader, Some(index))
+    }
+
+    /// Trust the index up to its last record if its first and last records
+    /// still match the log, leaving `reader` just past the last one.
+    fn resume(reader: &mut RecordReader, index: &Index) -> Result<Option<Self>, IntegrityError> {
+        let Some(last_pos) = index.len().checked_sub(1) else { return Ok(None) };
+        let (Some(first), Some(last)) = (index.get(0)?, index.get(last_pos)?) else { return Ok(None) };
+        let Some(first) = index::read_indexed(reader, &first, 0) else { return Ok(None) };
+        let Some(last_entry) = index::read_indexed(reader, &last, last_pos) else { return Ok(None) };
+        Ok(Some(Scan {
+            last_hash: last_entry.hash(),
+            next_seq: last_entry.seq + 1,
+            valid_len: last.offset + u64::from(last.len),
+            torn_len: 0,
+            format: reader.format(),
+            first: Some((first.seq, first.prev_hash, first.timestamp)),
+            last_timestamp: Some(last_entry.timestamp),
+            header: reader.header().cloned(),
+            next_alg: last_entry.next_hash_algorithm(),
+        }))
+    }
+
+    /// Continue reading records from `reader`, indexing each entry found.
+    fn read_rest(mut self, mut reader: RecordReader, mut index: Option<&mut Index>) -> Result<Self, IntegrityError> {
+        while let Some(record) = reader.next_record()? {
+            match record {
+                Record::Entry(entry) => {
+                    self.last_hash = entry.hash();
+                    self.next_seq = entry.seq + 1;
+                    self.last_timestamp = Some(entry.timestamp);
+                    self.next_alg = entry.next_hash_algorithm();
+                    if let Some(index) = index.as_deref_mut() {
+                        index.push(&IndexRecord::new(reader.offset, reader.len, entry.seq, &self.last_hash))?;
+                    }
+                    if self.first.is_none() {
+                        self.first = Some((entry.seq, entry.prev_hash, entry.timestamp));
+                    }
+                }
+                Record::Blank => {}
+                Record::Torn => {
+                    self.torn_len = reader.len;
+                    break;
+                }
+            }
+            self.valid_len = reader.offset + reader.len;
+        }
+        Ok(self)
+    }
+}
+
+/// Running state of a hash-chain walk across one or more files.
+pub(crate) struct ChainWalk<'a> {
+    /// Expected `prev_hash` of the next entry; `None` until the first entry
+    /// of an unanchored walk has been seen.
+    prev_hash: Option<String>,
+    /// Expected sequence number of the next entry.
+    next_seq: u64,
+    /// Entries checked so far.
+    pub(crate) count: usize,
+    /// Timestamp of the last entry checked.
+    last_timestamp: Option<DateTime<Utc>>,
+    /// Hash algorithm of the next entry, once a header or entry has said;
+    /// SHA-256 for an unanchored walk of a file without a header.
+    hash_alg: Option<HashAlgorithm>,
+    /// Checks beyond the chain itself.
+    options: Option<&'a VerifyOptions>,
+}
+
+/// What a [`ChainWalk`] saw in a single file.
+pub(crate) struct FileSpan {
+    /// Entries in the file.
+    pub(crate) entries: u64,
+    /// `prev_hash` of the file's first entry.
+    pub(crate) first_prev_hash: Option<String>,
+}
+
+impl<'a> ChainWalk<'a> {
+    /// Start at sequence 0 without constraining the first `prev_hash`.
+    pub(crate) fn unanchored() -> Self {
+        Self { prev_hash: None, next_seq: 0, count: 0, last_timestamp: None, hash_alg: None, options: None }
+    }
+
+    /// Start at `next_seq`, requiring the first entry to chain to `prev_hash`.
+    pub(crate) fn anchored(prev_hash: String, next_seq: u64) -> Self {
+        Self { prev_hash: Some(prev_hash), next_seq, count: 0, last_timestamp: None, hash_alg: None, options: None }
+    }
+
+    /// Also apply the checks in `options` to every entry.
+    pub(crate) fn with_options(mut self, options: &'a VerifyOptions) -> Self {
+        self.options = Some(options);
+        self
+    }
+
+    /// Hash of the last entry checked.
+    pub(crate) fn head_hash(&self) -> &str {
+        self.prev_hash.as_deref().unwrap_or(GENESIS_HASH)
+    }
+
+    /// Start reading a file with `header`, whose algorithm must be the one
+    /// in effect. A file without a header says nothing.
+    pub(crate) fn enter_file(&mut self, header: Option<&LogHeader>) -> Result<(), IntegrityError> {
+        let Some(found) = header.map(|h| h.hash_algorithm) else { return Ok(()) };
+        match self.hash_alg {
+            Some(expected) if expected != found => {
+                Err(IntegrityError::HashAlgorithmMismatch { index: self.next_seq as usize, expected, found })
+            }
+            _ => {
+                self.hash_alg = Some(found);
+                Ok(())
+            }
+        }
+    }
+
+    /// Check that `entry` continues the chain and advance past it.
+    pub(crate) fn check(&mut self, entry: &LogEntry) -> Result<(), IntegrityError> {
+        let index = self.next_seq as usize;
+        // An anchored walk that has seen neither a header nor an entry
+        // takes the first entry's algorithm; the chain still binds it.
+        let expected = match self.hash_alg {
+            None if self.prev_hash.is_some() => entry.hash_algorithm(),
+            alg => alg.unwrap_or_default(),
+        };
+        let found = match entry.operation {
+            Operation::HashAlgorithmChanged { from, .. } if from != entry.hash_algorithm() => from,
+            _ => entry.hash_algorithm(),
+        };
+        if found != expected {
+            return Err(IntegrityError::HashAlgorithmMismatch { index, expected, found });
+        }
+        if let Some(expected) = &self.prev_hash {
+            if entry.prev_hash != *expected {
+                return Err(IntegrityError::ChainBroken {
+                    index,
+                    expected: expected.clone(),
+                    found: entry.prev_hash.clone(),
+                });
+            }
+        }
+
+        // Sequence numbers are dense, so a deleted line cannot be hidden
+        // by re-chaining the survivors.
+        if entry.seq != self.next_seq {
+            return Err(IntegrityError::SequenceMismatch { index, expected: self.next_seq, found: entry.seq });
+        }
+
+        if let Some((kind, reason)) = self.options.and_then(|o| o.custom_violation(&entry.operation)) {
+            return Err(IntegrityError::InvalidCustomDetails { index, kind, reason });
+        }
+
+        // Appends clamp or refuse a backwards clock, so a regression means
+        // the entry was written by something else.
+        if let Some(previous) = self.last_timestamp.filter(|&t| entry.timestamp < t) {
+            return Err(IntegrityError::TimestampRegression { index, previous, found: entry.timestamp });
+        }
+
+        self.prev_hash = Some(entry.hash());
+        self.last_timestamp = Some(entry.timestamp);
+        self.hash_alg = Some(entry.next_hash_algorithm());
+        self.next_seq += 1;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Check every entry in the file at `path`; a torn tail is an error.
+    pub(crate) fn walk_file(&mut self, path: &Path) -> Result<FileSpan, IntegrityError> {
+        let mut reader = RecordReader::open(path)?;
+        self.enter_file(reader.header())?;
+        let mut span = FileSpan { entries: 0, first_prev_hash: None };
+        while let Some(record) = reader.next_record()? {
+            let entry = match record {
+                Record::Entry(entry) => *entry,
+                Record::Blank => continue,
+                Record::Torn => return Err(IntegrityError::TornTail { offset: reader.offset, bytes: reader.len }),
+            };
+            if span.first_prev_hash.is_none() {
+                span.first_prev_hash = Some(entry.prev_hash.clone());
+            }
+            self.check(&entry)?;
+            span.entries += 1;
+        }
+        Ok(span)
+    }
+}
+
+/// An append-only, hash-chained audit log backed by a flat NDJSON or binary
+/// file, optionally rotated into sealed segments.
 pub struct AuditLog {
-    /// Open file handle (append mode).
-    file: File,
+    /// Path of the active file.
+    path: PathBuf,
+    /// Options the log was opened with.
+    options: AuditLogOptions,
+    /// Storage format of the active file.
+    format: LogFormat,
+    /// Open file handle (append mode), shared with the group-commit writer.
+    file: Arc<File>,
+    /// Sidecar index of the active file.
+    index: Index,
+    /// Applies the configured durability mode.
+    syncer: Syncer,
     /// Hash of the most recently appended entry (or GENESIS_HASH if empty).
     last_hash: String,
+    /// Sequence number for the next appended entry.
+    next_seq: u64,
+    /// Bytes in the active file.
+    active_len: u64,
+    /// Timestamp of the active file's first entry, if it has one.
+    active_since: Option<DateTime<Utc>>,
+    /// Timestamp of the most recently appended entry, if any.
+    last_timestamp: Option<DateTime<Utc>>,
+    /// Source of entry timestamps.
+    clock: Arc<dyn Clock>,
+    /// Sequence number and `prev_hash` the active file starts from.
+    active_start: (u64, String),
+    /// Context stamped on appended entries.
+    context: Option<AuditContext>,
+    /// Copies every record is also written to.
+    mirrors: Vec<Mirror>,
+    /// Header of the active file; `None` for a file written before headers.
+    header: Option<LogHeader>,
+    /// Algorithm the next entry is hashed with.
+    hash_alg: HashAlgorithm,
 }
 
 impl AuditLog {
-    /// Open (or create) an audit log at `path`.
-    ///
-    /// If the file already contains entries the last hash is reconstructed
-    /// by reading the file from the beginning.
-    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        // Derive last_hash by scanning existing entries.
-        let last_hash = if path.as_ref().exists() {
-            Self::last_hash_from_file(path.as_ref())?
-        } else {
-            GENESIS_HASH.to_owned()
+    /// Open (or create) an audit log at `path` with default options.
+    ///
+    /// If the file already contains entries the last hash is taken from the
+    /// tail index after checking it against the log; only entries the index
+    /// does not cover are read. A torn final record is rejected; see
+    /// [`AuditLog::open_with`] to recover from one.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, IntegrityError> {
+        Self::open_with(path, AuditLogOptions::default())
+    }
+
+    /// Open (or create) an audit log at `path` with explicit `options`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IntegrityError::Deserialisation`] if a complete line that
+    /// has to be read is corrupt (every line, when the index is rebuilt),
+    /// and [`IntegrityError::TornTail`] if the log ends with a partial line
+    /// and `options.recovery` is [`RecoveryMode::Strict`]. Use
+    /// [`AuditLog::verify`] to check the whole log.
+    pub fn open_with<P: AsRef<Path>>(path: P, options: AuditLogOptions) -> Result<Self, IntegrityError> {
+        let path = path.as_ref();
+        let mut index = Index::open(path)?;
+        let mut scan = match LogFormat::detect(path)? {
+            Some(_) => Scan::read_indexed(path, &mut index)?,
+            None => {
+                index.clear()?;
+                Scan::empty(options.format)
+            }
         };
 
+        // A fresh active file continues from the last sealed segment, or
+        // from the checkpoint of a log compacted down to nothing.
+        let fresh = LogFormat::detect(path)?.is_none();
+        let mut header = scan.header.clone();
+        if fresh {
+            header = Some(Self::new_header(&options, scan.format, options.hash_algorithm, None)?);
+        }
+        if scan.first.is_none() {
+            if let Some((head, next_seq)) = SegmentManifest::load(path)?.and_then(|m| m.tip()) {
+                let last = Self::entry(path, next_seq - 1)?;
+                scan.last_hash = head;
+                scan.next_seq = next_seq;
+                scan.last_timestamp = last.as_ref().map(|e| e.timestamp);
+                if fresh {
+                    let alg = last.as_ref().map_or(options.hash_algorithm, LogEntry::next_hash_algorithm);
+                    let log_id = Self::entries(path)?.reader.header().map(|h| h.log_id.clone());
+                    header = Some(Self::new_header(&options, scan.format, alg, log_id)?);
+                }
+            } else if let Some(checkpoint) = compact::active_checkpoint(path)? {
+                scan.last_hash = checkpoint.head_hash;
+                scan.next_seq = checkpoint.entries;
+                scan.last_timestamp = checkpoint.last_timestamp;
+            }
+        }
+
+        let file = Self::open_active(path, scan.format, header.as_ref())?;
+        let prelude_len = format::prelude(scan.format, header.as_ref())?.len() as u64;
+        let hash_alg = match (&header, fresh) {
+            (Some(header), true) => header.hash_algorithm,
+            _ => scan.next_alg,
+        };
+        let syncer = Syncer::new(vec![Arc::clone(&file), index.file()], options.durability, scan.next_seq);
+        let active_start = match &scan.first {
+            Some((seq, prev_hash, _)) => (*seq, prev_hash.clone()),
+            None => (scan.next_seq, scan.last_hash.clone()),
+        };
+
+        let mut log = Self {
+            path: path.to_path_buf(),
+            format: scan.format,
+            file,
+            index,
+            syncer,
+            last_hash: scan.last_hash,
+            next_seq: scan.next_seq,
+            active_len: scan.valid_len.max(prelude_len),
+            active_since: scan.first.map(|(_, _, ts)| ts),
+            last_timestamp: scan.last_timestamp,
+            clock: options.clock.clone().unwrap_or_else(|| Arc::new(SystemClock)),
+            active_start,
+            context: options.context.clone().filter(|c| !c.is_empty()),
+            mirrors: Vec::new(),
+            header,
+            hash_alg,
+            options,
+        };
+        log.open_mirrors();
+        if scan.torn_len > 0 {
+            if log.options.recovery == RecoveryMode::Strict {
+                return Err(IntegrityError::TornTail { offset: scan.valid_len, bytes: scan.torn_len });
+            }
+            let quarantine = Self::quarantine_tail(path, &log.file, scan.valid_len, log.clock.now())?;
+            log.append(Operation::RecoveredFromCrash {
+                quarantine,
+                offset: scan.valid_len,
+                bytes: scan.torn_len,
+            })?;
+        }
+        Ok(log)
+    }
+
+    /// Open the active file for appending, writing the format magic and
+    /// `header` if the file is new.
+    fn open_active(path: &Path, format: LogFormat, header: Option<&LogHeader>) -> io::Result<Arc<File>> {
         let file = OpenOptions::new()
             .create(true)
             .append(true)
             .open(path)?;
+        let prelude = format::prelude(format, header)?;
+        if file.metadata()?.len() == 0 && !prelude.is_empty() {
+            (&file).write_all(&prelude)?;
+            file.sync_all()?;
+        }
+        Ok(Arc::new(file))
+    }
 
-        Ok(Self { file, last_hash })
+    /// A header for a new file of a log opened with `options`.
+    fn new_header(
+        options: &AuditLogOptions,
+        format: LogFormat,
+        hash_algorithm: HashAlgorithm,
+        log_id: Option<String>,
+    ) -> io::Result<LogHeader> {
+        let durability = match options.durability {
+            Durability::EveryEntry => "every-entry",
+            Durability::GroupCommit { .. } => "group-commit",
+            Durability::OnFlush => "on-flush",
+        };
+        let format = match format {
+            LogFormat::Ndjson => "ndjson",
+            LogFormat::Binary => "binary",
+        };
+        let params = BTreeMap::from([
+            ("durability".to_owned(), durability.to_owned()),
+            ("format".to_owned(), format.to_owned()),
+            ("tool_version".to_owned(), env!("CARGO_PKG_VERSION").to_owned()),
+        ]);
+        let created_at = options.clock.as_ref().map_or_else(Utc::now, |clock| clock.now());
+        LogHeader::new(hash_algorithm, log_id.or_else(|| options.log_id.clone()), created_at, params)
     }
 
-    /// Read the file and return the hash of the last valid entry.
-    fn last_hash_from_file(path: &Path) -> io::Result<String> {
-        let f = File::open(path)?;
-        let reader = BufReader::new(f);
-        let mut last_hash = GENESIS_HASH.to_owned();
-        for line in reader.lines() {
-            let line = line?;
-            if line.trim().is_empty() { continue; }
-            if let Ok(entry) = serde_json::from_str::<LogEntry>(&line) {
-                last_hash = entry.hash();
-            }
-        }
-        Ok(last_hash)
+    /// Copy everything after `valid_len` into a fresh quarantine file beside
+    /// `path`, then truncate the log back to `valid_len`.
+    ///
+    /// The quarantine copy is `fsync`-ed before the log is truncated so the
+    /// torn bytes are never lost, even if recovery itself is interrupted.
+    fn quarantine_tail(path: &Path, file: &File, valid_len: u64, now: DateTime<Utc>) -> io::Result<PathBuf> {
+        let mut torn = Vec::new();
+        let mut src = File::open(path)?;
+        src.seek(SeekFrom::Start(valid_len))?;
+        src.read_to_end(&mut torn)?;
+
+        let file_name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let stamp = now.format("%Y%m%dT%H%M%S%.9fZ");
+        let quarantine = path.with_file_name(format!("{file_name}.quarantine-{stamp}"));
+
+        let mut out = OpenOptions::new().write(true).create_new(true).open(&quarantine)?;
+        out.write_all(&torn)?;
+        out.sync_all()?;
+
+        file.set_len(valid_len)?;
+        file.sync_all()?;
+        Ok(quarantine)
     }
 
     /// Append `operation` to the log, chaining it to the previous entry.
     ///
-    /// Each entry is written as a single JSON line and then `fsync`-ed to
-    /// ensure physical persistence.
-    pub fn append(&mut self, operation: Operation) -> io::Result<()> {
+    /// Each entry is written as a single record. Whether it is also
+    /// `fsync`-ed before returning depends on the configured [`Durability`];
+    /// the returned ticket reports when it is.
+    pub fn append(&mut self, operation: Operation) -> io::Result<AppendTicket> {
+        self.append_with(operation, EntryMeta::default())
+    }
+
+    /// Append `operation` together with its outcome and before/after state.
+    ///
+    /// ```no_run
+    /// # use capability::audit_log::{self, AuditLog, Change, EntryMeta, Operation, Outcome};
+    /// # let mut log = AuditLog::open("audit.log").unwrap();
+    /// let before = audit_log::content_hash("notes.txt").unwrap();
+    /// std::fs::write("notes.txt", b"new contents").unwrap();
+    /// let after = audit_log::content_hash("notes.txt").unwrap();
+    /// log.append_with(
+    ///     Operation::FileWrite { path: "notes.txt".into() },
+    ///     EntryMeta::outcome(Outcome::Success).with_change(Change::Content { before, after }),
+    /// ).unwrap();
+    /// ```
+    pub fn append_with(&mut self, operation: Operation, meta: EntryMeta) -> io::Result<AppendTicket> {
+        let now = self.clock.now();
+        match self.last_timestamp.filter(|&last| now < last) {
+            None => self.write_entry(now, None, operation, meta),
+            Some(last) if self.options.clock_skew == ClockSkew::Clamp => self.write_entry(last, Some(now), operation, meta),
+            Some(last) => Err(io::Error::new(io::ErrorKind::InvalidData, ClockRegression { last, now })),
+        }
+    }
+
+    /// Append `operation` with an explicit `timestamp` instead of the
+    /// clock's. One earlier than the previous entry's is clamped to it and
+    /// kept in `clamped_from`, whatever the [`ClockSkew`] policy.
+    pub(crate) fn append_at(&mut self, timestamp: DateTime<Utc>, operation: Operation, meta: EntryMeta) -> io::Result<AppendTicket> {
+        match self.last_timestamp.filter(|&last| timestamp < last) {
+            None => self.write_entry(timestamp, None, operation, meta),
+            Some(last) => self.write_entry(last, Some(timestamp), operation, meta),
+        }
+    }
+
+    fn write_entry(
+        &mut self,
+        timestamp: DateTime<Utc>,
+        clamped_from: Option<DateTime<Utc>>,
+        operation: Operation,
+        meta: EntryMeta,
+    ) -> io::Result<AppendTicket> {
+        self.syncer.check()?;
+        if let Operation::Custom { kind, .. } = &operation {
+            if !is_valid_kind(kind) { return Err(schema::invalid_kind(kind)); }
+        }
+        if let Operation::HashAlgorithmChanged { from, .. } = &operation {
+            if *from != self.hash_alg {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("log is hashed with {}, not {from}", self.hash_alg),
+                ));
+            }
+        }
+        let operation = match &self.options.redaction {
+            Some(redactor) => redactor.redact(&operation),
+            None => operation,
+        };
+        if self.active_since.is_some_and(|since| self.options.rotation.is_due(self.active_len, since, timestamp)) {
+            self.rotate()?;
+        }
+
         let entry = LogEntry {
-            timestamp: Utc::now(),
+            version: ENTRY_VERSION,
+            seq: self.next_seq,
+            timestamp,
             prev_hash: self.last_hash.clone(),
             operation,
+            context: self.context.clone(),
+            outcome: meta.outcome,
+            change: meta.change,
+            tx_id: meta.tx_id,
+            clamped_from,
+            hash_alg: Some(self.hash_alg).filter(|&alg| alg != HashAlgorithm::Sha256),
+        };
+        let migration = matches!(entry.operation, Operation::HashAlgorithmChanged { .. });
+        let entry = match &self.options.encryption {
+            Some(keyring) if !migration => keyring.seal(entry).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+            _ => entry,
         };
         let new_hash = entry.hash();
-        let mut line = serde_json::to_string(&entry)
-            .expect("LogEntry must serialise");
-        line.push('\n');
-        self.file.write_all(line.as_bytes())?;
-        self.file.sync_all()?;
-        self.last_hash = new_hash;
+        let record = format::encode_record(&entry, self.format)?;
+        (&*self.file).write_all(&record)?;
+        let sync_mirrors = self.options.durability == Durability::EveryEntry;
+        for mirror in &mut self.mirrors {
+            mirror.write(&record, sync_mirrors);
+        }
+        let indexed = IndexRecord::new(self.active_len, record.len() as u64, entry.seq, &new_hash);
+        self.active_len += record.len() as u64;
+        self.active_since.get_or_insert(timestamp);
+        self.last_timestamp = Some(timestamp);
+        self.last_hash = new_hash.clone();
+        self.hash_alg = entry.next_hash_algorithm();
+        self.next_seq += 1;
+        // A failed index write leaves a stale index, which the next open
+        // detects and repairs; the entry itself is already in the log.
+        self.index.push(&indexed)?;
+        self.syncer.written(entry.seq, new_hash)
+    }
+
+    /// Hash entries appended from now on with `to`, recording the switch as
+    /// an [`Operation::HashAlgorithmChanged`] entry hashed with the current
+    /// algorithm.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`io::ErrorKind::InvalidInput`] if the log already uses `to`.
+    pub fn change_hash_algorithm(&mut self, to: HashAlgorithm) -> io::Result<AppendTicket> {
+        if to == self.hash_alg {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("log is already hashed with {to}")));
+        }
+        self.append(Operation::HashAlgorithmChanged { from: self.hash_alg, to })
+    }
+
+    /// Algorithm the next appended entry is hashed with.
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.hash_alg
+    }
+
+    /// Header of the active file; `None` for a log written before headers.
+    pub fn header(&self) -> Option<&LogHeader> {
+        self.header.as_ref()
+    }
+
+    /// Stamp `context` on every entry appended from now on; an empty
+    /// context clears it.
+    pub fn set_context(&mut self, context: AuditContext) {
+        self.context = Some(context).filter(|c| !c.is_empty());
+    }
+
+    /// The context currently stamped on appended entries.
+    pub fn context(&self) -> Option<&AuditContext> {
+        self.context.as_ref()
+    }
+
+    /// Use `context` for appends made through the returned guard, restoring
+    /// the current context when it is dropped.
+    ///
+    /// ```no_run
+    /// # use capability::audit_log::{AuditContext, AuditLog, Operation};
+    /// # let mut log = AuditLog::open("audit.log").unwrap();
+    /// let run = AuditContext::current().with_correlation_id("fix-repo-42");
+    /// let mut scoped = log.scoped(run);
+    /// scoped.append(Operation::GitStatusChecked { repo_path: "repo".into() }).unwrap();
+    /// ```
+    pub fn scoped(&mut self, context: AuditContext) -> ScopedContext<'_> {
+        ScopedContext::new(self, context)
+    }
+
+    /// Seal entries appended from now on with `key`, keeping earlier keys
+    /// for reading. On a log opened without encryption this turns it on,
+    /// with the default [`Cipher`]; earlier entries stay in plaintext.
+    pub fn rotate_key(&mut self, key: EncryptionKey) {
+        match &mut self.options.encryption {
+            Some(keyring) => keyring.rotate(key),
+            None => self.options.encryption = Some(Keyring::new(Cipher::default(), key)),
+        }
+    }
+
+    /// `fsync` every entry appended so far, regardless of [`Durability`].
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.mirrors.iter_mut().for_each(Mirror::flush);
+        self.syncer.flush()
+    }
+
+    /// Mirrors that are not being written because they do not end where
+    /// the log does, or a write to them failed.
+    pub fn lagging_mirrors(&self) -> Vec<&Path> {
+        self.mirrors.iter().filter(|m| m.is_lagging()).map(Mirror::path).collect()
+    }
+
+    /// Flush, [`reconcile`] the log with its mirrors, repairing lagging
+    /// ones, and resume writing to every mirror that is now in sync.
+    pub fn reconcile_mirrors(&mut self) -> Result<ReconcileReport, IntegrityError> {
+        self.flush()?;
+        let report = reconcile(&self.path, &self.options.mirrors, true)?;
+        self.open_mirrors();
+        Ok(report)
+    }
+
+    fn open_mirrors(&mut self) {
+        if self.options.mirrors.is_empty() {
+            return;
+        }
+        // Unreadable here means unreadable for the mirrors too; they lag.
+        let checkpoint = compact::active_checkpoint(&self.path).ok().flatten();
+        self.mirrors = self.options.mirrors.iter()
+            .map(|path| {
+                Mirror::open(path, self.format, self.header.as_ref(), self.next_seq, &self.last_hash, checkpoint.as_ref())
+            })
+            .collect();
+    }
+
+    /// Seal the active file as the next numbered segment and start a new,
+    /// empty active file that continues the chain.
+    ///
+    /// Does nothing if the active file holds no entries.
+    pub fn rotate(&mut self) -> io::Result<()> {
+        if self.active_since.is_none() { return Ok(()); }
+        self.syncer.flush()?;
+
+        let mut manifest = match SegmentManifest::load(&self.path) {
+            Ok(manifest) => manifest.unwrap_or_default(),
+            Err(IntegrityError::Io(e)) => return Err(e),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        let index = manifest.next_index();
+        let sealed = segment::segment_path(&self.path, index);
+        fs::rename(&self.path, &sealed)?;
+        fs::rename(Index::path_for(&self.path), Index::path_for(&sealed))?;
+        segment::sync_parent(&sealed)?;
+
+        let (first_seq, start_hash) = self.active_start.clone();
+        manifest.segments.push(SegmentInfo {
+            index,
+            file: sealed.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+            format: self.format,
+            first_seq,
+            entries: self.next_seq - first_seq,
+            start_hash,
+            head_hash: self.last_hash.clone(),
+            sealed_at: self.clock.now(),
+        });
+        manifest.store(&self.path)?;
+
+        let header = match &self.header {
+            Some(header) => header.with_algorithm(self.hash_alg),
+            None => Self::new_header(&self.options, self.format, self.hash_alg, None)?,
+        };
+        self.file = Self::open_active(&self.path, self.format, Some(&header))?;
+        self.index = Index::open(&self.path)?;
+        self.index.clear()?;
+        self.syncer = Syncer::new(vec![Arc::clone(&self.file), self.index.file()], self.options.durability, self.next_seq);
+        self.active_len = format::prelude(self.format, Some(&header))?.len() as u64;
+        self.header = Some(header);
+        self.active_since = None;
+        self.active_start = (self.next_seq, self.last_hash.clone());
         Ok(())
     }
 
+    /// Iterate over the entries of the log at `path`, in either format.
+    ///
+    /// A segmented log yields its sealed segments in order, then the active
+    /// file. The iterator stops after yielding the first error, including a
+    /// torn tail. Chain links are not checked; use [`AuditLog::verify`] for that.
+    pub fn entries<P: AsRef<Path>>(path: P) -> Result<Entries, IntegrityError> {
+        let path = path.as_ref();
+        let mut files: Vec<PathBuf> = SegmentManifest::load(path)?
+            .map(|m| m.segments.iter().map(|s| path.with_file_name(&s.file)).collect())
+            .unwrap_or_default();
+        files.push(path.to_path_buf());
+        files.reverse();
+        let first = files.pop().expect("active file is always listed");
+        Ok(Entries { reader: RecordReader::open(&first)?, pending: files, done: false })
+    }
+
+    /// Iterate over the entries of the log at `path` like
+    /// [`AuditLog::entries`], opening sealed entries with `keyring`.
+    ///
+    /// The iterator stops after yielding the first error, including a key
+    /// missing from `keyring`.
+    pub fn decrypted_entries<'k, P: AsRef<Path>>(
+        path: P,
+        keyring: &'k Keyring,
+    ) -> Result<impl Iterator<Item = Result<LogEntry, IntegrityError>> + 'k, IntegrityError> {
+        let mut failed = false;
+        Ok(Self::entries(path)?.map_while(move |entry| {
+            if failed { return None; }
+            let opened = entry.and_then(|e| Ok(keyring.open(&e)?));
+            failed = opened.is_err();
+            Some(opened)
+        }))
+    }
+
+    /// Read the entry with sequence number `seq` from the log at `path`,
+    /// looking in whichever sealed segment holds it.
+    ///
+    /// Uses the tail index for a single seek when it agrees with the log and
+    /// falls back to a scan of that one file otherwise. Returns `None` if no
+    /// such entry exists. The entry's chain link is not checked.
+    pub fn entry<P: AsRef<Path>>(path: P, seq: u64) -> Result<Option<LogEntry>, IntegrityError> {
+        let path = path.as_ref();
+        let manifest = SegmentManifest::load(path)?.unwrap_or_default();
+        let sealed = manifest.segments.iter().find(|s| (s.first_seq..s.first_seq + s.entries).contains(&seq));
+        let (file, first_seq) = match sealed {
+            Some(info) => (path.with_file_name(&info.file), info.first_seq),
+            None => {
+                let first_seq = match manifest.tip() {
+                    Some((_, next)) => next,
+                    None => compact::active_checkpoint(path)?.map_or(0, |c| c.entries),
+                };
+                (path.to_path_buf(), first_seq)
+            }
+        };
+        match seq.checked_sub(first_seq) {
+            Some(position) if file.exists() => index::entry_at(&file, position, first_seq),
+            _ => Ok(None),
+        }
+    }
+
     /// Verify the entire log at `path` by re-computing the hash chain.
     ///
-    /// Returns the number of entries verified if the chain is unbroken.
+    /// For a segmented log every sealed segment is verified against the
+    /// manifest, followed by the active file, as one continuous chain. A
+    /// compacted log is verified from its checkpoint, whose signature must
+    /// check. Returns the number of entries verified if the chain is
+    /// unbroken.
     pub fn verify<P: AsRef<Path>>(path: P) -> Result<usize, IntegrityError> {
-        let f = File::open(path.as_ref())?;
-        let reader = BufReader::new(f);
-        let mut prev_hash = GENESIS_HASH.to_owned();
-        let mut count = 0usize;
+        Self::verify_with(path, &VerifyOptions::default())
+    }
 
-        for (line_idx, line) in reader.lines().enumerate() {
-            let line = line?;
-            if line.trim().is_empty() { continue; }
+    /// Verify the log at `path` like [`AuditLog::verify`], additionally
+    /// applying the checks selected in `options`.
+    pub fn verify_with<P: AsRef<Path>>(path: P, options: &VerifyOptions) -> Result<usize, IntegrityError> {
+        let path = path.as_ref();
+        let walk = match compact::active_checkpoint(path)? {
+            Some(checkpoint) => {
+                checkpoint.verify_signature(&options.checkpoint_keys)?;
+                checkpoint.walk()
+            }
+            None => ChainWalk::unanchored(),
+        };
+        let walk = walk.with_options(options);
+        match SegmentManifest::load(path)? {
+            Some(manifest) => segment::verify_all(path, &manifest, walk),
+            None => {
+                let mut walk = walk;
+                walk.walk_file(path)?;
+                Ok(walk.count)
+            }
+        }
+    }
 
-            let entry: LogEntry = serde_json::from_str(&line)
-                .map_err(|e| IntegrityError::Deserialisation { line: line_idx + 1, cause: e.to_string() })?;
+    /// Scan the whole log at `path`, listing every problem found rather
+    /// than stopping at the first.
+    ///
+    /// Only a missing log or an unreadable segment manifest is an error;
+    /// everything else is reported as a [`Finding`].
+    pub fn verify_report<P: AsRef<Path>>(path: P) -> Result<VerifyReport, IntegrityError> {
+        Self::verify_report_with(path, &VerifyOptions::default())
+    }
 
-            // Every entry except the first must chain to its predecessor.
-            if count > 0 && entry.prev_hash != prev_hash {
-                return Err(IntegrityError::ChainBroken {
-                    index: count,
-                    expected: prev_hash,
-                    found: entry.prev_hash.clone(),
-                });
-            }
+    /// Scan the log at `path` like [`AuditLog::verify_report`], additionally
+    /// applying the checks selected in `options`.
+    pub fn verify_report_with<P: AsRef<Path>>(path: P, options: &VerifyOptions) -> Result<VerifyReport, IntegrityError> {
+        report::scan(path.as_ref(), options)
+    }
 
-            prev_hash = entry.hash();
-            count += 1;
-        }
+    /// Verify the log at `path` like [`AuditLog::verify`], then check that
+    /// it still matches every anchor in `anchors`.
+    pub fn verify_anchored<P: AsRef<Path>>(path: P, anchors: &[LogAnchor]) -> Result<usize, IntegrityError> {
+        let path = path.as_ref();
+        let count = Self::verify(path)?;
+        anchor::check(path, None, anchors)?;
+        Ok(count)
+    }
 
+    /// Verify the full history of a compacted log like
+    /// [`AuditLog::verify_with_archive`], then check every anchor in
+    /// `anchors`, including those taken before the checkpoint, whose head
+    /// is looked up in the archive.
+    pub fn verify_anchored_with_archive<P: AsRef<Path>, Q: AsRef<Path>>(
+        path: P,
+        archive: Q,
+        anchors: &[LogAnchor],
+    ) -> Result<usize, IntegrityError> {
+        let (path, archive) = (path.as_ref(), archive.as_ref());
+        let count = Self::verify_with_archive(path, archive)?;
+        anchor::check(path, Some(archive), anchors)?;
         Ok(count)
     }
+
+    /// Verify the full history of a compacted log: the archive at `archive`
+    /// against the log's checkpoint, then the log from that checkpoint.
+    ///
+    /// Returns the number of entries verified in both.
+    pub fn verify_with_archive<P: AsRef<Path>, Q: AsRef<Path>>(path: P, archive: Q) -> Result<usize, IntegrityError> {
+        let path = path.as_ref();
+        let checkpoint = compact::active_checkpoint(path)?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{} has not been compacted", path.display()))
+        })?;
+        let archived = checkpoint.verify_archive(archive)?;
+        Ok(archived + Self::verify(path)?)
+    }
+
+    /// Replace the first `entries` entries of the log at `path` with a
+    /// checkpoint signed by `key`, appending them to the archive log at
+    /// `archive`. The log must not be open for writing.
+    ///
+    /// To compact a log again, pass the same archive: it must already hold
+    /// everything removed so far, and grows by the newly removed entries.
+    ///
+    /// # Errors
+    ///
+    /// Fails without changing the log if it does not verify, is segmented,
+    /// if `entries` does not lie past the current checkpoint and within the
+    /// log, or if the archive does not match the current checkpoint.
+    pub fn compact<P: AsRef<Path>, Q: AsRef<Path>>(
+        path: P,
+        entries: u64,
+        key: &CheckpointKey,
+        archive: Q,
+    ) -> Result<Checkpoint, IntegrityError> {
+        compact::compact(path.as_ref(), entries, key, archive.as_ref())
+    }
+
+    /// Verify a segmented log starting at sealed segment `index`, trusting
+    /// the manifest's record of where that segment begins.
+    ///
+    /// Earlier segments are not read and may have been archived. Returns the
+    /// number of entries verified.
+    pub fn verify_from_segment<P: AsRef<Path>>(path: P, index: u32) -> Result<usize, IntegrityError> {
+        let path = path.as_ref();
+        let manifest = SegmentManifest::load(path)?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{} has no segment manifest", path.display()))
+        })?;
+        segment::verify_from(path, &manifest, index)
+    }
+}
+
+/// Iterator over the entries of a log file; see [`AuditLog::entries`].
+pub struct Entries {
+    reader: RecordReader,
+    /// Files still to read, last one next.
+    pending: Vec<PathBuf>,
+    done: bool,
+}
+
+impl Iterator for Entries {
+    type Item = Result<LogEntry, IntegrityError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.reader.next_record() {
+                Ok(Some(Record::Entry(entry))) => return Some(Ok(*entry)),
+                Ok(Some(Record::Blank)) => continue,
+                Ok(Some(Record::Torn)) => {
+                    self.done = true;
+                    return Some(Err(IntegrityError::TornTail { offset: self.reader.offset, bytes: self.reader.len }));
+                }
+                Ok(None) => match self.pending.pop() {
+                    Some(next) => match RecordReader::open(&next) {
+                        Ok(reader) => self.reader = reader,
+                        Err(e) => {
+                            self.done = true;
+                            return Some(Err(e.into()));
+                        }
+                    },
+                    None => self.done = true,
+                },
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
 }
 
 // ─── hex helper (avoid pulling in the hex crate) ─────────────────────────────
@@ -190,4 +1360,19 @@ mod hex {
     pub fn encode(bytes: &[u8]) -> String {
         bytes.iter().map(|b| format!("{:02x}", b)).collect()
     }
+
+    /// Decode lowercase hexadecimal, returning `None` for anything that
+    /// [`encode`] could not have produced.
+    pub fn decode(hex: &str) -> Option<Vec<u8>> {
+        if !hex.len().is_multiple_of(2) { return None; }
+        let digit = |c: u8| match c {
+            b'0'..=b'9' => Some(c - b'0'),
+            b'a'..=b'f' => Some(c - b'a' + 10),
+            _ => None,
+        };
+        hex.as_bytes()
+            .chunks(2)
+            .map(|pair| Some(digit(pair[0])? << 4 | digit(pair[1])?))
+            .collect()
+    }
 }
@@ -0,0 +1,80 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Anchors — Pinning the Log's Head Outside the Log.
+//!
+//! A hash chain shows that entries were not altered *relative to each
+//! other*, but someone able to rewrite the whole file can re-chain it. A
+//! [`LogAnchor`] records the entry count and head hash at a point in time so
+//! it can be stored somewhere harder to rewrite quietly — `git_ops` commits
+//! anchors to a git ref, where a rewrite would also have to rewrite git
+//! history that remotes and reflogs remember.
+//!
+//! [`AuditLog::verify_anchored`](super::AuditLog::verify_anchored) verifies
+//! the log and then checks that every anchor still matches: the log must
+//! still hold at least `entries` entries, and entry `entries - 1` must still
+//! hash to `head_hash`. Entries removed by compaction count towards
+//! `entries`; an anchor at the checkpoint itself is checked against the
+//! checkpoint's head, and one taken earlier against the archive with
+//! [`AuditLog::verify_anchored_with_archive`](super::AuditLog::verify_anchored_with_archive).
+
+use std::io;
+use std::path::Path;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{compact, AuditLog, IntegrityError, GENESIS_HASH};
+
+/// The state of a log at one moment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogAnchor {
+    /// Number of entries in the log.
+    pub entries: u64,
+    /// Hash of the last entry, or the genesis hash for an empty log.
+    pub head_hash: String,
+    /// When the anchor was taken.
+    pub anchored_at: DateTime<Utc>,
+}
+
+impl LogAnchor {
+    /// Verify the log at `path` and record its current head.
+    pub fn of<P: AsRef<Path>>(path: P) -> Result<Self, IntegrityError> {
+        let path = path.as_ref();
+        let compacted = compact::active_checkpoint(path)?.map_or(0, |c| c.entries);
+        let entries = compacted + AuditLog::verify(path)? as u64;
+        Ok(Self { entries, head_hash: head_at(path, None, entries)?, anchored_at: Utc::now() })
+    }
+}
+
+/// Check each anchor against the log at `path`, which has already been
+/// verified, looking up compacted entries in `archive` if given (and
+/// already verified against the checkpoint).
+pub(crate) fn check(path: &Path, archive: Option<&Path>, anchors: &[LogAnchor]) -> Result<(), IntegrityError> {
+    for anchor in anchors {
+        let found = head_at(path, archive, anchor.entries).ok();
+        if found.as_deref() != Some(anchor.head_hash.as_str()) {
+            return Err(IntegrityError::AnchorMismatch {
+                entries: anchor.entries,
+                expected: anchor.head_hash.clone(),
+                found,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Hash of the last of the first `entries` entries of the log at `path`,
+/// or of its `archive` if that entry was compacted.
+fn head_at(path: &Path, archive: Option<&Path>, entries: u64) -> Result<String, IntegrityError> {
+    let Some(seq) = entries.checked_sub(1) else { return Ok(GENESIS_HASH.to_owned()) };
+    let mut source = path;
+    if let Some(checkpoint) = compact::active_checkpoint(path)?.filter(|c| seq < c.entries) {
+        if entries == checkpoint.entries {
+            return Ok(checkpoint.head_hash);
+        }
+        source = archive.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("entry {seq} was compacted")))?;
+    }
+    AuditLog::entry(source, seq)?
+        .map(|entry| entry.hash())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("log has no entry {seq}")).into())
+}
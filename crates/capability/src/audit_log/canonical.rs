@@ -0,0 +1,121 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Canonical Entry Encoding — The Hash Input Contract.
+//!
+//! The bytes fed to the chain hash must not depend on how any particular
+//! serde or chrono release chooses to format JSON. This module defines a
+//! small, self-contained binary encoding that is the *only* input to
+//! `LogEntry::hash`.
+//!
+//! HASH INPUT (format version 1, all integers big-endian):
+//!
+//! ```text
+//! entry  := version:u8 seq:u64 secs:i64 nanos:u32 prev_hash:str body:value
+//! str    := len:u32 utf8-bytes
+//! value  := 0x00                          null
+//!         | 0x01 b:u8                     bool (0 or 1)
+//!         | 0x02 n:i64                    integer that fits in i64
+//!         | 0x03 n:u64                    integer above i64::MAX
+//!         | 0x04 bits:u64                 IEEE-754 f64 bit pattern
+//!         | 0x05 str                      string
+//!         | 0x06 count:u32 value*         array
+//!         | 0x07 count:u32 (str value)*   object, keys in byte order
+//! ```
+//!
+//! `secs`/`nanos` are the timestamp as seconds since the Unix epoch plus
+//! the sub-second nanoseconds. `body` is an object holding every entry field
+//! other than the four header fields above — at minimum `operation` in its
+//! serde data-model form (variant name → field object). Optional fields that
+//! are absent are omitted, so adding one never changes existing hashes.
+//! A `clamped_from` timestamp is given in the body as the array
+//! `[secs, nanos]`, encoded like the header timestamp.
+
+use serde_json::Value;
+
+const TAG_NULL: u8 = 0x00;
+const TAG_BOOL: u8 = 0x01;
+const TAG_INT: u8 = 0x02;
+const TAG_UINT: u8 = 0x03;
+const TAG_FLOAT: u8 = 0x04;
+const TAG_STR: u8 = 0x05;
+const TAG_ARRAY: u8 = 0x06;
+const TAG_OBJECT: u8 = 0x07;
+
+/// Append-only byte buffer implementing the canonical encoding.
+#[derive(Default)]
+pub(crate) struct Encoder {
+    out: Vec<u8>,
+}
+
+impl Encoder {
+    pub(crate) fn u8(&mut self, v: u8) -> &mut Self {
+        self.out.push(v);
+        self
+    }
+
+    pub(crate) fn u32(&mut self, v: u32) -> &mut Self {
+        self.out.extend_from_slice(&v.to_be_bytes());
+        self
+    }
+
+    pub(crate) fn u64(&mut self, v: u64) -> &mut Self {
+        self.out.extend_from_slice(&v.to_be_bytes());
+        self
+    }
+
+    pub(crate) fn i64(&mut self, v: i64) -> &mut Self {
+        self.out.extend_from_slice(&v.to_be_bytes());
+        self
+    }
+
+    pub(crate) fn str(&mut self, s: &str) -> &mut Self {
+        self.u32(len_u32(s.len()));
+        self.out.extend_from_slice(s.as_bytes());
+        self
+    }
+
+    /// Encode an arbitrary JSON value using the tagged `value` grammar.
+    pub(crate) fn value(&mut self, v: &Value) -> &mut Self {
+        match v {
+            Value::Null => self.u8(TAG_NULL),
+            Value::Bool(b) => self.u8(TAG_BOOL).u8(u8::from(*b)),
+            Value::Number(n) => {
+                if let Some(i) = n.as_i64() {
+                    self.u8(TAG_INT).i64(i)
+                } else if let Some(u) = n.as_u64() {
+                    self.u8(TAG_UINT).u64(u)
+                } else {
+                    let f = n.as_f64().unwrap_or(f64::NAN);
+                    self.u8(TAG_FLOAT).u64(f.to_bits())
+                }
+            }
+            Value::String(s) => self.u8(TAG_STR).str(s),
+            Value::Array(items) => {
+                self.u8(TAG_ARRAY).u32(len_u32(items.len()));
+                for item in items {
+                    self.value(item);
+                }
+                self
+            }
+            Value::Object(map) => {
+                let mut keys: Vec<&String> = map.keys().collect();
+                keys.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+                self.u8(TAG_OBJECT).u32(len_u32(keys.len()));
+                for key in keys {
+                    self.str(key).value(&map[key]);
+                }
+                self
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.out
+    }
+}
+
+/// Lengths are encoded as `u32`; a single field over 4 GiB is a bug.
+fn len_u32(len: usize) -> u32 {
+    u32::try_from(len).expect("canonical field length exceeds u32::MAX")
+}
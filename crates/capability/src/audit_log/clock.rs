@@ -0,0 +1,91 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Clocks — Where Entry Timestamps Come From.
+//!
+//! An `AuditLog` reads the time from a [`Clock`] set in `AuditLogOptions`
+//! (the system clock by default), so tests can pin or step time with a
+//! [`ManualClock`].
+//!
+//! MONOTONICITY:
+//! Timestamps in a log never decrease. If the clock reads earlier than the
+//! last entry (an NTP step, a VM resume, a hand-edited clock) the
+//! [`ClockSkew`] policy decides what happens: `Clamp` stamps the entry with
+//! the last entry's time and records the real reading in the entry's
+//! `clamped_from` field; `Reject` refuses the append with a
+//! [`ClockRegression`] error. Either way the skew is visible afterwards —
+//! `AuditLog::verify_report` lists clamped entries — and a timestamp that
+//! does go backwards fails `AuditLog::verify`.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, Utc};
+
+/// A source of entry timestamps.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// The current time.
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system wall clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl ManualClock {
+    /// A clock fixed at `start`.
+    pub fn new(start: DateTime<Utc>) -> Self {
+        Self { now: Arc::new(Mutex::new(start)) }
+    }
+
+    /// Set the time to `now`, which may be earlier than before.
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner()) = now;
+    }
+
+    /// Move the time by `by`, which may be negative.
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.now.lock().unwrap_or_else(|e| e.into_inner());
+        *now += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// What an append does when the clock reads earlier than the last entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClockSkew {
+    /// Use the last entry's timestamp and record the clock reading in
+    /// `LogEntry::clamped_from`.
+    #[default]
+    Clamp,
+    /// Fail the append with a [`ClockRegression`].
+    Reject,
+}
+
+/// The clock went backwards and the log's [`ClockSkew`] policy is `Reject`.
+///
+/// Returned inside an `io::Error` of kind `InvalidData` from appends.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("clock reads {now}, earlier than the last entry at {last}")]
+pub struct ClockRegression {
+    /// Timestamp of the last entry in the log.
+    pub last: DateTime<Utc>,
+    /// What the clock read.
+    pub now: DateTime<Utc>,
+}
@@ -0,0 +1,471 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Compaction — Replacing a Prefix With a Signed Checkpoint.
+//!
+//! [`AuditLog::compact`](super::AuditLog::compact) moves the first entries
+//! of a log into a separate archive file and replaces them with a
+//! [`Checkpoint`] stored beside the log:
+//!
+//! ```text
+//! audit.log             entries n.. (unchanged bytes), first chains to the checkpoint
+//! audit.log.checkpoint  signed summary of entries 0..n
+//! archive.log           entries 0..n, a complete log on its own
+//! ```
+//!
+//! THE CHECKPOINT:
+//! A checkpoint records how many entries were removed, the hash of the last
+//! one (which the first remaining entry still names as its `prev_hash`),
+//! the timestamp of the last one, a count of entries per operation variant
+//! (sealed entries count as `Encrypted`) and the Merkle root of the removed
+//! entry hashes. It is signed with an Ed25519 [`CheckpointKey`] over the
+//! canonical encoding of every other field, and carries the public key.
+//!
+//! MERKLE ROOT:
+//! The tree is the RFC 6962 one over the raw entry hashes: a leaf is
+//! `SHA-256(0x00 ‖ hash)`, a node `SHA-256(0x01 ‖ left ‖ right)`, and the
+//! left subtree of `n` leaves holds the largest power of two below `n`.
+//!
+//! VERIFYING:
+//! `AuditLog::verify` on a compacted log checks the signature and then the
+//! remainder as a chain starting at the checkpoint. The signature alone
+//! only shows the checkpoint is intact; list the public keys you trust in
+//! `VerifyOptions::checkpoint_keys` to also require who signed it.
+//! `AuditLog::verify_with_archive` checks the full history instead: the
+//! archive from genesis, its summary against the checkpoint, then the
+//! remainder.
+//!
+//! CRASH SAFETY:
+//! The archive is written and `fsync`-ed first, then the checkpoint, then
+//! the remainder, behind a header naming its first entry's hash algorithm,
+//! is renamed over the log. A checkpoint only takes effect
+//! once the log no longer starts at entry 0, so a crash part way leaves the
+//! uncompacted log exactly as it was; compacting again reuses what already
+//! reached the archive.
+//!
+//! LIMITS:
+//! Only unsegmented logs are compacted — sealed segments are already
+//! archived as whole files (see `verify_from_segment`); a compacted log may
+//! be rotated afterwards. No writer may have the log open while it is
+//! compacted. Mirrors are not compacted, and anchors taken before the
+//! checkpoint are checked against the archive with
+//! `AuditLog::verify_anchored_with_archive`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use ring::digest::{self, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+
+use super::export::variant_name;
+use super::format::{self, Record, RecordReader};
+use super::index::Index;
+use super::segment::{self, SegmentManifest};
+use super::{canonical, hex, ChainWalk, EncryptionError, IntegrityError, LogEntry, LogFormat, LogHeader};
+
+/// Version of the checkpoint record and its signing input.
+pub const CHECKPOINT_VERSION: u32 = 1;
+
+/// Domain separator prefixed to the signing input.
+const SIGNING_CONTEXT: &str = "polysafe-audit-checkpoint";
+
+/// A named Ed25519 key that signs checkpoints. The seed is never printed.
+pub struct CheckpointKey {
+    id: String,
+    seed: [u8; 32],
+    pair: Ed25519KeyPair,
+}
+
+impl CheckpointKey {
+    /// A key called `id` derived from a 32-byte seed.
+    pub fn new(id: impl Into<String>, seed: [u8; 32]) -> Self {
+        let pair = Ed25519KeyPair::from_seed_unchecked(&seed).expect("any 32-byte seed is an Ed25519 key");
+        Self { id: id.into(), seed, pair }
+    }
+
+    /// A fresh random key called `id`.
+    pub fn generate(id: impl Into<String>) -> Result<Self, EncryptionError> {
+        let mut seed = [0u8; 32];
+        SystemRandom::new().fill(&mut seed).map_err(|_| EncryptionError::Random)?;
+        Ok(Self::new(id, seed))
+    }
+
+    /// A key called `id` from a seed of 64 lowercase hex digits.
+    pub fn from_hex(id: impl Into<String>, text: &str) -> Result<Self, EncryptionError> {
+        let id = id.into();
+        let seed = hex::decode(text.trim())
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .ok_or_else(|| EncryptionError::InvalidKey { id: id.clone(), reason: "expected 64 hex digits".into() })?;
+        Ok(Self::new(id, seed))
+    }
+
+    /// The seed as 64 lowercase hex digits, for storing in a key file.
+    pub fn to_hex(&self) -> String {
+        hex::encode(&self.seed)
+    }
+
+    /// The key's id.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The public key as lowercase hex, for `VerifyOptions::checkpoint_keys`.
+    pub fn public_key(&self) -> String {
+        hex::encode(self.pair.public_key().as_ref())
+    }
+}
+
+impl fmt::Debug for CheckpointKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CheckpointKey").field("id", &self.id).finish_non_exhaustive()
+    }
+}
+
+/// Signed summary of the entries removed from the front of a log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Checkpoint format version; see [`CHECKPOINT_VERSION`].
+    pub version: u32,
+    /// Number of entries removed, which is also the sequence number of the
+    /// first remaining entry.
+    pub entries: u64,
+    /// Hash of the last removed entry.
+    pub head_hash: String,
+    /// Timestamp of the last removed entry.
+    pub last_timestamp: Option<DateTime<Utc>>,
+    /// Removed entries per operation variant name.
+    pub op_counts: BTreeMap<String, u64>,
+    /// Merkle root of the removed entry hashes, as hex.
+    pub merkle_root: String,
+    /// When the log was compacted.
+    pub compacted_at: DateTime<Utc>,
+    /// Id of the key that signed the checkpoint.
+    pub key_id: String,
+    /// Ed25519 public key of that key, as hex.
+    pub public_key: String,
+    /// Ed25519 signature over every other field, as hex.
+    pub signature: String,
+}
+
+impl Checkpoint {
+    /// Path of the checkpoint for the log at `log`.
+    pub fn path_for(log: &Path) -> PathBuf {
+        log.with_file_name(format!("{}.checkpoint", segment::file_name(log)))
+    }
+
+    /// Read the checkpoint stored beside the log at `log`, if there is one.
+    pub fn load<P: AsRef<Path>>(log: P) -> Result<Option<Self>, IntegrityError> {
+        match fs::read(Self::path_for(log.as_ref())) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map(Some).map_err(|e| {
+                IntegrityError::Deserialisation { line: 0, cause: format!("checkpoint: {e}") }
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Atomically replace the checkpoint beside the log at `log`.
+    pub(crate) fn store(&self, log: &Path) -> io::Result<()> {
+        let target = Self::path_for(log);
+        let temp = target.with_extension("checkpoint.tmp");
+        let mut out = File::create(&temp)?;
+        out.write_all(&serde_json::to_vec_pretty(self).map_err(io::Error::other)?)?;
+        out.sync_all()?;
+        fs::rename(&temp, &target)?;
+        segment::sync_parent(&target)
+    }
+
+    /// Check the signature, and with a non-empty `trusted` list of public
+    /// keys, that one of them made it.
+    pub fn verify_signature(&self, trusted: &[String]) -> Result<(), IntegrityError> {
+        let invalid = |reason: String| Err(IntegrityError::InvalidCheckpoint { reason });
+        if self.version != CHECKPOINT_VERSION {
+            return invalid(format!("unsupported version {}", self.version));
+        }
+        if !trusted.is_empty() && !trusted.contains(&self.public_key) {
+            return invalid(format!("signed by untrusted key {:?}", self.key_id));
+        }
+        let (Some(public_key), Some(signature)) = (hex::decode(&self.public_key), hex::decode(&self.signature)) else {
+            return invalid("public key or signature is not hex".into());
+        };
+        match UnparsedPublicKey::new(&ED25519, public_key).verify(&self.signing_input(), &signature) {
+            Ok(()) => Ok(()),
+            Err(_) => invalid("signature does not verify".into()),
+        }
+    }
+
+    /// Verify the archive at `archive` as a log of its own and check that it
+    /// holds exactly the entries this checkpoint summarises.
+    ///
+    /// Returns the number of entries verified.
+    pub fn verify_archive<P: AsRef<Path>>(&self, archive: P) -> Result<usize, IntegrityError> {
+        let mut summary = Summary::new();
+        summary.read_file(archive.as_ref(), Some(self))?;
+        if summary.walk.next_seq != self.entries {
+            return Err(IntegrityError::CheckpointMismatch { field: "entries" });
+        }
+        Ok(summary.walk.count)
+    }
+
+    /// The chain walk for the remainder of a log compacted at this
+    /// checkpoint.
+    pub(crate) fn walk(&self) -> ChainWalk<'static> {
+        let mut walk = ChainWalk::anchored(self.head_hash.clone(), self.entries);
+        walk.last_timestamp = self.last_timestamp;
+        walk
+    }
+
+    /// Canonical encoding of every field except the signature.
+    fn signing_input(&self) -> Vec<u8> {
+        let mut value = serde_json::to_value(self).expect("checkpoint serialises");
+        if let Some(fields) = value.as_object_mut() {
+            fields.remove("signature");
+        }
+        let mut encoder = canonical::Encoder::default();
+        encoder.str(SIGNING_CONTEXT).value(&value);
+        encoder.finish()
+    }
+}
+
+/// The checkpoint the log at `path` starts from, if it has been compacted.
+///
+/// A checkpoint whose log still starts at entry 0 is left over from an
+/// interrupted compaction and does not apply.
+pub(crate) fn active_checkpoint(path: &Path) -> Result<Option<Checkpoint>, IntegrityError> {
+    let Some(checkpoint) = Checkpoint::load(path)? else { return Ok(None) };
+    let first_seq = match SegmentManifest::load(path)?.and_then(|m| m.segments.first().map(|s| s.first_seq)) {
+        Some(seq) => Some(seq),
+        None => first_seq(path)?,
+    };
+    Ok((first_seq != Some(0)).then_some(checkpoint))
+}
+
+/// Sequence number of the first entry in the file at `path`.
+fn first_seq(path: &Path) -> Result<Option<u64>, IntegrityError> {
+    let mut reader = match RecordReader::open(path) {
+        Ok(reader) => reader,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    while let Some(record) = reader.next_record()? {
+        match record {
+            Record::Entry(entry) => return Ok(Some(entry.seq)),
+            Record::Blank => {}
+            Record::Torn => break,
+        }
+    }
+    Ok(None)
+}
+
+/// See [`AuditLog::compact`](super::AuditLog::compact).
+pub(crate) fn compact(path: &Path, entries: u64, key: &CheckpointKey, archive: &Path) -> Result<Checkpoint, IntegrityError> {
+    if SegmentManifest::load(path)?.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "segmented logs are compacted by archiving sealed segments; see verify_from_segment",
+        ).into());
+    }
+    let previous = active_checkpoint(path)?;
+    if let Some(checkpoint) = &previous {
+        checkpoint.verify_signature(&[])?;
+    }
+
+    // Everything that stays must verify before anything is moved.
+    let mut kept = previous.as_ref().map_or_else(ChainWalk::unanchored, Checkpoint::walk);
+    kept.walk_file(path)?;
+    let start = previous.as_ref().map_or(0, |c| c.entries);
+    if entries <= start || entries > kept.next_seq {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("can compact through {} to {} entries, not {entries}", start + 1, kept.next_seq),
+        ).into());
+    }
+
+    // The archive must already hold the history before the log's first entry.
+    let mut summary = Summary::new();
+    let archive_format = match LogFormat::detect(archive)? {
+        Some(format) => {
+            summary.read_file(archive, previous.as_ref())?;
+            format
+        }
+        None => LogFormat::detect(path)?.unwrap_or_default(),
+    };
+    let archived = summary.walk.next_seq;
+    if archived < start {
+        return Err(IntegrityError::CheckpointMismatch { field: "entries" });
+    }
+    if archived > entries {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("the archive already holds {archived} entries"),
+        ).into());
+    }
+
+    // Move entries archived..entries to the archive, checking any overlap.
+    let mut reader = RecordReader::open(path)?;
+    let mut out = Vec::new();
+    if archived == 0 {
+        summary.walk.enter_file(reader.header())?;
+        out.extend_from_slice(&format::prelude(archive_format, reader.header())?);
+    }
+    let mut cut = None;
+    while let Some(record) = reader.next_record()? {
+        let Record::Entry(entry) = record else { continue };
+        if entry.seq >= entries {
+            cut = Some(reader.offset);
+            break;
+        }
+        if entry.seq < archived {
+            if summary.leaves.get(entry.seq as usize) != Some(&leaf_hash(&entry.hash())) {
+                return Err(IntegrityError::CheckpointMismatch { field: "archive" });
+            }
+            continue;
+        }
+        summary.add(&entry)?;
+        out.extend_from_slice(&format::encode_record(&entry, archive_format)?);
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(archive)?;
+    file.write_all(&out)?;
+    file.sync_all()?;
+    segment::sync_parent(archive)?;
+
+    let checkpoint = summary.sign(key);
+    checkpoint.store(path)?;
+
+    // Only now replace the log, keeping the remainder's bytes as they are
+    // behind a header naming the algorithm of its first entry.
+    let format = reader.format();
+    let next_alg = summary.walk.hash_alg.unwrap_or_default();
+    let header = match reader.header() {
+        Some(header) => header.with_algorithm(next_alg),
+        None => LogHeader::new(next_alg, None, Utc::now(), BTreeMap::new())?,
+    };
+    let mut rest = Vec::new();
+    if let Some(cut) = cut {
+        let mut src = File::open(path)?;
+        src.seek(SeekFrom::Start(cut))?;
+        src.read_to_end(&mut rest)?;
+    }
+    let temp = path.with_file_name(format!("{}.compact.tmp", segment::file_name(path)));
+    let mut out = File::create(&temp)?;
+    out.write_all(&format::prelude(format, Some(&header))?)?;
+    out.write_all(&rest)?;
+    out.sync_all()?;
+    fs::rename(&temp, path)?;
+    match fs::remove_file(Index::path_for(path)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    segment::sync_parent(path)?;
+    Ok(checkpoint)
+}
+
+/// Running summary of a chain read from genesis.
+struct Summary {
+    walk: ChainWalk<'static>,
+    /// Merkle leaf hash of every entry so far.
+    leaves: Vec<Vec<u8>>,
+    op_counts: BTreeMap<String, u64>,
+    last_timestamp: Option<DateTime<Utc>>,
+}
+
+impl Summary {
+    fn new() -> Self {
+        Self { walk: ChainWalk::unanchored(), leaves: Vec::new(), op_counts: BTreeMap::new(), last_timestamp: None }
+    }
+
+    fn add(&mut self, entry: &LogEntry) -> Result<(), IntegrityError> {
+        self.walk.check(entry)?;
+        self.leaves.push(leaf_hash(self.walk.head_hash()));
+        *self.op_counts.entry(variant_name(&entry.operation)).or_default() += 1;
+        self.last_timestamp = Some(entry.timestamp);
+        Ok(())
+    }
+
+    /// Add every entry of the file at `path`, checking the summary against
+    /// `checkpoint` once it covers as many entries. A torn tail is an error.
+    fn read_file(&mut self, path: &Path, checkpoint: Option<&Checkpoint>) -> Result<(), IntegrityError> {
+        let mut reader = RecordReader::open(path)?;
+        self.walk.enter_file(reader.header())?;
+        if checkpoint.is_some_and(|c| c.entries == 0) {
+            return Err(IntegrityError::CheckpointMismatch { field: "entries" });
+        }
+        while let Some(record) = reader.next_record()? {
+            let entry = match record {
+                Record::Entry(entry) => *entry,
+                Record::Blank => continue,
+                Record::Torn => return Err(IntegrityError::TornTail { offset: reader.offset, bytes: reader.len }),
+            };
+            self.add(&entry)?;
+            if let Some(checkpoint) = checkpoint.filter(|c| c.entries == self.walk.next_seq) {
+                self.check(checkpoint)?;
+            }
+        }
+        if checkpoint.is_some_and(|c| c.entries > self.walk.next_seq) {
+            return Err(IntegrityError::CheckpointMismatch { field: "entries" });
+        }
+        Ok(())
+    }
+
+    /// Compare the summary so far with `checkpoint`.
+    fn check(&self, checkpoint: &Checkpoint) -> Result<(), IntegrityError> {
+        let field = if self.walk.head_hash() != checkpoint.head_hash {
+            "head_hash"
+        } else if self.last_timestamp != checkpoint.last_timestamp {
+            "last_timestamp"
+        } else if self.op_counts != checkpoint.op_counts {
+            "op_counts"
+        } else if hex::encode(&merkle_root(&self.leaves)) != checkpoint.merkle_root {
+            "merkle_root"
+        } else {
+            return Ok(());
+        };
+        Err(IntegrityError::CheckpointMismatch { field })
+    }
+
+    fn sign(&self, key: &CheckpointKey) -> Checkpoint {
+        let mut checkpoint = Checkpoint {
+            version: CHECKPOINT_VERSION,
+            entries: self.walk.next_seq,
+            head_hash: self.walk.head_hash().to_owned(),
+            last_timestamp: self.last_timestamp,
+            op_counts: self.op_counts.clone(),
+            merkle_root: hex::encode(&merkle_root(&self.leaves)),
+            compacted_at: Utc::now(),
+            key_id: key.id.clone(),
+            public_key: key.public_key(),
+            signature: String::new(),
+        };
+        checkpoint.signature = hex::encode(key.pair.sign(&checkpoint.signing_input()).as_ref());
+        checkpoint
+    }
+}
+
+/// RFC 6962 leaf hash of the entry hash `hash` (hex).
+fn leaf_hash(hash: &str) -> Vec<u8> {
+    let mut context = digest::Context::new(&SHA256);
+    context.update(&[0x00]);
+    context.update(&hex::decode(hash).unwrap_or_else(|| hash.as_bytes().to_vec()));
+    context.finish().as_ref().to_vec()
+}
+
+/// RFC 6962 Merkle tree hash over already-hashed leaves.
+fn merkle_root(leaves: &[Vec<u8>]) -> Vec<u8> {
+    match leaves {
+        [] => digest::digest(&SHA256, &[]).as_ref().to_vec(),
+        [leaf] => leaf.clone(),
+        _ => {
+            let split = leaves.len().next_power_of_two() / 2;
+            let mut context = digest::Context::new(&SHA256);
+            context.update(&[0x01]);
+            context.update(&merkle_root(&leaves[..split]));
+            context.update(&merkle_root(&leaves[split..]));
+            context.finish().as_ref().to_vec()
+        }
+    }
+}
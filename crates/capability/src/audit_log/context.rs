@@ -0,0 +1,126 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Audit Context — Who, Where and Which Run.
+//!
+//! An `AuditContext` is stamped onto every entry appended while it is set,
+//! answering questions the operation alone cannot: which user and process
+//! on which host produced the entry, in which session, as part of which
+//! top-level run. The correlation id ties together every entry of one run
+//! (for example one "fix repository X" invocation) so the run can be
+//! reconstructed end to end with [`group_by_correlation`](super::group_by_correlation).
+//!
+//! The context is part of the hashed entry body, so it is as tamper-evident
+//! as the operation itself. Entries written without a context hash exactly
+//! as they did before contexts existed.
+
+use std::ops::{Deref, DerefMut};
+use serde::{Deserialize, Serialize};
+
+use super::AuditLog;
+
+/// Provenance stamped onto audit log entries. Every field is optional and
+/// omitted from the entry when unset.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditContext {
+    /// User or service account on whose behalf the action ran.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actor: Option<String>,
+    /// Host the action ran on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// Process id of the writer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    /// Caller-defined session (for example a BEAM node or editor session).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// Identifier shared by every entry of one top-level run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    /// Version of the tool that wrote the entry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_version: Option<String>,
+}
+
+impl AuditContext {
+    /// Context describing the current process: actor from `$USER`
+    /// (or `%USERNAME%`), hostname, pid and this crate's version.
+    pub fn current() -> Self {
+        Self {
+            actor: std::env::var("USER").or_else(|_| std::env::var("USERNAME")).ok(),
+            hostname: hostname(),
+            pid: Some(std::process::id()),
+            session_id: None,
+            correlation_id: None,
+            tool_version: Some(env!("CARGO_PKG_VERSION").to_owned()),
+        }
+    }
+
+    /// Set the actor.
+    pub fn with_actor(mut self, actor: impl Into<String>) -> Self {
+        self.actor = Some(actor.into());
+        self
+    }
+
+    /// Set the session id.
+    pub fn with_session_id(mut self, session_id: impl Into<String>) -> Self {
+        self.session_id = Some(session_id.into());
+        self
+    }
+
+    /// Set the correlation id.
+    pub fn with_correlation_id(mut self, correlation_id: impl Into<String>) -> Self {
+        self.correlation_id = Some(correlation_id.into());
+        self
+    }
+
+    /// Whether no field is set.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Best-effort hostname lookup without a platform dependency.
+fn hostname() -> Option<String> {
+    std::env::var("HOSTNAME").ok()
+        .or_else(|| std::fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+}
+
+/// An [`AuditLog`] borrowed with a temporary context; see
+/// [`AuditLog::scoped`]. The previous context is restored on drop.
+pub struct ScopedContext<'a> {
+    log: &'a mut AuditLog,
+    previous: Option<AuditContext>,
+}
+
+impl<'a> ScopedContext<'a> {
+    pub(crate) fn new(log: &'a mut AuditLog, context: AuditContext) -> Self {
+        let previous = log.context.replace(context);
+        Self { log, previous }
+    }
+}
+
+impl Deref for ScopedContext<'_> {
+    type Target = AuditLog;
+
+    fn deref(&self) -> &AuditLog {
+        self.log
+    }
+}
+
+impl DerefMut for ScopedContext<'_> {
+    fn deref_mut(&mut self) -> &mut AuditLog {
+        self.log
+    }
+}
+
+impl Drop for ScopedContext<'_> {
+    fn drop(&mut self) {
+        self.log.context = self.previous.take();
+    }
+}
@@ -0,0 +1,252 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Durability Modes — When Appended Entries Reach Stable Storage.
+//!
+//! `fsync` dominates the cost of an append. This module lets an `AuditLog`
+//! trade latency for throughput:
+//!
+//! - **EveryEntry**: `fsync` inside every `append` (the default).
+//! - **GroupCommit**: a background writer thread `fsync`s once `max_entries`
+//!   entries are pending or the oldest pending entry is `max_delay` old.
+//! - **OnFlush**: nothing is `fsync`-ed until `AuditLog::flush` is called.
+//!
+//! Every append returns an [`AppendTicket`] that reports whether — or
+//! blocks until — that particular entry is durable. A failed `fsync` is
+//! sticky: it is reported to every waiter and to every later append,
+//! because the kernel gives no guarantee about which writes were lost.
+
+use std::fs::File;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// When appended entries are forced to stable storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /// `fsync` after every entry before `append` returns.
+    #[default]
+    EveryEntry,
+    /// `fsync` in the background once `max_entries` entries are pending or
+    /// the oldest pending entry has waited `max_delay`, whichever is first.
+    GroupCommit { max_entries: usize, max_delay: Duration },
+    /// Only `fsync` when [`AuditLog::flush`](super::AuditLog::flush) is called
+    /// (and when the log is dropped).
+    OnFlush,
+}
+
+/// Sync progress shared between the log, its writer thread and tickets.
+struct SyncState {
+    /// Number of entries (by sequence number) written to the file.
+    written: u64,
+    /// Number of entries known to be on stable storage.
+    durable: u64,
+    /// When the oldest not-yet-durable entry was written.
+    oldest_pending: Option<Instant>,
+    /// Sticky `fsync` failure.
+    failed: Option<(io::ErrorKind, String)>,
+    /// Set when the owning log is dropped.
+    shutdown: bool,
+}
+
+impl SyncState {
+    fn error(&self) -> Option<io::Error> {
+        self.failed.as_ref().map(|(kind, msg)| io::Error::new(*kind, format!("audit log fsync failed: {msg}")))
+    }
+}
+
+struct Shared {
+    /// The log file followed by any companion files (its index), synced in
+    /// that order.
+    files: Vec<Arc<File>>,
+    state: Mutex<SyncState>,
+    /// Wakes the writer thread when entries are written or on shutdown.
+    work: Condvar,
+    /// Wakes ticket holders when `durable` advances or an error occurs.
+    synced: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, SyncState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// `fsync` the files and advance `durable` to `target` on success.
+    fn sync_to(&self, target: u64) -> io::Result<()> {
+        let result = self.files.iter().try_for_each(|f| f.sync_all());
+        let mut state = self.lock();
+        match &result {
+            Ok(()) => {
+                state.durable = state.durable.max(target);
+                state.oldest_pending = (state.written > state.durable).then(Instant::now);
+            }
+            Err(e) => state.failed = Some((e.kind(), e.to_string())),
+        }
+        self.synced.notify_all();
+        result
+    }
+}
+
+/// Receipt for an appended entry.
+///
+/// The entry is in the file as soon as the ticket exists; the ticket tells
+/// the caller when it is also on stable storage.
+#[derive(Clone)]
+pub struct AppendTicket {
+    seq: u64,
+    hash: String,
+    shared: Arc<Shared>,
+}
+
+impl std::fmt::Debug for AppendTicket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AppendTicket")
+            .field("seq", &self.seq)
+            .field("hash", &self.hash)
+            .field("durable", &self.is_durable())
+            .finish()
+    }
+}
+
+impl AppendTicket {
+    /// Sequence number of the appended entry.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Chain hash of the appended entry.
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    /// Whether the entry has been `fsync`-ed.
+    pub fn is_durable(&self) -> bool {
+        self.shared.lock().durable > self.seq
+    }
+
+    /// Block until the entry is durable.
+    ///
+    /// Returns the sticky `fsync` error if syncing failed. In
+    /// [`Durability::OnFlush`] mode this blocks until someone calls `flush`.
+    pub fn wait_durable(&self) -> io::Result<()> {
+        let mut state = self.shared.lock();
+        loop {
+            if state.durable > self.seq { return Ok(()); }
+            if let Some(e) = state.error() { return Err(e); }
+            state = self.shared.synced.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+/// Tracks written/durable positions and owns the group-commit writer thread.
+pub(crate) struct Syncer {
+    mode: Durability,
+    shared: Arc<Shared>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl Syncer {
+    /// Start tracking `files`, whose first `durable` entries are already synced.
+    pub(crate) fn new(files: Vec<Arc<File>>, mode: Durability, durable: u64) -> Self {
+        let shared = Arc::new(Shared {
+            files,
+            state: Mutex::new(SyncState {
+                written: durable,
+                durable,
+                oldest_pending: None,
+                failed: None,
+                shutdown: false,
+            }),
+            work: Condvar::new(),
+            synced: Condvar::new(),
+        });
+
+        let writer = match mode {
+            Durability::GroupCommit { max_entries, max_delay } => {
+                let shared = Arc::clone(&shared);
+                Some(thread::Builder::new()
+                    .name("audit-log-sync".into())
+                    .spawn(move || group_commit_loop(&shared, max_entries.max(1), max_delay))
+                    .expect("spawn audit log writer thread"))
+            }
+            Durability::EveryEntry | Durability::OnFlush => None,
+        };
+
+        Self { mode, shared, writer }
+    }
+
+    /// Fail fast if an earlier `fsync` failed.
+    pub(crate) fn check(&self) -> io::Result<()> {
+        self.shared.lock().error().map_or(Ok(()), Err)
+    }
+
+    /// Record that the entry `seq` has been written and apply the policy.
+    pub(crate) fn written(&self, seq: u64, hash: String) -> io::Result<AppendTicket> {
+        {
+            let mut state = self.shared.lock();
+            state.written = seq + 1;
+            if state.oldest_pending.is_none() {
+                state.oldest_pending = Some(Instant::now());
+            }
+        }
+
+        match self.mode {
+            Durability::EveryEntry => self.shared.sync_to(seq + 1)?,
+            Durability::GroupCommit { .. } => self.shared.work.notify_one(),
+            Durability::OnFlush => {}
+        }
+
+        Ok(AppendTicket { seq, hash, shared: Arc::clone(&self.shared) })
+    }
+
+    /// `fsync` everything written so far.
+    pub(crate) fn flush(&self) -> io::Result<()> {
+        let target = {
+            let state = self.shared.lock();
+            if let Some(e) = state.error() { return Err(e); }
+            if state.durable >= state.written { return Ok(()); }
+            state.written
+        };
+        self.shared.sync_to(target)
+    }
+}
+
+impl Drop for Syncer {
+    /// Stop the writer thread after a final flush.
+    fn drop(&mut self) {
+        self.shared.lock().shutdown = true;
+        self.shared.work.notify_one();
+        match self.writer.take() {
+            Some(writer) => { let _ = writer.join(); }
+            None => { let _ = self.flush(); }
+        }
+    }
+}
+
+/// Body of the group-commit writer thread.
+fn group_commit_loop(shared: &Shared, max_entries: usize, max_delay: Duration) {
+    let mut state = shared.lock();
+    loop {
+        if state.failed.is_some() { return; }
+        let pending = state.written - state.durable;
+        if pending == 0 {
+            if state.shutdown { return; }
+            state = shared.work.wait(state).unwrap_or_else(|e| e.into_inner());
+            continue;
+        }
+
+        let deadline = state.oldest_pending.unwrap_or_else(Instant::now) + max_delay;
+        let now = Instant::now();
+        if !state.shutdown && pending < max_entries as u64 && now < deadline {
+            state = shared.work.wait_timeout(state, deadline - now)
+                .unwrap_or_else(|e| e.into_inner()).0;
+            continue;
+        }
+
+        let target = state.written;
+        drop(state);
+        let _ = shared.sync_to(target);
+        state = shared.lock();
+    }
+}
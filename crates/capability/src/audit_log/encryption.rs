@@ -0,0 +1,310 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Encryption at Rest — Sealed Entries, Public Chain.
+//!
+//! With a [`Keyring`] in `AuditLogOptions::encryption`, every appended entry
+//! is sealed before it is written: its operation, context, outcome, change
+//! and transaction id are serialised to JSON and encrypted with AES-256-GCM
+//! or ChaCha20-Poly1305, and the entry stores an `Operation::Encrypted`
+//! holding the cipher, key id, nonce and ciphertext instead.
+//!
+//! WHAT STAYS PUBLIC:
+//! The version, sequence number, timestamp, `prev_hash`, any
+//! `clamped_from` and any `hash_alg` are left in the clear, and the entry
+//! hash is computed over the sealed entry — that is, over the ciphertext.
+//! Anyone can therefore verify the chain, run a full-scan report or check
+//! segment boundaries without the key; only reading what happened requires
+//! it. Schema checks of custom details cannot see inside sealed entries.
+//!
+//! NONCES AND BINDING:
+//! Each entry gets a fresh random 96-bit nonce. The public header fields and
+//! the key id are authenticated as associated data, so ciphertext cannot be
+//! moved to another position in the log without failing to decrypt. Random
+//! nonces stay safe well past 2^32 entries per key; rotate keys long before.
+//!
+//! KEY ROTATION:
+//! Entries name the key that sealed them. [`Keyring::rotate`] (or
+//! [`AuditLog::rotate_key`](super::AuditLog::rotate_key)) makes a new key
+//! the one used for writing while keeping earlier keys for reading, so a
+//! log spans any number of keys. Retired keys can be removed from a keyring
+//! once the entries they sealed are no longer needed in plaintext; the
+//! chain stays verifiable regardless. A key file is `<key-id>.key` holding
+//! 64 hex digits, loaded with [`Keyring::load_dir`].
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, CHACHA20_POLY1305, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+
+use super::{canonical, hex, AuditContext, Change, LogEntry, Operation, Outcome};
+
+/// Authenticated cipher used to seal an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Cipher {
+    /// AES-256 in Galois/Counter Mode.
+    #[default]
+    Aes256Gcm,
+    /// ChaCha20 with a Poly1305 authenticator.
+    ChaCha20Poly1305,
+}
+
+impl Cipher {
+    fn algorithm(self) -> &'static ring::aead::Algorithm {
+        match self {
+            Cipher::Aes256Gcm => &AES_256_GCM,
+            Cipher::ChaCha20Poly1305 => &CHACHA20_POLY1305,
+        }
+    }
+}
+
+/// Errors raised when sealing or opening entries, or loading keys.
+#[derive(Debug, thiserror::Error)]
+pub enum EncryptionError {
+    /// The keyring has no active key to seal new entries with.
+    #[error("the keyring has no active key")]
+    NoActiveKey,
+
+    /// No key with this id is in the keyring.
+    #[error("no key {0:?} in the keyring")]
+    UnknownKey(String),
+
+    /// Key material is not 32 bytes of lowercase hex.
+    #[error("key {id:?} is invalid: {reason}")]
+    InvalidKey { id: String, reason: String },
+
+    /// The ciphertext failed authentication: wrong key, or the entry was
+    /// altered or moved.
+    #[error("entry {seq} could not be decrypted with key {key_id:?}")]
+    Decrypt { seq: u64, key_id: String },
+
+    /// A sealed entry is not well formed.
+    #[error("sealed entry {seq} is malformed: {reason}")]
+    Malformed { seq: u64, reason: String },
+
+    /// A key file could not be read.
+    #[error("key file {file}: {cause}")]
+    File { file: String, cause: String },
+
+    /// The system random number generator failed.
+    #[error("no randomness available for a nonce")]
+    Random,
+}
+
+/// A named 256-bit key. The key material is never printed.
+#[derive(Clone)]
+pub struct EncryptionKey {
+    id: String,
+    material: [u8; 32],
+}
+
+impl EncryptionKey {
+    /// A key called `id` with the given material.
+    pub fn new(id: impl Into<String>, material: [u8; 32]) -> Self {
+        Self { id: id.into(), material }
+    }
+
+    /// A fresh random key called `id`.
+    pub fn generate(id: impl Into<String>) -> Result<Self, EncryptionError> {
+        let mut material = [0u8; 32];
+        SystemRandom::new().fill(&mut material).map_err(|_| EncryptionError::Random)?;
+        Ok(Self::new(id, material))
+    }
+
+    /// A key called `id` from 64 lowercase hex digits.
+    pub fn from_hex(id: impl Into<String>, text: &str) -> Result<Self, EncryptionError> {
+        let id = id.into();
+        let material = hex::decode(text.trim())
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .ok_or_else(|| EncryptionError::InvalidKey { id: id.clone(), reason: "expected 64 hex digits".into() })?;
+        Ok(Self::new(id, material))
+    }
+
+    /// The key material as 64 lowercase hex digits, for storing in a key file.
+    pub fn to_hex(&self) -> String {
+        hex::encode(&self.material)
+    }
+
+    /// The key's id.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn bind(&self, cipher: Cipher) -> LessSafeKey {
+        let unbound = UnboundKey::new(cipher.algorithm(), &self.material).expect("32-byte key fits both ciphers");
+        LessSafeKey::new(unbound)
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptionKey").field("id", &self.id).finish_non_exhaustive()
+    }
+}
+
+/// Keys for sealing and opening entries: one active key for writing, any
+/// number of older keys for reading.
+#[derive(Debug, Clone)]
+pub struct Keyring {
+    keys: BTreeMap<String, EncryptionKey>,
+    active: Option<String>,
+    cipher: Cipher,
+}
+
+impl Keyring {
+    /// A keyring that seals new entries with `key` using `cipher`.
+    pub fn new(cipher: Cipher, key: EncryptionKey) -> Self {
+        let mut keyring = Self::read_only();
+        keyring.cipher = cipher;
+        keyring.rotate(key);
+        keyring
+    }
+
+    /// A keyring with no active key, for reading only.
+    pub fn read_only() -> Self {
+        Self { keys: BTreeMap::new(), active: None, cipher: Cipher::default() }
+    }
+
+    /// Add `key` for reading without changing the active key.
+    pub fn add(&mut self, key: EncryptionKey) {
+        self.keys.insert(key.id.clone(), key);
+    }
+
+    /// Add `key` and seal every entry from now on with it. Earlier keys
+    /// stay available for reading.
+    pub fn rotate(&mut self, key: EncryptionKey) {
+        self.active = Some(key.id.clone());
+        self.add(key);
+    }
+
+    /// Add every `<key-id>.key` file in `dir` for reading. Returns the
+    /// number of keys loaded.
+    pub fn load_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result<usize, EncryptionError> {
+        let file_error = |file: &Path, cause: String| EncryptionError::File { file: file.display().to_string(), cause };
+        let dir = dir.as_ref();
+        let mut count = 0;
+        for entry in fs::read_dir(dir).map_err(|e| file_error(dir, e.to_string()))? {
+            let path = entry.map_err(|e| file_error(dir, e.to_string()))?.path();
+            let Some(id) = path.file_name().and_then(|n| n.to_str()).and_then(|n| n.strip_suffix(".key")) else {
+                continue;
+            };
+            let text = fs::read_to_string(&path).map_err(|e| file_error(&path, e.to_string()))?;
+            self.add(EncryptionKey::from_hex(id, &text)?);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Id of the key new entries are sealed with.
+    pub fn active_key(&self) -> Option<&str> {
+        self.active.as_deref()
+    }
+
+    /// Seal `entry` with the active key. Its hash must be computed
+    /// afterwards, over the sealed form.
+    pub(crate) fn seal(&self, entry: LogEntry) -> Result<LogEntry, EncryptionError> {
+        let key_id = self.active.clone().ok_or(EncryptionError::NoActiveKey)?;
+        let key = self.keys.get(&key_id).ok_or_else(|| EncryptionError::UnknownKey(key_id.clone()))?;
+
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new().fill(&mut nonce).map_err(|_| EncryptionError::Random)?;
+        let sealed = Sealed {
+            operation: entry.operation.clone(),
+            context: entry.context.clone(),
+            outcome: entry.outcome.clone(),
+            change: entry.change.clone(),
+            tx_id: entry.tx_id.clone(),
+        };
+        let mut in_out = serde_json::to_vec(&sealed).expect("sealed fields must serialise");
+        let aad = associated_data(&entry, &key_id);
+        key.bind(self.cipher)
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(&aad), &mut in_out)
+            .map_err(|_| EncryptionError::Malformed { seq: entry.seq, reason: "entry too large to seal".into() })?;
+
+        Ok(LogEntry {
+            operation: Operation::Encrypted {
+                cipher: self.cipher,
+                key_id,
+                nonce: hex::encode(&nonce),
+                ciphertext: hex::encode(&in_out),
+            },
+            context: None,
+            outcome: None,
+            change: None,
+            tx_id: None,
+            ..entry
+        })
+    }
+
+    /// The plaintext form of `entry`. Entries that are not sealed are
+    /// returned unchanged.
+    pub fn open(&self, entry: &LogEntry) -> Result<LogEntry, EncryptionError> {
+        let Operation::Encrypted { cipher, key_id, nonce, ciphertext } = &entry.operation else {
+            return Ok(entry.clone());
+        };
+        let malformed = |reason: &str| EncryptionError::Malformed { seq: entry.seq, reason: reason.into() };
+        let key = self.keys.get(key_id).ok_or_else(|| EncryptionError::UnknownKey(key_id.clone()))?;
+        let nonce = hex::decode(nonce)
+            .and_then(|n| <[u8; NONCE_LEN]>::try_from(n).ok())
+            .ok_or_else(|| malformed("nonce is not 12 hex-encoded bytes"))?;
+        let mut in_out = hex::decode(ciphertext).ok_or_else(|| malformed("ciphertext is not hex"))?;
+
+        let aad = associated_data(entry, key_id);
+        let plaintext = key.bind(*cipher)
+            .open_in_place(Nonce::assume_unique_for_key(nonce), Aad::from(&aad), &mut in_out)
+            .map_err(|_| EncryptionError::Decrypt { seq: entry.seq, key_id: key_id.clone() })?;
+        let sealed: Sealed = serde_json::from_slice(plaintext).map_err(|e| malformed(&e.to_string()))?;
+
+        Ok(LogEntry {
+            operation: sealed.operation,
+            context: sealed.context,
+            outcome: sealed.outcome,
+            change: sealed.change,
+            tx_id: sealed.tx_id,
+            ..entry.clone()
+        })
+    }
+}
+
+/// The fields of an entry that encryption hides.
+#[derive(Serialize, Deserialize)]
+struct Sealed {
+    operation: Operation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    context: Option<AuditContext>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    outcome: Option<Outcome>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    change: Option<Change>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tx_id: Option<String>,
+}
+
+/// Public fields authenticated alongside the ciphertext.
+fn associated_data(entry: &LogEntry, key_id: &str) -> Vec<u8> {
+    let mut enc = canonical::Encoder::default();
+    enc.u8(entry.version)
+        .u64(entry.seq)
+        .i64(entry.timestamp.timestamp())
+        .u32(entry.timestamp.timestamp_subsec_nanos())
+        .str(&entry.prev_hash);
+    // A clamped clock reading is bound as `[secs, nanos]`, as in the hash
+    // input, so it does not depend on how a timestamp is formatted. Its
+    // leading array tag keeps it apart from the empty string bound in its
+    // place when there is none.
+    match entry.clamped_from {
+        Some(t) => enc.value(&serde_json::json!([t.timestamp(), t.timestamp_subsec_nanos()])),
+        None => enc.str(""),
+    };
+    enc.str(key_id);
+    // Only entries hashed with another algorithm than SHA-256 bind it, so
+    // entries sealed before hash agility still open.
+    if let Some(alg) = entry.hash_alg {
+        enc.str(alg.name());
+    }
+    enc.finish()
+}
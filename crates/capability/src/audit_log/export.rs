@@ -0,0 +1,333 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Exporters — Audit Entries for Other Tools.
+//!
+//! An [`Exporter`] writes entries as RFC 5424 syslog messages, RFC 4180
+//! CSV or versioned JSON Lines, for SIEMs and spreadsheets that do not read
+//! the log's own NDJSON. It takes any iterator of entries, so a whole log
+//! (`AuditLog::entries`) or a filtered query can be exported the same way.
+//!
+//! FLAT RECORDS:
+//! All three formats carry the same flat record, whose fields are the
+//! [`EXPORT_COLUMNS`] in order. `op` is the operation's variant name; its
+//! fields map onto shared columns:
+//!
+//! | Operation                                | `path`       | `to_path`   | `message` | `count`       |
+//! |------------------------------------------|--------------|-------------|-----------|---------------|
+//! | `FileRead`, `FileWrite`, `FileDelete`    | path         |             |           |               |
+//! | `DirCreate`                              | path         |             |           |               |
+//! | `FileMove`                               | from         | to          |           |               |
+//! | `CapabilityCreated`                      | root         |             |           |               |
+//! | `CapabilityResolved`                     | relative     | canonical   |           |               |
+//! | `GitStatusChecked` / `GitCommitCreated`  | repo_path    |             | message   |               |
+//! | `RecoveredFromCrash`                     | quarantine   |             |           | bytes         |
+//! | `LegacyMigrated`                         | source       |             |           | entries       |
+//! | `TransactionBegin`                       |              |             |           | planned ops   |
+//! | `TransactionCommitted` / `RolledBack`    |              |             | error     | applied       |
+//!
+//! Anything else goes in `details` as JSON: custom details, planned
+//! operations, crash offsets, legacy heads, and the cipher and key id of a
+//! sealed entry that was exported without its key.
+//!
+//! VERIFIABILITY:
+//! Every record carries the entry's `hash` and `prev_hash` as stored in the
+//! log — for a sealed entry exported with a keyring, the hash of the sealed
+//! form — so an export can be matched against the source chain entry by
+//! entry.
+//!
+//! SCHEMA STABILITY:
+//! JSON Lines records start with `"schema": "polysafe-audit-export/1"`
+//! ([`EXPORT_SCHEMA`]) and omit empty fields. Columns are only ever added,
+//! at the end, under the same version; a rename or removal bumps it.
+
+use std::io::Write;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use super::{AuditContext, Change, IntegrityError, Keyring, LogEntry, Operation, Outcome};
+
+/// Version tag of the JSON Lines export schema.
+pub const EXPORT_SCHEMA: &str = "polysafe-audit-export/1";
+
+/// Fields of an exported record, in CSV column order.
+pub const EXPORT_COLUMNS: [&str; 23] = [
+    "seq", "timestamp", "hash", "prev_hash", "op", "path", "to_path", "message", "kind", "details", "tx_id",
+    "count", "outcome", "error", "before", "after", "actor", "hostname", "pid", "session_id",
+    "correlation_id", "tool_version", "clamped_from",
+];
+
+/// Syslog facility 13, "log audit".
+const SYSLOG_FACILITY: u8 = 13;
+
+/// Structured-data id for syslog exports. 32473 is the private enterprise
+/// number reserved for documentation (RFC 5612).
+const SYSLOG_SD_ID: &str = "polysafe@32473";
+
+/// APP-NAME field of syslog exports.
+const SYSLOG_APP_NAME: &str = "polysafe-gitfixer";
+
+/// Output format of an [`Exporter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// One RFC 5424 message per line, fields as structured data.
+    Syslog,
+    /// RFC 4180 CSV with a header row of [`EXPORT_COLUMNS`].
+    Csv,
+    /// One JSON object per line, tagged with [`EXPORT_SCHEMA`].
+    JsonLines,
+}
+
+/// Writes entries in an [`ExportFormat`].
+#[derive(Debug, Clone, Copy)]
+pub struct Exporter<'k> {
+    format: ExportFormat,
+    keyring: Option<&'k Keyring>,
+}
+
+impl<'k> Exporter<'k> {
+    /// An exporter writing `format`. Sealed entries are exported as sealed.
+    pub fn new(format: ExportFormat) -> Self {
+        Self { format, keyring: None }
+    }
+
+    /// Open sealed entries with `keyring` before exporting them.
+    pub fn with_keyring(mut self, keyring: &'k Keyring) -> Self {
+        self.keyring = Some(keyring);
+        self
+    }
+
+    /// Write `entries`, as stored in the log, to `out`. Returns the number
+    /// of entries written; stops at the first entry that cannot be read or
+    /// opened.
+    pub fn write<W, I>(&self, entries: I, mut out: W) -> Result<usize, IntegrityError>
+    where
+        W: Write,
+        I: IntoIterator<Item = Result<LogEntry, IntegrityError>>,
+    {
+        if self.format == ExportFormat::Csv {
+            write_csv_row(&mut out, EXPORT_COLUMNS.iter().map(|c| c.to_string()))?;
+        }
+        let mut count = 0;
+        for stored in entries {
+            let stored = stored?;
+            let plain = match self.keyring {
+                Some(keyring) => keyring.open(&stored)?,
+                None => stored.clone(),
+            };
+            let record = FlatRecord::new(&stored, &plain);
+            match self.format {
+                ExportFormat::Syslog => writeln!(out, "{}", record.syslog())?,
+                ExportFormat::Csv => write_csv_row(&mut out, record.columns().map(|(_, v)| text(v)))?,
+                ExportFormat::JsonLines => {
+                    serde_json::to_writer(&mut out, &record).map_err(std::io::Error::other)?;
+                    out.write_all(b"\n")?;
+                }
+            }
+            count += 1;
+        }
+        out.flush()?;
+        Ok(count)
+    }
+}
+
+/// One entry flattened to [`EXPORT_COLUMNS`].
+#[derive(Debug, Default, Serialize)]
+struct FlatRecord {
+    #[serde(skip)]
+    at: DateTime<Utc>,
+    schema: &'static str,
+    seq: u64,
+    timestamp: String,
+    hash: String,
+    prev_hash: String,
+    op: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tx_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    count: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    outcome: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    before: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    after: Option<String>,
+    #[serde(flatten)]
+    context: AuditContext,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clamped_from: Option<String>,
+}
+
+impl FlatRecord {
+    /// Flatten `plain`, taking the hashes from `stored` (its form in the log).
+    fn new(stored: &LogEntry, plain: &LogEntry) -> Self {
+        let mut record = FlatRecord {
+            at: stored.timestamp,
+            schema: EXPORT_SCHEMA,
+            seq: stored.seq,
+            timestamp: stored.timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            hash: stored.hash(),
+            prev_hash: stored.prev_hash.clone(),
+            op: variant_name(&plain.operation),
+            tx_id: plain.tx_id.clone(),
+            context: plain.context.clone().unwrap_or_default(),
+            clamped_from: plain.clamped_from.map(|t| t.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+            ..Default::default()
+        };
+        record.flatten_operation(&plain.operation);
+        match &plain.outcome {
+            Some(Outcome::Success) => record.outcome = Some("success"),
+            Some(Outcome::Failure { error }) => {
+                record.outcome = Some("failure");
+                record.error = Some(error.clone());
+            }
+            Some(Outcome::RolledBack) => record.outcome = Some("rolled_back"),
+            None => {}
+        }
+        match &plain.change {
+            Some(Change::Content { before, after }) => (record.before, record.after) = (before.clone(), after.clone()),
+            Some(Change::Git { old_oid, new_oid }) => (record.before, record.after) = (old_oid.clone(), new_oid.clone()),
+            None => {}
+        }
+        record
+    }
+
+    fn flatten_operation(&mut self, operation: &Operation) {
+        let path = |p: &std::path::Path| Some(p.display().to_string());
+        match operation {
+            Operation::FileRead { path: p }
+            | Operation::FileWrite { path: p }
+            | Operation::FileDelete { path: p }
+            | Operation::DirCreate { path: p }
+            | Operation::CapabilityCreated { root: p }
+            | Operation::GitStatusChecked { repo_path: p } => self.path = path(p),
+            Operation::FileMove { from, to } => (self.path, self.to_path) = (path(from), path(to)),
+            Operation::CapabilityResolved { relative, canonical } => {
+                (self.path, self.to_path) = (path(relative), path(canonical));
+            }
+            Operation::GitCommitCreated { repo_path, message } => {
+                (self.path, self.message) = (path(repo_path), Some(message.clone()));
+            }
+            Operation::RecoveredFromCrash { quarantine, offset, bytes } => {
+                (self.path, self.count) = (path(quarantine), Some(*bytes));
+                self.details = Some(json!({ "offset": offset }));
+            }
+            Operation::LegacyMigrated { source, legacy_head, entries } => {
+                (self.path, self.count) = (path(source), Some(*entries));
+                self.details = Some(json!({ "legacy_head": legacy_head }));
+            }
+            Operation::Custom { kind, details } => (self.kind, self.details) = (Some(kind.clone()), Some(details.clone())),
+            Operation::TransactionBegin { tx_id, planned_ops } => {
+                (self.tx_id, self.count) = (Some(tx_id.clone()), Some(planned_ops.len() as u64));
+                self.details = serde_json::to_value(planned_ops).ok();
+            }
+            Operation::TransactionCommitted { tx_id, applied } => {
+                (self.tx_id, self.count) = (Some(tx_id.clone()), Some(*applied));
+            }
+            Operation::TransactionRolledBack { tx_id, applied, error } => {
+                (self.tx_id, self.count) = (Some(tx_id.clone()), Some(*applied));
+                self.message = Some(error.clone());
+            }
+            Operation::Encrypted { cipher, key_id, .. } => {
+                self.details = Some(json!({ "cipher": cipher, "key_id": key_id }));
+            }
+            Operation::HashAlgorithmChanged { from, to } => self.details = Some(json!({ "from": from, "to": to })),
+        }
+    }
+
+    /// Every column as a JSON value, in [`EXPORT_COLUMNS`] order.
+    fn columns(&self) -> impl Iterator<Item = (&'static str, Value)> {
+        let Value::Object(mut fields) = serde_json::to_value(self).expect("flat record must serialise") else {
+            unreachable!("flat record serialises as an object")
+        };
+        EXPORT_COLUMNS.into_iter().map(move |column| (column, fields.remove(column).unwrap_or(Value::Null)))
+    }
+
+    /// The record as an RFC 5424 message.
+    fn syslog(&self) -> String {
+        let severity = match self.outcome {
+            Some("failure") | Some("rolled_back") => 4,
+            _ => 5,
+        };
+        let params: String = self.columns()
+            .filter(|(name, value)| !value.is_null() && !matches!(*name, "timestamp" | "op" | "hostname" | "pid"))
+            .map(|(name, value)| format!(" {name}=\"{}\"", escape_param(&text(value))))
+            .collect();
+        let summary = [self.path.as_deref(), self.kind.as_deref(), self.tx_id.as_deref()]
+            .into_iter()
+            .flatten()
+            .fold(self.op.clone(), |msg, part| msg + " " + part);
+        format!(
+            "<{}>1 {} {} {SYSLOG_APP_NAME} {} {} [{SYSLOG_SD_ID}{params}] {summary}",
+            SYSLOG_FACILITY * 8 + severity,
+            // RFC 5424 allows at most six fractional digits.
+            self.at.to_rfc3339_opts(SecondsFormat::Micros, true),
+            header_field(self.context.hostname.as_deref(), 255),
+            self.context.pid.map_or_else(|| "-".to_owned(), |pid| pid.to_string()),
+            header_field(Some(&self.op), 32),
+        )
+    }
+}
+
+/// The externally tagged variant name of `operation`.
+pub(crate) fn variant_name(operation: &Operation) -> String {
+    match serde_json::to_value(operation) {
+        Ok(Value::Object(map)) => map.keys().next().cloned().unwrap_or_default(),
+        Ok(Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+/// A column value as text: strings bare, other values as JSON, null empty.
+fn text(value: Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s,
+        other => other.to_string(),
+    }
+}
+
+/// A syslog header field: printable ASCII without spaces, at most `max`
+/// characters, or `-` when absent.
+fn header_field(value: Option<&str>, max: usize) -> String {
+    let field: String = value.unwrap_or_default().chars().filter(|c| c.is_ascii_graphic()).take(max).collect();
+    if field.is_empty() { "-".to_owned() } else { field }
+}
+
+/// Escape `"`, `\` and `]` in a structured-data parameter value.
+fn escape_param(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') { out.push('\\'); }
+        out.push(c);
+    }
+    out
+}
+
+/// Write one CSV row, quoting fields as RFC 4180 requires.
+fn write_csv_row<W: Write>(out: &mut W, fields: impl Iterator<Item = String>) -> std::io::Result<()> {
+    let row: Vec<String> = fields
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field
+            }
+        })
+        .collect();
+    out.write_all(row.join(",").as_bytes())?;
+    out.write_all(b"\r\n")
+}
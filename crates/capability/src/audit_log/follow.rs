@@ -0,0 +1,375 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Following — Watching a Live Log.
+//!
+//! A [`Follower`] reads a log while another process appends to it, yielding
+//! each new entry as a [`FollowEvent`] and checking its chain link against
+//! the entry before it as it arrives, so tampering is reported as soon as
+//! it is visible rather than at the next full `verify`.
+//!
+//! WAKEUPS:
+//! On Linux the follower watches the log's directory with inotify and
+//! re-reads the file as soon as anything in it changes. Elsewhere, or if
+//! inotify is unavailable, it polls every `poll_interval`; on Linux the
+//! interval remains a safety net for missed notifications.
+//!
+//! WHAT IS REPORTED:
+//! - `Entry`: a new entry whose sequence number and `prev_hash` link to the
+//!   previous entry.
+//! - `ChainBroken`: a new entry that does not link. Following continues
+//!   from it, so later entries are checked against what is now on disk.
+//! - `Unreadable`: a complete record that cannot be parsed.
+//! - `Truncated`: the file shrank below what had already been read.
+//! - `Replaced`: the file was swapped for one that does not continue the
+//!   chain.
+//! - `Rotated`: the file was sealed as a segment (see the `segment`
+//!   module) and a new active file continues the chain.
+//!
+//! After `Truncated` or `Replaced` the follower resumes from the end of
+//! whatever is now at the path. A record still being written (a partial
+//! final line) is not an error; it is read once it is complete.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+#[cfg(target_os = "linux")]
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::format::{Record, RecordReader};
+use super::{compact, AuditLog, IntegrityError, LogEntry, SegmentManifest, GENESIS_HASH};
+
+/// Default interval between re-reads when no notification arrives.
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Something the follower observed in the log.
+#[derive(Debug, Clone)]
+pub enum FollowEvent {
+    /// A new entry that links to the one before it.
+    Entry(Box<LogEntry>),
+    /// A new entry that does not link to the one before it.
+    ChainBroken {
+        /// Sequence number the entry should have carried.
+        expected_seq: u64,
+        /// `prev_hash` the entry should have carried.
+        expected_prev_hash: String,
+        /// The entry as found.
+        entry: Box<LogEntry>,
+    },
+    /// A complete record at byte `offset` that could not be read.
+    Unreadable { offset: u64, reason: String },
+    /// The file is now `len` bytes, shorter than the `read` bytes already
+    /// read from it.
+    Truncated { read: u64, len: u64 },
+    /// The file was replaced by one that does not continue the chain.
+    Replaced,
+    /// The file was sealed as segment `segment` and a new active file
+    /// started.
+    Rotated { segment: u32 },
+}
+
+/// Reads a log as it grows; see the module docs.
+///
+/// [`Follower::poll`] returns immediately; [`Follower::next_timeout`] and
+/// the `Iterator` implementation wait for the next event.
+pub struct Follower {
+    path: PathBuf,
+    reader: Option<RecordReader>,
+    file_id: Option<(u64, u64)>,
+    /// End offset of the last complete record read.
+    read: u64,
+    next_seq: u64,
+    last_hash: String,
+    /// Rebuild the chain state silently from the file when next opened.
+    resync: bool,
+    pending: VecDeque<FollowEvent>,
+    wakeup: Wakeup,
+    poll_interval: Duration,
+}
+
+impl Follower {
+    /// Follow the log at `path` from its first entry, yielding every entry
+    /// already in the active file before new ones. For a segmented log the
+    /// chain is checked from where the sealed segments end, and for a
+    /// compacted one from its checkpoint.
+    pub fn from_start<P: AsRef<Path>>(path: P) -> Result<Self, IntegrityError> {
+        let path = path.as_ref().to_path_buf();
+        let tip = SegmentManifest::load(&path)?.and_then(|manifest| manifest.tip());
+        let (last_hash, next_seq) = match tip {
+            Some(tip) => tip,
+            None => compact::active_checkpoint(&path)?
+                .map_or_else(|| (GENESIS_HASH.to_owned(), 0), |c| (c.head_hash, c.entries)),
+        };
+        Ok(Self {
+            wakeup: Wakeup::new(&path),
+            path,
+            reader: None,
+            file_id: None,
+            read: 0,
+            next_seq,
+            last_hash,
+            resync: false,
+            pending: VecDeque::new(),
+            poll_interval: DEFAULT_POLL_INTERVAL,
+        })
+    }
+
+    /// Verify the log at `path` and follow it from its current end.
+    pub fn from_end<P: AsRef<Path>>(path: P) -> Result<Self, IntegrityError> {
+        let path = path.as_ref();
+        if path.exists() {
+            AuditLog::verify(path)?;
+        }
+        let mut follower = Self::from_start(path)?;
+        follower.resync = true;
+        follower.fill()?;
+        follower.pending.clear();
+        Ok(follower)
+    }
+
+    /// Re-read the file at least this often even without a notification.
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Whether changes are detected with inotify rather than by polling.
+    pub fn is_notified(&self) -> bool {
+        !matches!(self.wakeup, Wakeup::Poll)
+    }
+
+    /// The next event if one is available now, without waiting.
+    pub fn poll(&mut self) -> Result<Option<FollowEvent>, IntegrityError> {
+        if self.pending.is_empty() {
+            self.fill()?;
+        }
+        Ok(self.pending.pop_front())
+    }
+
+    /// The next event, waiting up to `timeout` for one.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Result<Option<FollowEvent>, IntegrityError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(event) = self.poll()? {
+                return Ok(Some(event));
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(None);
+            }
+            self.wakeup.wait(left.min(self.poll_interval));
+        }
+    }
+
+    /// Read every complete record appended since the last call, then check
+    /// whether the file was truncated, replaced or rotated.
+    fn fill(&mut self) -> Result<(), IntegrityError> {
+        if self.reader.is_none() {
+            self.open_reader()?;
+        }
+        if let Some(reader) = self.reader.as_mut() {
+            while !reader.lost_sync {
+                match reader.next_record() {
+                    Ok(None) => break,
+                    Ok(Some(Record::Torn)) => {
+                        // Still being written: read it again once complete.
+                        reader.unread()?;
+                        break;
+                    }
+                    Ok(Some(Record::Blank)) => self.read = reader.offset + reader.len,
+                    Ok(Some(Record::Entry(entry))) => {
+                        self.read = reader.offset + reader.len;
+                        let event = link(&mut self.next_seq, &mut self.last_hash, entry);
+                        self.pending.push_back(event);
+                    }
+                    Err(IntegrityError::Io(e)) => return Err(e.into()),
+                    Err(e) => {
+                        self.read = reader.offset + reader.len;
+                        self.pending.push_back(FollowEvent::Unreadable { offset: reader.offset, reason: e.to_string() });
+                    }
+                }
+            }
+        }
+        self.check_file()
+    }
+
+    fn open_reader(&mut self) -> Result<(), IntegrityError> {
+        let meta = match fs::metadata(&self.path) {
+            Ok(meta) if meta.len() > 0 => meta,
+            Ok(_) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut reader = RecordReader::open(&self.path)?;
+        self.file_id = file_id(&meta);
+        self.read = reader.offset;
+        if std::mem::take(&mut self.resync) {
+            // Adopt whatever the file now ends with as the chain tip.
+            while let Some(record) = reader.next_record().ok().flatten() {
+                match record {
+                    Record::Torn => {
+                        reader.unread()?;
+                        break;
+                    }
+                    Record::Blank => {}
+                    Record::Entry(entry) => {
+                        self.next_seq = entry.seq + 1;
+                        self.last_hash = entry.hash();
+                    }
+                }
+                self.read = reader.offset + reader.len;
+            }
+        }
+        self.reader = Some(reader);
+        Ok(())
+    }
+
+    fn check_file(&mut self) -> Result<(), IntegrityError> {
+        if self.reader.is_none() {
+            return Ok(());
+        }
+        let meta = match fs::metadata(&self.path) {
+            Ok(meta) => meta,
+            // Mid-rotation or deleted; look again next time.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let replaced = matches!((self.file_id, file_id(&meta)), (Some(old), Some(new)) if old != new);
+        if !replaced && meta.len() >= self.read {
+            return Ok(());
+        }
+
+        let rotated = SegmentManifest::load(&self.path)?
+            .and_then(|manifest| manifest.segments.last().cloned())
+            .filter(|s| s.head_hash == self.last_hash && s.first_seq + s.entries == self.next_seq);
+        let event = match rotated {
+            Some(segment) => FollowEvent::Rotated { segment: segment.index },
+            None if replaced => FollowEvent::Replaced,
+            None => FollowEvent::Truncated { read: self.read, len: meta.len() },
+        };
+        self.resync = !matches!(event, FollowEvent::Rotated { .. });
+        self.pending.push_back(event);
+        self.reader = None;
+        self.file_id = None;
+        self.read = 0;
+        Ok(())
+    }
+}
+
+impl Iterator for Follower {
+    type Item = Result<FollowEvent, IntegrityError>;
+
+    /// Wait for the next event. Never returns `None`.
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.poll() {
+                Ok(Some(event)) => return Some(Ok(event)),
+                Ok(None) => self.wakeup.wait(self.poll_interval),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Follower {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Follower")
+            .field("path", &self.path)
+            .field("next_seq", &self.next_seq)
+            .field("notified", &self.is_notified())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Check `entry` against the chain tip and advance the tip to it.
+fn link(next_seq: &mut u64, last_hash: &mut String, entry: Box<LogEntry>) -> FollowEvent {
+    let linked = entry.seq == *next_seq && entry.prev_hash == *last_hash;
+    let expected_seq = std::mem::replace(next_seq, entry.seq + 1);
+    let expected_prev_hash = std::mem::replace(last_hash, entry.hash());
+    if linked {
+        FollowEvent::Entry(entry)
+    } else {
+        FollowEvent::ChainBroken { expected_seq, expected_prev_hash, entry }
+    }
+}
+
+#[cfg(unix)]
+fn file_id(meta: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_meta: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// How the follower learns that the file may have changed.
+enum Wakeup {
+    /// A thread blocked on inotify sends a message per batch of events in
+    /// the log's directory.
+    #[cfg(target_os = "linux")]
+    Inotify { events: Receiver<()>, watches: inotify::Watches, watch: inotify::WatchDescriptor },
+    /// Sleep for the poll interval.
+    Poll,
+}
+
+impl Wakeup {
+    fn new(path: &Path) -> Self {
+        #[cfg(target_os = "linux")]
+        if let Ok(wakeup) = Self::inotify(path) {
+            return wakeup;
+        }
+        let _ = path;
+        Wakeup::Poll
+    }
+
+    #[cfg(target_os = "linux")]
+    fn inotify(path: &Path) -> io::Result<Self> {
+        use inotify::{EventMask, Inotify, WatchMask};
+
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let mut inotify = Inotify::init()?;
+        let mut watches = inotify.watches();
+        let mask = WatchMask::MODIFY | WatchMask::CREATE | WatchMask::DELETE | WatchMask::MOVED_FROM | WatchMask::MOVED_TO;
+        let watch = watches.add(dir, mask)?;
+        let (notify, events) = std::sync::mpsc::channel();
+        thread::Builder::new().name("polysafe-audit-follow".into()).spawn(move || {
+            let mut buffer = [0u8; 4096];
+            // Ends when the watch is removed (the follower was dropped or the
+            // directory went away) or the follower stops listening.
+            while let Ok(batch) = inotify.read_events_blocking(&mut buffer) {
+                let ignored = batch.into_iter().any(|event| event.mask.contains(EventMask::IGNORED));
+                if notify.send(()).is_err() || ignored {
+                    return;
+                }
+            }
+        })?;
+        Ok(Wakeup::Inotify { events, watches, watch })
+    }
+
+    /// Wait until a notification arrives or `timeout` passes.
+    fn wait(&self, timeout: Duration) {
+        match self {
+            #[cfg(target_os = "linux")]
+            Wakeup::Inotify { events, .. } => match events.recv_timeout(timeout) {
+                Ok(()) => while events.try_recv().is_ok() {},
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => thread::sleep(timeout),
+            },
+            Wakeup::Poll => thread::sleep(timeout),
+        }
+    }
+}
+
+impl Drop for Wakeup {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        if let Wakeup::Inotify { watches, watch, .. } = self {
+            let _ = watches.remove(watch.clone());
+        }
+    }
+}
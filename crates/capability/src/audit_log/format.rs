@@ -0,0 +1,507 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! On-Disk Formats — NDJSON and Compact Binary Records.
+//!
+//! An audit log is stored either as NDJSON (one JSON entry per line) or as
+//! a binary segment. The format is a storage detail only: both hold the
+//! same `LogEntry` values, hashes are always computed over the canonical
+//! encoding, and `AuditLog::verify` gives the same answer for either.
+//!
+//! BINARY LAYOUT (integers little-endian):
+//!
+//! ```text
+//! file    := magic [header] record*
+//! magic   := "PSALBIN" 0x01
+//! header  := len:u32 HEADER_MARKER header-json crc32:u32
+//! record  := len:u32 payload[len] crc32(payload):u32
+//! payload := bincode(BinaryEntry) [bincode(extras)], varint integer encoding
+//! extras  := JSON object of the entry's optional fields, as a string
+//! ```
+//!
+//! An NDJSON file likewise starts with an optional `{"log_header": …}`
+//! line; see the `header` module.
+//!
+//! `BinaryEntry` holds the fields every entry has. Optional fields (such as
+//! the audit context) go in the trailing, self-describing `extras` object,
+//! which is omitted when none is set; optional fields added later therefore
+//! never change the layout of records that do not use them.
+//!
+//! Timestamps are stored as seconds + nanoseconds and hashes as raw digest
+//! bytes, which together with the absence of JSON keys makes records a
+//! fraction of the size of their NDJSON lines.
+//!
+//! A record cut short by end-of-file is a torn tail; a complete record with
+//! a bad checksum or undecodable payload is corruption.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use bincode::Options;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::header::HEADER_MARKER;
+use super::{hex, migrate, AuditContext, Change, HashAlgorithm, IntegrityError, LogEntry, LogHeader, Operation, Outcome, ENTRY_VERSION};
+
+/// Leading bytes identifying a binary audit log.
+pub const BINARY_MAGIC: &[u8; 8] = b"PSALBIN\x01";
+
+/// Upper bound on a single binary record payload.
+const MAX_RECORD_LEN: u32 = 16 * 1024 * 1024;
+
+/// Storage format of an audit log file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Newline-delimited JSON, one entry per line.
+    #[default]
+    Ndjson,
+    /// Length-prefixed, checksummed bincode records after [`BINARY_MAGIC`].
+    Binary,
+}
+
+impl LogFormat {
+    /// Bytes written at the start of a new file in this format.
+    pub(crate) fn header(self) -> &'static [u8] {
+        match self {
+            LogFormat::Ndjson => &[],
+            LogFormat::Binary => BINARY_MAGIC,
+        }
+    }
+
+    /// Detect the format of the log at `path`, or `None` if it is missing
+    /// or empty.
+    pub fn detect<P: AsRef<Path>>(path: P) -> io::Result<Option<Self>> {
+        let mut file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut magic = [0u8; BINARY_MAGIC.len()];
+        let n = read_full(&mut file, &mut magic)?;
+        Ok(match n {
+            0 => None,
+            _ if magic == *BINARY_MAGIC => Some(LogFormat::Binary),
+            _ => Some(LogFormat::Ndjson),
+        })
+    }
+}
+
+/// Field-for-field binary mirror of [`LogEntry`].
+///
+/// Kept separate so the binary layout never depends on JSON-oriented serde
+/// attributes, which non-self-describing formats cannot honour.
+#[derive(Serialize, Deserialize)]
+struct BinaryEntry {
+    version: u8,
+    seq: u64,
+    secs: i64,
+    nanos: u32,
+    prev_hash: Vec<u8>,
+    operation: Operation,
+}
+
+/// Optional [`LogEntry`] fields, stored after the [`BinaryEntry`] as JSON.
+#[derive(Default, Serialize, Deserialize)]
+struct BinaryExtras {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    context: Option<AuditContext>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    outcome: Option<Outcome>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    change: Option<Change>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tx_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    clamped_from: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hash_alg: Option<HashAlgorithm>,
+}
+
+impl BinaryExtras {
+    fn from_entry(entry: &LogEntry) -> Option<Self> {
+        let extras = Self {
+            context: entry.context.clone(),
+            outcome: entry.outcome.clone(),
+            change: entry.change.clone(),
+            tx_id: entry.tx_id.clone(),
+            clamped_from: entry.clamped_from,
+            hash_alg: entry.hash_alg,
+        };
+        let any = extras.context.is_some() || extras.outcome.is_some() || extras.change.is_some()
+            || extras.tx_id.is_some() || extras.clamped_from.is_some() || extras.hash_alg.is_some();
+        any.then_some(extras)
+    }
+}
+
+impl BinaryEntry {
+    fn from_entry(entry: &LogEntry) -> io::Result<Self> {
+        let prev_hash = hex::decode(&entry.prev_hash).ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("prev_hash of entry {} is not lowercase hex", entry.seq),
+        ))?;
+        Ok(Self {
+            version: entry.version,
+            seq: entry.seq,
+            secs: entry.timestamp.timestamp(),
+            nanos: entry.timestamp.timestamp_subsec_nanos(),
+            prev_hash,
+            operation: entry.operation.clone(),
+        })
+    }
+
+    fn into_entry(self, extras: BinaryExtras) -> Result<LogEntry, String> {
+        let timestamp = DateTime::from_timestamp(self.secs, self.nanos)
+            .ok_or_else(|| format!("timestamp {}.{} out of range", self.secs, self.nanos))?;
+        Ok(LogEntry {
+            version: self.version,
+            seq: self.seq,
+            timestamp,
+            prev_hash: hex::encode(&self.prev_hash),
+            operation: self.operation,
+            context: extras.context,
+            outcome: extras.outcome,
+            change: extras.change,
+            tx_id: extras.tx_id,
+            clamped_from: extras.clamped_from,
+            hash_alg: extras.hash_alg,
+        })
+    }
+
+    /// Decode a record payload: the entry, then any extras.
+    fn decode(mut payload: &[u8]) -> Result<LogEntry, String> {
+        let entry: Self = bincode_options().deserialize_from(&mut payload).map_err(|e| e.to_string())?;
+        let extras = if payload.is_empty() {
+            BinaryExtras::default()
+        } else {
+            let json: String = bincode_options().deserialize_from(&mut payload).map_err(|e| e.to_string())?;
+            serde_json::from_str(&json).map_err(|e| format!("record extras: {e}"))?
+        };
+        if !payload.is_empty() {
+            return Err(format!("{} unexpected trailing bytes", payload.len()));
+        }
+        entry.into_entry(extras)
+    }
+}
+
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new().with_limit(u64::from(MAX_RECORD_LEN))
+}
+
+/// The bytes a new file in `format` starts with: the format's magic, then
+/// `header` as a record, if given.
+pub(crate) fn prelude(format: LogFormat, header: Option<&LogHeader>) -> io::Result<Vec<u8>> {
+    let mut out = format.header().to_vec();
+    let Some(header) = header else { return Ok(out) };
+    match format {
+        LogFormat::Ndjson => {
+            serde_json::to_writer(&mut out, &serde_json::json!({ "log_header": header })).map_err(io::Error::other)?;
+            out.push(b'\n');
+        }
+        LogFormat::Binary => {
+            let mut payload = HEADER_MARKER.to_vec();
+            serde_json::to_writer(&mut payload, header).map_err(io::Error::other)?;
+            out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            out.extend_from_slice(&payload);
+            out.extend_from_slice(&crc32(&payload).to_le_bytes());
+        }
+    }
+    Ok(out)
+}
+
+/// Encode `entry` as one complete record in `format`.
+pub(crate) fn encode_record(entry: &LogEntry, format: LogFormat) -> io::Result<Vec<u8>> {
+    match format {
+        LogFormat::Ndjson => {
+            let mut line = serde_json::to_vec(entry).map_err(io::Error::other)?;
+            line.push(b'\n');
+            Ok(line)
+        }
+        LogFormat::Binary => {
+            let mut payload = bincode_options()
+                .serialize(&BinaryEntry::from_entry(entry)?)
+                .map_err(io::Error::other)?;
+            if let Some(extras) = BinaryExtras::from_entry(entry) {
+                let json = serde_json::to_string(&extras).map_err(io::Error::other)?;
+                bincode_options().serialize_into(&mut payload, &json).map_err(io::Error::other)?;
+            }
+            let len = u32::try_from(payload.len()).ok()
+                .filter(|&n| n <= MAX_RECORD_LEN)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "audit log entry too large"))?;
+            let mut record = Vec::with_capacity(payload.len() + 8);
+            record.extend_from_slice(&len.to_le_bytes());
+            record.extend_from_slice(&payload);
+            record.extend_from_slice(&crc32(&payload).to_le_bytes());
+            Ok(record)
+        }
+    }
+}
+
+/// One record read back from a log file.
+pub(crate) enum Record {
+    /// A complete, parseable entry.
+    Entry(Box<LogEntry>),
+    /// A blank NDJSON line (tolerated, carries no entry).
+    Blank,
+    /// A final record cut short by end-of-file.
+    Torn,
+}
+
+/// Sequential reader over the records of a log file in either format that
+/// tracks byte offsets so torn tails can be located precisely.
+pub(crate) struct RecordReader {
+    inner: BufReader<File>,
+    format: LogFormat,
+    header: Option<LogHeader>,
+    buf: Vec<u8>,
+    /// Byte offset of the first record after the magic and header.
+    pub(crate) start: u64,
+    /// 1-based number of the record most recently read, counting the header
+    /// (the line number for NDJSON logs).
+    pub(crate) line_no: usize,
+    /// Byte offset at which the most recently read record starts.
+    pub(crate) offset: u64,
+    /// Byte length of the most recently read record.
+    pub(crate) len: u64,
+    /// Set when a record's framing was unreadable, so the position of the
+    /// next record is unknown and reading cannot continue past an error.
+    pub(crate) lost_sync: bool,
+}
+
+impl RecordReader {
+    /// Open `path`, detecting its format from the leading bytes and reading
+    /// its header record, if it has one.
+    pub(crate) fn open(path: &Path) -> io::Result<Self> {
+        let format = LogFormat::detect(path)?.unwrap_or_default();
+        let mut inner = BufReader::new(File::open(path)?);
+        let magic = format.header().len() as u64;
+        inner.seek(SeekFrom::Start(magic))?;
+        let header = read_header(&mut inner, format)?;
+        let start = inner.stream_position()?;
+        let line_no = usize::from(header.is_some());
+        Ok(Self { inner, format, header, buf: Vec::new(), start, line_no, offset: start, len: 0, lost_sync: false })
+    }
+
+    /// The file's header, if it has one.
+    pub(crate) fn header(&self) -> Option<&LogHeader> {
+        self.header.as_ref()
+    }
+
+    /// Position the reader at the record starting at byte `offset`, which is
+    /// preceded by `records` earlier records after the header.
+    pub(crate) fn seek_to(&mut self, offset: u64, records: usize) -> io::Result<()> {
+        self.inner.seek(SeekFrom::Start(offset))?;
+        self.offset = offset;
+        self.len = 0;
+        self.line_no = records + usize::from(self.header.is_some());
+        Ok(())
+    }
+
+    /// Step back so the record most recently read is read again.
+    pub(crate) fn unread(&mut self) -> io::Result<()> {
+        self.inner.seek(SeekFrom::Start(self.offset))?;
+        self.len = 0;
+        self.line_no -= 1;
+        Ok(())
+    }
+
+    /// Storage format of the file being read.
+    pub(crate) fn format(&self) -> LogFormat {
+        self.format
+    }
+
+    /// Read the next record, or `None` at end of file.
+    ///
+    /// A complete but unreadable record is an error; only a record cut
+    /// short by end-of-file is classified as [`Record::Torn`].
+    pub(crate) fn next_record(&mut self) -> Result<Option<Record>, IntegrityError> {
+        self.offset += self.len;
+        self.len = 0;
+        match self.format {
+            LogFormat::Ndjson => self.next_line(),
+            LogFormat::Binary => self.next_binary(),
+        }
+    }
+
+    fn next_line(&mut self) -> Result<Option<Record>, IntegrityError> {
+        self.buf.clear();
+        let n = self.inner.read_until(b'\n', &mut self.buf)?;
+        if n == 0 { return Ok(None); }
+        self.line_no += 1;
+        self.len = n as u64;
+
+        if self.buf.last() != Some(&b'\n') {
+            return Ok(Some(Record::Torn));
+        }
+
+        let line = String::from_utf8_lossy(&self.buf);
+        if line.trim().is_empty() {
+            return Ok(Some(Record::Blank));
+        }
+
+        let entry: LogEntry = serde_json::from_str(&line).map_err(|e| {
+            if migrate::is_legacy_line(&line) {
+                IntegrityError::LegacyEntry { line: self.line_no }
+            } else {
+                IntegrityError::Deserialisation { line: self.line_no, cause: e.to_string() }
+            }
+        })?;
+        self.checked(entry)
+    }
+
+    fn next_binary(&mut self) -> Result<Option<Record>, IntegrityError> {
+        let mut len_bytes = [0u8; 4];
+        let n = read_full(&mut self.inner, &mut len_bytes)?;
+        if n == 0 { return Ok(None); }
+        self.line_no += 1;
+        self.len = n as u64;
+        if n < len_bytes.len() { return Ok(Some(Record::Torn)); }
+
+        let len = u32::from_le_bytes(len_bytes);
+        if len > MAX_RECORD_LEN {
+            self.lost_sync = true;
+            return Err(IntegrityError::Deserialisation {
+                line: self.line_no,
+                cause: format!("record length {len} exceeds limit"),
+            });
+        }
+
+        self.buf.resize(len as usize + 4, 0);
+        let n = read_full(&mut self.inner, &mut self.buf)?;
+        self.len += n as u64;
+        if n < self.buf.len() { return Ok(Some(Record::Torn)); }
+
+        let (payload, crc) = self.buf.split_at(len as usize);
+        let crc = u32::from_le_bytes(crc.try_into().expect("4-byte checksum"));
+        if crc32(payload) != crc {
+            return Err(IntegrityError::ChecksumMismatch { record: self.line_no, offset: self.offset });
+        }
+
+        let entry = BinaryEntry::decode(payload)
+            .map_err(|cause| IntegrityError::Deserialisation { line: self.line_no, cause })?;
+        self.checked(entry)
+    }
+
+    fn checked(&self, entry: LogEntry) -> Result<Option<Record>, IntegrityError> {
+        if entry.version != ENTRY_VERSION {
+            return Err(IntegrityError::UnsupportedVersion { line: self.line_no, version: entry.version });
+        }
+        Ok(Some(Record::Entry(Box::new(entry))))
+    }
+}
+
+/// Read a header record at the current position of `inner`, leaving it just
+/// past the header, or where it was if there is none. A record that is not
+/// complete is left to be read as a torn tail.
+fn read_header(inner: &mut BufReader<File>, format: LogFormat) -> io::Result<Option<LogHeader>> {
+    let at = inner.stream_position()?;
+    let json = match format {
+        LogFormat::Ndjson => {
+            let mut line = Vec::new();
+            inner.read_until(b'\n', &mut line)?;
+            let value = match serde_json::from_slice::<serde_json::Value>(&line) {
+                Ok(serde_json::Value::Object(mut map)) if line.ends_with(b"\n") => map.remove("log_header"),
+                _ => None,
+            };
+            value.map(serde_json::from_value::<LogHeader>)
+        }
+        LogFormat::Binary => {
+            let mut len = [0u8; 4];
+            let mut record = Vec::new();
+            if read_full(inner, &mut len)? == len.len() && u32::from_le_bytes(len) <= MAX_RECORD_LEN {
+                record.resize(u32::from_le_bytes(len) as usize + 4, 0);
+                if read_full(inner, &mut record)? < record.len() {
+                    record.clear();
+                }
+            }
+            match record.len().checked_sub(4).map(|n| record.split_at(n)) {
+                Some((payload, crc)) if payload.starts_with(HEADER_MARKER) => {
+                    if crc32(payload).to_le_bytes() != crc {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "log header checksum mismatch"));
+                    }
+                    Some(serde_json::from_slice::<LogHeader>(&payload[HEADER_MARKER.len()..]))
+                }
+                _ => None,
+            }
+        }
+    };
+    match json {
+        Some(parsed) => {
+            let header = parsed.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("log header: {e}")))?;
+            header.check()?;
+            Ok(Some(header))
+        }
+        None => {
+            inner.seek(SeekFrom::Start(at))?;
+            Ok(None)
+        }
+    }
+}
+
+/// Losslessly copy every entry of the log at `src` into a new log at `dst`
+/// stored in `format`.
+///
+/// Entries are copied verbatim — the chain is not re-computed or repaired —
+/// so `verify` reports exactly the same result for both files. `dst` must
+/// not exist. Returns the number of entries copied.
+pub fn convert<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q, format: LogFormat) -> Result<usize, IntegrityError> {
+    let mut reader = RecordReader::open(src.as_ref())?;
+    let mut out = OpenOptions::new().write(true).create_new(true).open(dst.as_ref())?;
+    let mut buffered = io::BufWriter::new(&mut out);
+    buffered.write_all(&prelude(format, reader.header())?)?;
+
+    let mut count = 0usize;
+    while let Some(record) = reader.next_record()? {
+        match record {
+            Record::Entry(entry) => {
+                buffered.write_all(&encode_record(&entry, format)?)?;
+                count += 1;
+            }
+            Record::Blank => {}
+            Record::Torn => return Err(IntegrityError::TornTail { offset: reader.offset, bytes: reader.len }),
+        }
+    }
+
+    buffered.flush()?;
+    drop(buffered);
+    out.sync_all()?;
+    Ok(count)
+}
+
+/// Read until `buf` is full or end-of-file; returns the bytes read.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+// ─── CRC-32 (IEEE 802.3) ─────────────────────────────────────────────────────
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xEDB8_8320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 checksum of `bytes`, as used by zlib and PNG.
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |c, &b| CRC_TABLE[((c ^ u32::from(b)) & 0xFF) as usize] ^ (c >> 8))
+}
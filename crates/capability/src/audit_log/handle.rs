@@ -0,0 +1,177 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Shared Handles — One Log, Many Threads.
+//!
+//! `AuditLog::append` takes `&mut self`. An [`AuditHandle`] instead moves
+//! the log onto a dedicated writer thread and hands out cheap, cloneable,
+//! `Send + Sync` handles that submit entries over a bounded channel.
+//!
+//! ORDERING:
+//! The writer appends requests in the order the channel delivers them, so
+//! the chain order is the order in which `append` calls returned — in
+//! particular, entries submitted by one thread keep their relative order.
+//!
+//! BACKPRESSURE:
+//! The channel holds at most `capacity` pending requests. When it is full,
+//! `append` blocks until the writer catches up and `try_append_with`
+//! fails with `io::ErrorKind::WouldBlock`, so a burst of producers cannot
+//! grow memory without bound while the writer waits on `fsync`.
+//!
+//! ERRORS:
+//! `append` only enqueues; an entry that then fails to be written is
+//! reported by the next [`AuditHandle::flush`] (or by `shutdown`).
+//! [`AuditHandle::append_wait`] waits for the writer and returns the
+//! entry's `AppendTicket` or error directly.
+//!
+//! SHUTDOWN:
+//! [`AuditHandle::shutdown`], or dropping the last handle, writes every
+//! entry already queued, flushes the log to stable storage and joins the
+//! writer thread. Appends after shutdown fail with `BrokenPipe`.
+
+use std::fmt;
+use std::io;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use super::{AppendTicket, AuditLog, EntryMeta, Operation};
+
+/// A cloneable, thread-safe handle to an [`AuditLog`] owned by a background
+/// writer thread.
+#[derive(Clone)]
+pub struct AuditHandle {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    requests: SyncSender<Request>,
+    writer: Mutex<Option<JoinHandle<io::Result<()>>>>,
+}
+
+enum Request {
+    Append { operation: Operation, meta: EntryMeta, reply: Option<mpsc::Sender<io::Result<AppendTicket>>> },
+    Flush(mpsc::Sender<io::Result<()>>),
+    Shutdown,
+}
+
+impl AuditHandle {
+    /// Move `log` onto a new writer thread that accepts up to `capacity`
+    /// queued requests. A capacity of 0 makes every append wait for the
+    /// writer to take it.
+    pub fn spawn(log: AuditLog, capacity: usize) -> io::Result<Self> {
+        let (requests, queue) = mpsc::sync_channel(capacity);
+        let writer = thread::Builder::new()
+            .name("polysafe-audit-writer".into())
+            .spawn(move || run(log, queue))?;
+        Ok(Self { shared: Arc::new(Shared { requests, writer: Mutex::new(Some(writer)) }) })
+    }
+
+    /// Queue `operation`, blocking while the queue is full.
+    pub fn append(&self, operation: Operation) -> io::Result<()> {
+        self.append_with(operation, EntryMeta::default())
+    }
+
+    /// Queue `operation` with `meta`, blocking while the queue is full.
+    pub fn append_with(&self, operation: Operation, meta: EntryMeta) -> io::Result<()> {
+        self.send(Request::Append { operation, meta, reply: None })
+    }
+
+    /// Queue `operation` with `meta` if there is room, failing with
+    /// `WouldBlock` otherwise.
+    pub fn try_append_with(&self, operation: Operation, meta: EntryMeta) -> io::Result<()> {
+        match self.shared.requests.try_send(Request::Append { operation, meta, reply: None }) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(io::Error::new(io::ErrorKind::WouldBlock, "audit log queue is full")),
+            Err(TrySendError::Disconnected(_)) => Err(stopped()),
+        }
+    }
+
+    /// Queue `operation` with `meta` and wait until the writer has appended
+    /// it.
+    pub fn append_wait(&self, operation: Operation, meta: EntryMeta) -> io::Result<AppendTicket> {
+        let (reply, response) = mpsc::channel();
+        self.send(Request::Append { operation, meta, reply: Some(reply) })?;
+        response.recv().map_err(|_| stopped())?
+    }
+
+    /// Wait until every entry queued before this call is written and
+    /// `fsync`-ed. Fails with the first error of any append queued with
+    /// [`append`](Self::append) since the last flush.
+    pub fn flush(&self) -> io::Result<()> {
+        let (reply, response) = mpsc::channel();
+        self.send(Request::Flush(reply))?;
+        response.recv().map_err(|_| stopped())?
+    }
+
+    /// Write everything queued, flush the log and stop the writer thread.
+    /// Every clone of this handle stops working. Calling it again does
+    /// nothing.
+    pub fn shutdown(&self) -> io::Result<()> {
+        self.shared.shutdown()
+    }
+
+    fn send(&self, request: Request) -> io::Result<()> {
+        self.shared.requests.send(request).map_err(|_| stopped())
+    }
+}
+
+impl fmt::Debug for AuditHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditHandle").field("handles", &Arc::strong_count(&self.shared)).finish_non_exhaustive()
+    }
+}
+
+impl Shared {
+    fn shutdown(&self) -> io::Result<()> {
+        let Some(writer) = self.writer.lock().unwrap_or_else(|e| e.into_inner()).take() else { return Ok(()) };
+        // A send error means the writer already stopped; join reports why.
+        let _ = self.requests.send(Request::Shutdown);
+        writer.join().map_err(|_| io::Error::other("audit log writer thread panicked"))?
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+fn stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "audit log writer has shut down")
+}
+
+/// The writer thread: apply requests in order until shutdown, then drain
+/// what is left and flush.
+fn run(mut log: AuditLog, queue: Receiver<Request>) -> io::Result<()> {
+    let mut failed = None;
+    for request in queue.iter() {
+        if !handle(&mut log, request, &mut failed) {
+            break;
+        }
+    }
+    while let Ok(request) = queue.try_recv() {
+        handle(&mut log, request, &mut failed);
+    }
+    let flushed = log.flush();
+    failed.map_or(flushed, Err)
+}
+
+/// Apply one request. Returns `false` for a shutdown request.
+fn handle(log: &mut AuditLog, request: Request, failed: &mut Option<io::Error>) -> bool {
+    match request {
+        Request::Append { operation, meta, reply } => {
+            let result = log.append_with(operation, meta);
+            match (reply, result) {
+                (Some(reply), result) => { let _ = reply.send(result); }
+                (None, Err(e)) => { failed.get_or_insert(e); }
+                (None, Ok(_)) => {}
+            }
+        }
+        Request::Flush(reply) => {
+            let _ = reply.send(failed.take().map_or_else(|| log.flush(), Err));
+        }
+        Request::Shutdown => return false,
+    }
+    true
+}
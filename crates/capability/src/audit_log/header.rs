@@ -0,0 +1,170 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Log Header — Self-Describing Files and Hash Agility.
+//!
+//! Every log file this version creates — the active file, each new segment,
+//! archives, mirrors and converted copies — starts with a header record
+//! naming what a reader needs to know before the first entry:
+//!
+//! ```text
+//! NDJSON: {"log_header":{"version":1,"entry_version":1,"hash_algorithm":"sha-256",
+//!          "log_id":"…","created_at":"…","params":{"format":"ndjson",…}}}
+//! binary: magic, then one framed record whose payload is HEADER_MARKER
+//!         followed by the same header as JSON
+//! ```
+//!
+//! Files written before headers existed have none and are read as SHA-256.
+//! The log id is random unless one is given in `AuditLogOptions::log_id`,
+//! and is kept when the log rotates or is compacted, converted or mirrored.
+//!
+//! HASH ALGORITHMS:
+//! An entry is hashed with SHA-256, SHA-384 or SHA-512. An entry hashed with
+//! anything but SHA-256 names its algorithm in `hash_alg`, which is part of
+//! its hash input, so every entry can be hashed on its own and cannot be
+//! re-labelled without changing its hash. SHA-256 entries omit the field,
+//! so no existing hash changes. The header names the algorithm of the
+//! file's first entry; `verify` starts from it and rejects any entry that
+//! uses another algorithm than the one in effect.
+//!
+//! SWITCHING ALGORITHMS:
+//! `AuditLog::change_hash_algorithm` appends
+//! `Operation::HashAlgorithmChanged { from, to }`, itself hashed with
+//! `from`. Every later entry is hashed with `to` and its `prev_hash` is the
+//! previous entry's hash in that entry's own algorithm, so the chain stays
+//! unbroken across the switch. Migration entries are never encrypted, so
+//! the switch can be verified without keys. The first entry's `prev_hash`
+//! is the genesis hash whatever the algorithm.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use chrono::{DateTime, Utc};
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+
+use super::format::RecordReader;
+use super::{hex, IntegrityError, ENTRY_VERSION};
+
+/// Version of the header record.
+pub const LOG_HEADER_VERSION: u32 = 1;
+
+/// Leading bytes of a binary header record's payload. No entry payload can
+/// start with them: an entry's first byte is its version.
+pub(crate) const HEADER_MARKER: &[u8; 8] = b"\xffPSALHDR";
+
+/// Digest used to hash entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum HashAlgorithm {
+    /// SHA-256, the algorithm of every log written before headers existed.
+    #[default]
+    #[serde(rename = "sha-256")]
+    Sha256,
+    /// SHA-384.
+    #[serde(rename = "sha-384")]
+    Sha384,
+    /// SHA-512.
+    #[serde(rename = "sha-512")]
+    Sha512,
+}
+
+impl HashAlgorithm {
+    /// The `ring` digest implementing this algorithm.
+    pub(crate) fn digest(self) -> &'static digest::Algorithm {
+        match self {
+            HashAlgorithm::Sha256 => &digest::SHA256,
+            HashAlgorithm::Sha384 => &digest::SHA384,
+            HashAlgorithm::Sha512 => &digest::SHA512,
+        }
+    }
+
+    /// The algorithm's name as written in headers and entries.
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha-256",
+            HashAlgorithm::Sha384 => "sha-384",
+            HashAlgorithm::Sha512 => "sha-512",
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [HashAlgorithm::Sha256, HashAlgorithm::Sha384, HashAlgorithm::Sha512]
+            .into_iter()
+            .find(|alg| alg.name() == s)
+            .ok_or_else(|| format!("unknown hash algorithm {s:?}; expected sha-256, sha-384 or sha-512"))
+    }
+}
+
+/// The first record of a log file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogHeader {
+    /// Header format version; see [`LOG_HEADER_VERSION`].
+    pub version: u32,
+    /// Version of the entries written under this header.
+    pub entry_version: u8,
+    /// Algorithm hashing the file's first entry.
+    pub hash_algorithm: HashAlgorithm,
+    /// Identifies the log across its segments and copies.
+    pub log_id: String,
+    /// When the log was created.
+    pub created_at: DateTime<Utc>,
+    /// Settings the log was created with, such as its storage format.
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+}
+
+impl LogHeader {
+    /// A header for a new log hashed with `hash_algorithm`, with a random
+    /// log id unless `log_id` is given.
+    pub fn new(
+        hash_algorithm: HashAlgorithm,
+        log_id: Option<String>,
+        created_at: DateTime<Utc>,
+        params: BTreeMap<String, String>,
+    ) -> io::Result<Self> {
+        let log_id = match log_id {
+            Some(id) => id,
+            None => {
+                let mut bytes = [0u8; 16];
+                SystemRandom::new().fill(&mut bytes).map_err(|_| io::Error::other("no randomness available for a log id"))?;
+                hex::encode(&bytes)
+            }
+        };
+        Ok(Self { version: LOG_HEADER_VERSION, entry_version: ENTRY_VERSION, hash_algorithm, log_id, created_at, params })
+    }
+
+    /// Read the header of the log file at `path`; `None` if the file has
+    /// none.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Option<Self>, IntegrityError> {
+        Ok(RecordReader::open(path.as_ref())?.header().cloned())
+    }
+
+    /// The same header for a file whose first entry uses `hash_algorithm`.
+    pub(crate) fn with_algorithm(&self, hash_algorithm: HashAlgorithm) -> Self {
+        Self { hash_algorithm, ..self.clone() }
+    }
+
+    /// Check that this build can read entries written under the header.
+    pub(crate) fn check(&self) -> io::Result<()> {
+        if self.version != LOG_HEADER_VERSION || self.entry_version != ENTRY_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported log header version {} (entry version {})", self.version, self.entry_version),
+            ));
+        }
+        Ok(())
+    }
+}
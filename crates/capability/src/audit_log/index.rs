@@ -0,0 +1,216 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Tail Index — Constant-Time Open and Random Access.
+//!
+//! Beside each log file `audit.log` sits `audit.log.idx`, holding one
+//! fixed-width record per entry:
+//!
+//! ```text
+//! index  := "PSALIDX" 0x01 record*
+//! record := offset:u64 len:u32 seq:u64 hash[32]     (little-endian)
+//! ```
+//!
+//! Because records are fixed-width, the tail of the log — and any entry by
+//! position — is found with a single seek instead of a scan.
+//!
+//! The index is a cache, never a source of truth. On open, the first and
+//! last indexed entries are re-read from the log and must match in length,
+//! sequence number and hash; entries written after the last indexed one are
+//! read and indexed. If the check fails the index is discarded and rebuilt
+//! by a full scan, so a stale, truncated or missing index costs time but
+//! never correctness. The index is `fsync`-ed together with the log under
+//! the configured durability mode.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::{hex, IntegrityError, LogEntry};
+use super::format::{Record, RecordReader};
+
+/// Leading bytes identifying an index file.
+const INDEX_MAGIC: &[u8; 8] = b"PSALIDX\x01";
+
+/// Byte width of one index record.
+const RECORD_LEN: u64 = 8 + 4 + 8 + 32;
+
+/// Location and identity of one entry in a log file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct IndexRecord {
+    /// Byte offset of the entry's record in the log file.
+    pub(crate) offset: u64,
+    /// Byte length of the record.
+    pub(crate) len: u32,
+    /// Sequence number of the entry.
+    pub(crate) seq: u64,
+    /// Leading 32 bytes of the entry's hash.
+    hash: [u8; 32],
+}
+
+impl IndexRecord {
+    pub(crate) fn new(offset: u64, len: u64, seq: u64, hash: &str) -> Self {
+        let mut digest = [0u8; 32];
+        if let Some(bytes) = hex::decode(hash) {
+            let n = bytes.len().min(digest.len());
+            digest[..n].copy_from_slice(&bytes[..n]);
+        }
+        Self { offset, len: len as u32, seq, hash: digest }
+    }
+
+    /// Whether `entry`, read back from `offset`, is the entry indexed here.
+    fn matches(&self, entry: &LogEntry) -> bool {
+        entry.seq == self.seq && Self::new(0, 0, entry.seq, &entry.hash()).hash == self.hash
+    }
+
+    fn encode(&self) -> [u8; RECORD_LEN as usize] {
+        let mut out = [0u8; RECORD_LEN as usize];
+        out[0..8].copy_from_slice(&self.offset.to_le_bytes());
+        out[8..12].copy_from_slice(&self.len.to_le_bytes());
+        out[12..20].copy_from_slice(&self.seq.to_le_bytes());
+        out[20..52].copy_from_slice(&self.hash);
+        out
+    }
+
+    fn decode(bytes: &[u8; RECORD_LEN as usize]) -> Self {
+        let field = |range: std::ops::Range<usize>| &bytes[range];
+        Self {
+            offset: u64::from_le_bytes(field(0..8).try_into().expect("8 bytes")),
+            len: u32::from_le_bytes(field(8..12).try_into().expect("4 bytes")),
+            seq: u64::from_le_bytes(field(12..20).try_into().expect("8 bytes")),
+            hash: field(20..52).try_into().expect("32 bytes"),
+        }
+    }
+}
+
+/// Sidecar index of a single log file.
+pub(crate) struct Index {
+    file: Arc<File>,
+    /// Number of complete records in the index.
+    count: u64,
+}
+
+impl Index {
+    /// Path of the index for the log file at `log`.
+    pub(crate) fn path_for(log: &Path) -> PathBuf {
+        let name = log.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        log.with_file_name(format!("{name}.idx"))
+    }
+
+    /// Open or create the index for the log file at `log`.
+    ///
+    /// An index with a bad header is reset; a partial trailing record (a
+    /// crash mid-write) is dropped.
+    pub(crate) fn open(log: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).append(true).create(true).open(Self::path_for(log))?;
+        let len = file.metadata()?.len();
+        let mut magic = [0u8; INDEX_MAGIC.len()];
+        let valid_header = len >= magic.len() as u64 && {
+            (&file).seek(SeekFrom::Start(0))?;
+            (&file).read_exact(&mut magic)?;
+            magic == *INDEX_MAGIC
+        };
+
+        let mut index = Self { file: Arc::new(file), count: 0 };
+        if valid_header {
+            index.count = (len - INDEX_MAGIC.len() as u64) / RECORD_LEN;
+            if len != index.end() {
+                index.file.set_len(index.end())?;
+            }
+        } else {
+            index.file.set_len(0)?;
+            (&*index.file).write_all(INDEX_MAGIC)?;
+        }
+        Ok(index)
+    }
+
+    /// Open the index for `log` read-only, if it exists and has a valid header.
+    fn open_existing(log: &Path) -> io::Result<Option<Self>> {
+        let mut file = match File::open(Self::path_for(log)) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let len = file.metadata()?.len();
+        let mut magic = [0u8; INDEX_MAGIC.len()];
+        if len < magic.len() as u64 { return Ok(None); }
+        file.read_exact(&mut magic)?;
+        if magic != *INDEX_MAGIC { return Ok(None); }
+        let count = (len - magic.len() as u64) / RECORD_LEN;
+        Ok(Some(Self { file: Arc::new(file), count }))
+    }
+
+    /// Handle to the index file, for syncing alongside the log.
+    pub(crate) fn file(&self) -> Arc<File> {
+        Arc::clone(&self.file)
+    }
+
+    /// Number of indexed entries.
+    pub(crate) fn len(&self) -> u64 {
+        self.count
+    }
+
+    /// The record at `position`, counting from the file's first entry.
+    pub(crate) fn get(&self, position: u64) -> io::Result<Option<IndexRecord>> {
+        if position >= self.count { return Ok(None); }
+        let mut bytes = [0u8; RECORD_LEN as usize];
+        (&*self.file).seek(SeekFrom::Start(INDEX_MAGIC.len() as u64 + position * RECORD_LEN))?;
+        (&*self.file).read_exact(&mut bytes)?;
+        Ok(Some(IndexRecord::decode(&bytes)))
+    }
+
+    /// Append a record for the next entry of the log.
+    pub(crate) fn push(&mut self, record: &IndexRecord) -> io::Result<()> {
+        (&*self.file).write_all(&record.encode())?;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Discard every record.
+    pub(crate) fn clear(&mut self) -> io::Result<()> {
+        self.count = 0;
+        self.file.set_len(self.end())
+    }
+
+    fn end(&self) -> u64 {
+        INDEX_MAGIC.len() as u64 + self.count * RECORD_LEN
+    }
+}
+
+/// Re-read the entry `record` points at, returning it only if it is still
+/// exactly the entry that was indexed.
+pub(crate) fn read_indexed(reader: &mut RecordReader, record: &IndexRecord, position: u64) -> Option<LogEntry> {
+    reader.seek_to(record.offset, position as usize).ok()?;
+    match reader.next_record() {
+        Ok(Some(Record::Entry(entry))) if reader.len == u64::from(record.len) && record.matches(&entry) => Some(*entry),
+        _ => None,
+    }
+}
+
+/// Read the entry at `position` of the log file at `path`, whose first
+/// entry has sequence number `first_seq`.
+///
+/// Uses the index when it is present and agrees with the log, and falls
+/// back to a sequential scan otherwise.
+pub(crate) fn entry_at(path: &Path, position: u64, first_seq: u64) -> Result<Option<LogEntry>, IntegrityError> {
+    let mut reader = RecordReader::open(path)?;
+    if let Some(index) = Index::open_existing(path)? {
+        if let Some(record) = index.get(position)? {
+            if let Some(entry) = read_indexed(&mut reader, &record, position) {
+                return Ok(Some(entry));
+            }
+        }
+        reader.seek_to(reader.start, 0)?;
+    }
+
+    let seq = first_seq + position;
+    while let Some(record) = reader.next_record()? {
+        match record {
+            Record::Entry(entry) if entry.seq == seq => return Ok(Some(*entry)),
+            Record::Entry(_) | Record::Blank => {}
+            Record::Torn => break,
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,262 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Tracing Layer — Auditing by Instrumentation.
+//!
+//! [`AuditLayer`] is a `tracing_subscriber::Layer` that appends selected
+//! spans and events to an `AuditLog`, so library code can audit an action
+//! with a `tracing` macro instead of threading a log handle through every
+//! call:
+//!
+//! ```ignore
+//! tracing::info!(target: "audit", op = "file_write", path = %path.display());
+//! ```
+//!
+//! SELECTION:
+//! An event or span is audited if its target is [`AUDIT_TARGET`] (or a
+//! `audit::…` sub-target), or if it carries an `audit` field (`audit = true`
+//! works from any target). Everything else passes through untouched. A span
+//! is audited once, when it is created.
+//!
+//! MAPPING:
+//! An `op` field naming an operation (`file_read`, `file_write`,
+//! `file_move`, `file_delete`, `dir_create`, `git_status_checked`,
+//! `git_commit_created`) together with that operation's fields (`path`;
+//! `from` and `to`; `repo_path` and `message`) becomes the typed
+//! `Operation`. Record paths with `%` or as strings — `?` would quote them.
+//! Anything else becomes `Operation::Custom`: its kind is the `kind` field
+//! if that is a valid namespaced kind, otherwise `tracing.event` or
+//! `tracing.span`, and its details are the remaining fields plus the
+//! target, level and (for spans) the span name.
+//!
+//! METADATA:
+//! `outcome` (`success`, `failure`, `rolled_back`) and `error` fields become
+//! the entry's `Outcome`; a `tx_id` field, on the event or on any enclosing
+//! audited span, tags the entry with that transaction.
+//!
+//! ERRORS:
+//! A layer cannot return errors to the instrumented code. Appends that fail
+//! are counted instead — see [`AuditLayer::failed_appends`] — and the log
+//! itself is left as `AuditLog::append` leaves it.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use serde_json::{Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Event, Metadata, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
+use tracing_subscriber::Layer;
+
+use super::{is_valid_kind, AuditLog, EntryMeta, Operation, Outcome};
+
+/// Target that marks spans and events for the audit log.
+pub const AUDIT_TARGET: &str = "audit";
+
+/// Field that marks a span or event for the audit log from any target.
+const MARKER_FIELD: &str = "audit";
+
+/// A `tracing` layer that appends audited spans and events to an
+/// [`AuditLog`]. Clones write to the same log.
+#[derive(Clone)]
+pub struct AuditLayer {
+    log: Arc<Mutex<AuditLog>>,
+    failures: Arc<AtomicU64>,
+}
+
+impl AuditLayer {
+    /// A layer that takes ownership of `log`.
+    pub fn new(log: AuditLog) -> Self {
+        Self::shared(Arc::new(Mutex::new(log)))
+    }
+
+    /// A layer that appends to a log shared with other writers.
+    pub fn shared(log: Arc<Mutex<AuditLog>>) -> Self {
+        Self { log, failures: Arc::new(AtomicU64::new(0)) }
+    }
+
+    /// The log this layer appends to.
+    pub fn log(&self) -> Arc<Mutex<AuditLog>> {
+        Arc::clone(&self.log)
+    }
+
+    /// Number of audited spans and events whose append failed.
+    pub fn failed_appends(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    fn append(&self, operation: Operation, meta: EntryMeta) {
+        let mut log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+        if log.append_with(operation, meta).is_err() {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl fmt::Debug for AuditLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditLayer").field("failed_appends", &self.failed_appends()).finish_non_exhaustive()
+    }
+}
+
+/// Fields of an audited span, kept for the events inside it.
+struct SpanFields(Map<String, Value>);
+
+impl<S> Layer<S> for AuditLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = FieldMap::default();
+        attrs.record(&mut fields);
+        let metadata = attrs.metadata();
+        if !is_audited(metadata, &fields.0) {
+            return;
+        }
+        let tx_id = fields.string("tx_id").or_else(|| inherited_tx_id(ctx.span_scope(id).into_iter().flatten().skip(1)));
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(fields.0.clone()));
+        }
+        let (operation, meta) = convert(fields.0, metadata, Some(metadata.name()), tx_id);
+        self.append(operation, meta);
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = FieldMap::default();
+        event.record(&mut fields);
+        let metadata = event.metadata();
+        if !is_audited(metadata, &fields.0) {
+            return;
+        }
+        let tx_id = fields.string("tx_id").or_else(|| inherited_tx_id(ctx.event_scope(event).into_iter().flatten()));
+        let (operation, meta) = convert(fields.0, metadata, None, tx_id);
+        self.append(operation, meta);
+    }
+}
+
+fn is_audited(metadata: &Metadata<'_>, fields: &Map<String, Value>) -> bool {
+    let target = metadata.target();
+    let by_target = target == AUDIT_TARGET
+        || target.strip_prefix(AUDIT_TARGET).is_some_and(|rest| rest.starts_with("::"));
+    by_target || fields.get(MARKER_FIELD).is_some_and(|v| v != &Value::Bool(false))
+}
+
+/// The `tx_id` of the innermost audited span in `scope` that has one.
+fn inherited_tx_id<'a, R: LookupSpan<'a> + 'a>(mut scope: impl Iterator<Item = SpanRef<'a, R>>) -> Option<String> {
+    scope.find_map(|span| {
+        let extensions = span.extensions();
+        let fields = extensions.get::<SpanFields>()?;
+        fields.0.get("tx_id").and_then(Value::as_str).map(str::to_owned)
+    })
+}
+
+/// The operation and metadata for an audited span (`span_name` set) or event.
+fn convert(
+    mut fields: Map<String, Value>,
+    metadata: &Metadata<'_>,
+    span_name: Option<&str>,
+    tx_id: Option<String>,
+) -> (Operation, EntryMeta) {
+    fields.remove(MARKER_FIELD);
+    fields.remove("tx_id");
+    let label = take_string(&mut fields, "outcome");
+    let outcome = match (label.as_deref(), take_string(&mut fields, "error")) {
+        (Some("success"), None) => Some(Outcome::Success),
+        (Some("rolled_back"), None) => Some(Outcome::RolledBack),
+        (Some("failure") | None, Some(error)) => Some(Outcome::Failure { error }),
+        (Some("failure"), None) => Some(Outcome::Failure { error: String::new() }),
+        // Contradictory or unknown: keep what was said as details.
+        (_, error) => {
+            fields.extend(label.map(|l| ("outcome".to_owned(), Value::String(l))));
+            fields.extend(error.map(|e| ("error".to_owned(), Value::String(e))));
+            None
+        }
+    };
+    let meta = EntryMeta { outcome, change: None, tx_id };
+
+    if let Some(operation) = typed_operation(&fields) {
+        return (operation, meta);
+    }
+    let kind = match fields.get("kind").and_then(Value::as_str) {
+        Some(kind) if is_valid_kind(kind) => {
+            let kind = kind.to_owned();
+            fields.remove("kind");
+            kind
+        }
+        _ if span_name.is_some() => "tracing.span".to_owned(),
+        _ => "tracing.event".to_owned(),
+    };
+    fields.insert("target".into(), Value::String(metadata.target().to_owned()));
+    fields.insert("level".into(), Value::String(metadata.level().to_string()));
+    if let Some(name) = span_name {
+        fields.insert("span".into(), Value::String(name.to_owned()));
+    }
+    (Operation::Custom { kind, details: Value::Object(fields) }, meta)
+}
+
+/// The typed operation named by the `op` field, if every field it needs is
+/// present and nothing else is.
+fn typed_operation(fields: &Map<String, Value>) -> Option<Operation> {
+    let path = |name: &str| fields.get(name).and_then(Value::as_str).map(PathBuf::from);
+    let (operation, used) = match fields.get("op")?.as_str()? {
+        "file_read" => (Operation::FileRead { path: path("path")? }, 2),
+        "file_write" => (Operation::FileWrite { path: path("path")? }, 2),
+        "file_delete" => (Operation::FileDelete { path: path("path")? }, 2),
+        "dir_create" => (Operation::DirCreate { path: path("path")? }, 2),
+        "file_move" => (Operation::FileMove { from: path("from")?, to: path("to")? }, 3),
+        "git_status_checked" => (Operation::GitStatusChecked { repo_path: path("repo_path")? }, 2),
+        "git_commit_created" => {
+            let message = fields.get("message")?.as_str()?.to_owned();
+            (Operation::GitCommitCreated { repo_path: path("repo_path")?, message }, 3)
+        }
+        _ => return None,
+    };
+    // Extra fields would be lost in the typed form; keep them in a Custom.
+    (fields.len() == used).then_some(operation)
+}
+
+fn take_string(fields: &mut Map<String, Value>, name: &str) -> Option<String> {
+    match fields.remove(name)? {
+        Value::String(s) => Some(s),
+        other => Some(other.to_string()),
+    }
+}
+
+/// Collects recorded field values as JSON.
+#[derive(Default)]
+struct FieldMap(Map<String, Value>);
+
+impl FieldMap {
+    fn string(&self, name: &str) -> Option<String> {
+        self.0.get(name).and_then(Value::as_str).map(str::to_owned)
+    }
+}
+
+impl Visit for FieldMap {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().into(), serde_json::Number::from_f64(value).map_or(Value::Null, Value::Number));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name().into(), format!("{value:?}").into());
+    }
+}
@@ -0,0 +1,97 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Legacy Log Migration — Unversioned NDJSON to Format Version 1.
+//!
+//! Logs written before entries carried a version and sequence number were
+//! chained by hashing `serde_json::to_string` of `{timestamp, prev_hash,
+//! operation}`. This module verifies such a log under its original rules
+//! and rewrites it as a fresh version-1 chain, preserving every timestamp
+//! and operation; a timestamp earlier than the one before it is clamped,
+//! with the original kept in `clamped_from`. A closing
+//! `Operation::LegacyMigrated` entry records the legacy head hash so the
+//! new chain stays anchored to the old one.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use chrono::{DateTime, Utc};
+use ring::digest::{Context, SHA256};
+use serde::{Deserialize, Serialize};
+
+use super::{hex, AuditLog, EntryMeta, IntegrityError, Operation, GENESIS_HASH};
+
+/// Entry layout used before format version 1.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LegacyEntry {
+    timestamp: DateTime<Utc>,
+    prev_hash: String,
+    operation: Operation,
+}
+
+impl LegacyEntry {
+    /// The legacy hash: SHA-256 over the compact JSON serialisation.
+    fn hash(&self) -> String {
+        let serialised = serde_json::to_string(self)
+            .expect("LegacyEntry must be serialisable to compute hash");
+        let mut ctx = Context::new(&SHA256);
+        ctx.update(serialised.as_bytes());
+        hex::encode(ctx.finish().as_ref())
+    }
+}
+
+/// Whether `line` parses as a pre-version-1 entry.
+pub(crate) fn is_legacy_line(line: &str) -> bool {
+    serde_json::from_str::<LegacyEntry>(line).is_ok()
+}
+
+/// Convert the legacy log at `src` into a new version-1 log at `dst`.
+///
+/// The legacy chain is verified first; a broken link or unparseable line
+/// aborts the migration before anything is written. `dst` must not exist.
+/// Returns the number of legacy entries migrated (the closing
+/// `LegacyMigrated` entry is not counted).
+pub fn migrate_legacy<P: AsRef<Path>, Q: AsRef<Path>>(
+    src: P,
+    dst: Q,
+) -> Result<usize, IntegrityError> {
+    let src = src.as_ref();
+    let dst = dst.as_ref();
+    if dst.exists() {
+        let message = format!("{} already exists", dst.display());
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, message).into());
+    }
+
+    let mut entries = Vec::new();
+    let mut prev_hash = GENESIS_HASH.to_owned();
+    for (line_idx, line) in BufReader::new(File::open(src)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() { continue; }
+
+        let entry: LegacyEntry = serde_json::from_str(&line).map_err(|e| {
+            IntegrityError::Deserialisation { line: line_idx + 1, cause: e.to_string() }
+        })?;
+        if !entries.is_empty() && entry.prev_hash != prev_hash {
+            return Err(IntegrityError::ChainBroken {
+                index: entries.len(),
+                expected: prev_hash,
+                found: entry.prev_hash,
+            });
+        }
+        prev_hash = entry.hash();
+        entries.push(entry);
+    }
+
+    let mut log = AuditLog::open(dst)?;
+    for entry in &entries {
+        log.append_at(entry.timestamp, entry.operation.clone(), EntryMeta::default())?;
+    }
+    log.append(Operation::LegacyMigrated {
+        source: src.to_path_buf(),
+        legacy_head: prev_hash,
+        entries: entries.len() as u64,
+    })?;
+
+    Ok(entries.len())
+}
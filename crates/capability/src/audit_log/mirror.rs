@@ -0,0 +1,418 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Mirrors — Extra Copies of the Chain Elsewhere.
+//!
+//! With paths in `AuditLogOptions::mirrors`, every record appended to the
+//! log is also appended, byte for byte, to each mirror file — typically on
+//! another mount, so losing one disk does not lose the log. A mirror is a
+//! single file holding the whole chain in the primary's storage format; it
+//! is not rotated into segments and has no index.
+//!
+//! LAGGING MIRRORS:
+//! A mirror is only written while it ends exactly where the log does. A
+//! mirror that is missing, behind, damaged or on a disk that failed a write
+//! is marked lagging and skipped — the append itself still succeeds — until
+//! [`reconcile`] (or `AuditLog::reconcile_mirrors`) repairs it.
+//!
+//! RECONCILING:
+//! [`reconcile`] walks the valid chain prefix of the primary and of each
+//! mirror, reports which copy has the longest one and where each copy
+//! diverges from it, and with `repair` appends the missing entries to any
+//! mirror that is a shorter prefix of the primary. Diverged or corrupted
+//! copies are reported, never overwritten.
+//!
+//! COMPACTED LOGS:
+//! Copies are compared by sequence number, each from where its chain
+//! starts: genesis, or the checkpoint stored beside it. A mirror is not
+//! compacted along with the log, so it may keep entries the log has moved
+//! to its archive; it only has to agree with the log where both hold
+//! entries, and at the checkpoint's head. A mirror started or rebuilt from
+//! a compacted log gets a copy of its checkpoint.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+
+use super::compact::{self, Checkpoint};
+use super::format::{self, Record, RecordReader};
+use super::{AuditLog, IntegrityError, LogEntry, LogFormat, LogHeader, GENESIS_HASH};
+
+/// How one copy of the log compares with the longest valid chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum CopyState {
+    /// Holds the longest valid chain.
+    InSync,
+    /// The file does not exist.
+    Missing,
+    /// A prefix of the longest chain, `behind` entries short.
+    Lagging { behind: u64 },
+    /// Disagrees with the longest chain from entry `at_seq` on.
+    Diverged { at_seq: u64 },
+    /// Was lagging or missing and had `added` entries appended from the
+    /// primary.
+    Repaired { added: u64 },
+}
+
+/// One copy of the log in a [`ReconcileReport`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CopyReport {
+    /// Path of the copy.
+    pub path: PathBuf,
+    /// Entries in the copy's valid chain prefix (before any repair).
+    pub valid_entries: u64,
+    /// Hash of the last entry of that prefix.
+    pub head_hash: String,
+    /// Why the chain stops before the end of the file, if it does.
+    pub problem: Option<String>,
+    /// Comparison with the longest valid chain.
+    pub state: CopyState,
+}
+
+/// Result of [`reconcile`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReconcileReport {
+    /// The primary log.
+    pub primary: CopyReport,
+    /// Each mirror, in the order given.
+    pub mirrors: Vec<CopyReport>,
+    /// Path of the copy with the longest valid chain; the primary on a tie.
+    pub longest: PathBuf,
+}
+
+impl ReconcileReport {
+    /// Whether every copy holds the longest chain (after any repair).
+    pub fn is_consistent(&self) -> bool {
+        std::iter::once(&self.primary)
+            .chain(&self.mirrors)
+            .all(|copy| matches!(copy.state, CopyState::InSync | CopyState::Repaired { .. }))
+    }
+}
+
+/// Compare the log at `primary` with each of `mirrors`; with `repair`,
+/// bring lagging and missing mirrors up to date from the primary.
+pub fn reconcile<P: AsRef<Path>>(primary: P, mirrors: &[PathBuf], repair: bool) -> Result<ReconcileReport, IntegrityError> {
+    let primary = primary.as_ref();
+    let primary_chain = Chain::of_log(primary)?;
+    let mirror_chains = mirrors.iter().map(|path| Chain::of_file(path)).collect::<Result<Vec<_>, _>>()?;
+
+    let reference = mirror_chains.iter()
+        .fold(&primary_chain, |best, chain| if chain.end() > best.end() { chain } else { best });
+
+    let mut reports = Vec::with_capacity(mirrors.len());
+    for chain in &mirror_chains {
+        let mut report = chain.report(reference);
+        // An empty mirror is rebuilt from wherever the primary starts.
+        let continues_primary = chain.hashes.is_empty()
+            || (chain.end() >= primary_chain.start_seq && chain.diverges_from(&primary_chain).is_none());
+        let repairable = matches!(report.state, CopyState::Missing | CopyState::Lagging { .. })
+            && chain.torn_only
+            && continues_primary
+            && chain.end() < primary_chain.end();
+        if repair && repairable {
+            let added = chain.repair_from(primary, &primary_chain)?;
+            report.state = if primary_chain.end() < reference.end() {
+                CopyState::Lagging { behind: reference.end() - primary_chain.end() }
+            } else {
+                CopyState::Repaired { added }
+            };
+        }
+        reports.push(report);
+    }
+    Ok(ReconcileReport { primary: primary_chain.report(reference), mirrors: reports, longest: reference.path.clone() })
+}
+
+/// The valid chain prefix of one copy.
+struct Chain {
+    path: PathBuf,
+    exists: bool,
+    /// Sequence number of the first entry: 0, or the checkpoint's count.
+    start_seq: u64,
+    /// `prev_hash` of the first entry: genesis, or the checkpoint's head.
+    start_hash: String,
+    /// Hashes of the entries from `start_seq` on.
+    hashes: Vec<String>,
+    /// Byte length of the valid prefix, header included.
+    valid_len: u64,
+    format: LogFormat,
+    problem: Option<String>,
+    /// Whether nothing but a torn final record follows the valid prefix.
+    torn_only: bool,
+}
+
+impl Chain {
+    /// No entries yet, starting from the checkpoint beside `path` if there
+    /// is one that applies.
+    fn empty(path: &Path, format: LogFormat) -> Result<Self, IntegrityError> {
+        let (start_seq, start_hash) = match compact::active_checkpoint(path)? {
+            Some(checkpoint) => (checkpoint.entries, checkpoint.head_hash),
+            None => (0, GENESIS_HASH.to_owned()),
+        };
+        Ok(Chain {
+            path: path.to_path_buf(),
+            exists: path.exists(),
+            start_seq,
+            start_hash,
+            hashes: Vec::new(),
+            valid_len: 0,
+            format,
+            problem: None,
+            torn_only: true,
+        })
+    }
+
+    /// Sequence number the next entry would have.
+    fn end(&self) -> u64 {
+        self.start_seq + self.hashes.len() as u64
+    }
+
+    /// Hash of the last entry, or what the first one chains to.
+    fn head(&self) -> &str {
+        self.hashes.last().map_or(&self.start_hash, String::as_str)
+    }
+
+    /// Hash of entry `seq`, if this chain holds it or starts right after it.
+    fn hash_at(&self, seq: u64) -> Option<&str> {
+        if seq + 1 == self.start_seq {
+            return Some(&self.start_hash);
+        }
+        let index = usize::try_from(seq.checked_sub(self.start_seq)?).ok()?;
+        self.hashes.get(index).map(String::as_str)
+    }
+
+    /// First sequence number at which both chains know the hash and the
+    /// hashes differ.
+    fn diverges_from(&self, other: &Chain) -> Option<u64> {
+        let from = self.start_seq.max(other.start_seq).saturating_sub(1);
+        (from..self.end().min(other.end()))
+            .find(|&seq| matches!((self.hash_at(seq), other.hash_at(seq)), (Some(a), Some(b)) if a != b))
+    }
+
+    /// The primary, read across its sealed segments.
+    fn of_log(path: &Path) -> Result<Self, IntegrityError> {
+        let format = LogFormat::detect(path)?.unwrap_or_default();
+        let mut chain = Self::empty(path, format)?;
+        if !chain.exists {
+            return Ok(chain);
+        }
+        for entry in AuditLog::entries(path)? {
+            match entry {
+                Ok(entry) if chain.push(&entry) => {}
+                Ok(entry) => {
+                    chain.problem = Some(format!("entry {} does not link to the chain", entry.seq));
+                    break;
+                }
+                Err(e) => {
+                    chain.problem = Some(e.to_string());
+                    break;
+                }
+            }
+        }
+        Ok(chain)
+    }
+
+    /// A single mirror file.
+    fn of_file(path: &Path) -> Result<Self, IntegrityError> {
+        let mut chain = Self::empty(path, LogFormat::default())?;
+        let mut reader = match RecordReader::open(path) {
+            Ok(reader) => reader,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(chain),
+            Err(e) => return Err(e.into()),
+        };
+        chain.format = reader.format();
+        chain.valid_len = reader.offset;
+        loop {
+            match reader.next_record() {
+                Ok(None) => break,
+                Ok(Some(Record::Blank)) => chain.valid_len = reader.offset + reader.len,
+                Ok(Some(Record::Torn)) => {
+                    chain.problem = Some(format!("torn final record at byte {}", reader.offset));
+                    break;
+                }
+                Ok(Some(Record::Entry(entry))) if chain.push(&entry) => chain.valid_len = reader.offset + reader.len,
+                Ok(Some(Record::Entry(entry))) => {
+                    chain.problem = Some(format!("entry {} does not link to the chain", entry.seq));
+                    chain.torn_only = false;
+                    break;
+                }
+                Err(IntegrityError::Io(e)) => return Err(e.into()),
+                Err(e) => {
+                    chain.problem = Some(e.to_string());
+                    chain.torn_only = false;
+                    break;
+                }
+            }
+        }
+        Ok(chain)
+    }
+
+    /// Extend the chain with `entry` if it links; `false` if it does not.
+    fn push(&mut self, entry: &LogEntry) -> bool {
+        if entry.seq != self.end() || entry.prev_hash != self.head() {
+            return false;
+        }
+        self.hashes.push(entry.hash());
+        true
+    }
+
+    fn report(&self, reference: &Chain) -> CopyReport {
+        let state = match self.diverges_from(reference) {
+            _ if !self.exists => CopyState::Missing,
+            Some(at_seq) => CopyState::Diverged { at_seq },
+            None if self.end() < reference.end() => CopyState::Lagging { behind: reference.end() - self.end() },
+            None => CopyState::InSync,
+        };
+        CopyReport {
+            path: self.path.clone(),
+            valid_entries: self.hashes.len() as u64,
+            head_hash: self.head().to_owned(),
+            problem: self.problem.clone(),
+            state,
+        }
+    }
+
+    /// Cut the mirror back to its valid prefix and append the primary's
+    /// later entries. Returns the number appended.
+    fn repair_from(&self, primary: &Path, primary_chain: &Chain) -> Result<u64, IntegrityError> {
+        // A mirror with no entries yet is rewritten in the primary's format,
+        // starting where the primary does.
+        let (format, keep, from) = if self.hashes.is_empty() {
+            (primary_chain.format, 0, primary_chain.start_seq)
+        } else {
+            (self.format, self.valid_len, self.end())
+        };
+        if self.hashes.is_empty() {
+            match compact::active_checkpoint(primary)? {
+                Some(checkpoint) => checkpoint.store(&self.path)?,
+                None => remove_checkpoint(&self.path)?,
+            }
+        }
+        OpenOptions::new().create(true).write(true).truncate(false).open(&self.path)?.set_len(keep)?;
+        let mut out = io::BufWriter::new(OpenOptions::new().append(true).open(&self.path)?);
+        let entries = AuditLog::entries(primary)?;
+        if keep == 0 {
+            out.write_all(&format::prelude(format, entries.reader.header())?)?;
+        }
+        let mut added = 0;
+        for entry in entries {
+            let entry = entry?;
+            if entry.seq < from {
+                continue;
+            }
+            out.write_all(&format::encode_record(&entry, format)?)?;
+            added += 1;
+        }
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        Ok(added)
+    }
+}
+
+/// Remove a checkpoint left beside a mirror that is being rebuilt from
+/// genesis.
+fn remove_checkpoint(mirror: &Path) -> io::Result<()> {
+    match std::fs::remove_file(Checkpoint::path_for(mirror)) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
+}
+
+/// A mirror file an open log appends to.
+pub(crate) struct Mirror {
+    path: PathBuf,
+    /// `None` while the mirror is lagging.
+    file: Option<File>,
+}
+
+impl Mirror {
+    /// Open the mirror at `path` for a log in `format` whose chain ends at
+    /// (`next_seq`, `last_hash`), compacted at `checkpoint` if any. The
+    /// mirror is lagging unless its own chain ends at exactly the same
+    /// place. A new mirror starts with `header`.
+    pub(crate) fn open(
+        path: &Path,
+        format: LogFormat,
+        header: Option<&LogHeader>,
+        next_seq: u64,
+        last_hash: &str,
+        checkpoint: Option<&Checkpoint>,
+    ) -> Self {
+        let file = Self::open_in_sync(path, format, header, (next_seq, last_hash), checkpoint).ok().flatten();
+        Mirror { path: path.to_path_buf(), file }
+    }
+
+    fn open_in_sync(
+        path: &Path,
+        format: LogFormat,
+        header: Option<&LogHeader>,
+        (next_seq, last_hash): (u64, &str),
+        checkpoint: Option<&Checkpoint>,
+    ) -> Result<Option<File>, IntegrityError> {
+        let chain = Chain::of_file(path)?;
+        // Missing or empty: can start along with a log that has no entries
+        // beyond genesis or its checkpoint.
+        let log_is_empty = match checkpoint {
+            Some(checkpoint) => (checkpoint.entries, checkpoint.head_hash.as_str()) == (next_seq, last_hash),
+            None => next_seq == 0,
+        };
+        let fresh = log_is_empty && chain.hashes.is_empty() && chain.problem.is_none()
+            && (chain.valid_len == 0 || chain.format == format);
+        let in_sync = chain.exists
+            && chain.format == format
+            && chain.problem.is_none()
+            && chain.end() == next_seq
+            && chain.head() == last_hash;
+        if !fresh && !in_sync {
+            return Ok(None);
+        }
+        if fresh {
+            match checkpoint {
+                Some(checkpoint) => checkpoint.store(path)?,
+                None => remove_checkpoint(path)?,
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let prelude = format::prelude(format, header)?;
+        if file.metadata()?.len() == 0 && !prelude.is_empty() {
+            (&file).write_all(&prelude)?;
+            file.sync_all()?;
+        }
+        Ok(Some(file))
+    }
+
+    /// Append `record`, `fsync`-ing it if `sync`. A failure marks the
+    /// mirror lagging.
+    pub(crate) fn write(&mut self, record: &[u8], sync: bool) {
+        let Some(file) = &self.file else { return };
+        let written = (|| {
+            (&*file).write_all(record)?;
+            if sync { file.sync_data()?; }
+            io::Result::Ok(())
+        })();
+        if written.is_err() {
+            self.file = None;
+        }
+    }
+
+    /// `fsync` the mirror. A failure marks it lagging.
+    pub(crate) fn flush(&mut self) {
+        if self.file.as_ref().is_some_and(|file| file.sync_data().is_err()) {
+            self.file = None;
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn is_lagging(&self) -> bool {
+        self.file.is_none()
+    }
+}
+
+impl Drop for Mirror {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
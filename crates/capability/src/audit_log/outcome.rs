@@ -0,0 +1,102 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Outcomes and Changes — What Happened, and to Which Bytes.
+//!
+//! An `Operation` names an action; these types record its result. Both are
+//! optional, entry-level fields attached through [`EntryMeta`], so entries
+//! written without them hash exactly as before.
+//!
+//! CHANGE SEMANTICS:
+//! `Change::Content` holds SHA-256 digests of file content, `None` meaning
+//! "no file". For each operation they describe the path the bytes end up at:
+//!
+//! | Operation    | `before`                           | `after`                  |
+//! |--------------|------------------------------------|--------------------------|
+//! | `FileWrite`  | old content of `path`              | new content of `path`    |
+//! | `FileDelete` | content of `path` that was removed | `None`                   |
+//! | `FileMove`   | old content of `to` (overwritten)  | content moved into `to`  |
+//!
+//! `Change::Git` holds the object ids a git action moved a ref between.
+//! Together they let a reviewer establish from the log alone exactly which
+//! bytes the tool destroyed or created.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use ring::digest::{Context, SHA256};
+use serde::{Deserialize, Serialize};
+
+use super::hex;
+
+/// Whether an audited operation took effect.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Outcome {
+    /// The operation completed.
+    Success,
+    /// The operation failed with `error` and left no effect behind.
+    Failure { error: String },
+    /// The operation completed but was later undone.
+    RolledBack,
+}
+
+/// The state an operation moved something between.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Change {
+    /// SHA-256 hex digests of file content; see the module docs.
+    Content { before: Option<String>, after: Option<String> },
+    /// Git object ids before and after, `None` for an unborn ref.
+    Git { old_oid: Option<String>, new_oid: Option<String> },
+}
+
+/// Optional per-entry fields supplied to [`AuditLog::append_with`](super::AuditLog::append_with).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EntryMeta {
+    /// Result of the operation.
+    pub outcome: Option<Outcome>,
+    /// Content or object ids before and after the operation.
+    pub change: Option<Change>,
+    /// Transaction the operation belongs to.
+    pub tx_id: Option<String>,
+}
+
+impl EntryMeta {
+    /// Metadata recording `outcome`.
+    pub fn outcome(outcome: Outcome) -> Self {
+        Self { outcome: Some(outcome), ..Self::default() }
+    }
+
+    /// Add `change` to the metadata.
+    pub fn with_change(mut self, change: Change) -> Self {
+        self.change = Some(change);
+        self
+    }
+
+    /// Tag the entry as part of transaction `tx_id`.
+    pub fn with_tx_id(mut self, tx_id: impl Into<String>) -> Self {
+        self.tx_id = Some(tx_id.into());
+        self
+    }
+}
+
+/// SHA-256 hex digest of the file at `path`, or `None` if there is no file.
+///
+/// Call it before and after an operation to build a [`Change::Content`].
+pub fn content_hash<P: AsRef<Path>>(path: P) -> io::Result<Option<String>> {
+    let mut file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut ctx = Context::new(&SHA256);
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => ctx.update(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Some(hex::encode(ctx.finish().as_ref())))
+}
@@ -0,0 +1,75 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Queries — Reconstructing Runs From a Log.
+//!
+//! Read-only views over the entries of a (possibly segmented) log. Queries
+//! read entries in order but do not check chain links; run
+//! `AuditLog::verify` first when the answer has to be trusted.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use super::{AuditLog, IntegrityError, LogEntry, Operation};
+
+/// Every entry of the log at `path` whose context carries `correlation_id`,
+/// in log order.
+pub fn correlated_entries<P: AsRef<Path>>(path: P, correlation_id: &str) -> Result<Vec<LogEntry>, IntegrityError> {
+    AuditLog::entries(path)?
+        .filter(|entry| entry.as_ref().map_or(true, |e| e.correlation_id() == Some(correlation_id)))
+        .collect()
+}
+
+/// The entries of the log at `path` grouped by correlation id, each group
+/// in log order. Entries without a correlation id are left out.
+pub fn group_by_correlation<P: AsRef<Path>>(path: P) -> Result<BTreeMap<String, Vec<LogEntry>>, IntegrityError> {
+    let mut groups: BTreeMap<String, Vec<LogEntry>> = BTreeMap::new();
+    for entry in AuditLog::entries(path)? {
+        let entry = entry?;
+        if let Some(id) = entry.correlation_id() {
+            groups.entry(id.to_owned()).or_default().push(entry);
+        }
+    }
+    Ok(groups)
+}
+
+/// A transaction that began but never logged a commit or rollback.
+#[derive(Debug, Clone)]
+pub struct IncompleteTransaction {
+    /// Transaction id.
+    pub tx_id: String,
+    /// The `TransactionBegin` entry.
+    pub begin: LogEntry,
+    /// Operations the transaction planned to apply.
+    pub planned_ops: Vec<Operation>,
+    /// Entries for operations logged as part of the transaction, in order.
+    pub applied: Vec<LogEntry>,
+}
+
+/// Transactions in the log at `path` that began but were neither committed
+/// nor rolled back — typically because the process crashed mid-commit.
+///
+/// Comparing `planned_ops` with `applied` shows how far each one got.
+pub fn incomplete_transactions<P: AsRef<Path>>(path: P) -> Result<Vec<IncompleteTransaction>, IntegrityError> {
+    let mut open: BTreeMap<String, IncompleteTransaction> = BTreeMap::new();
+    for entry in AuditLog::entries(path)? {
+        let entry = entry?;
+        match &entry.operation {
+            Operation::TransactionBegin { tx_id, planned_ops } => {
+                let planned_ops = planned_ops.clone();
+                open.insert(tx_id.clone(), IncompleteTransaction { tx_id: tx_id.clone(), begin: entry, planned_ops, applied: Vec::new() });
+            }
+            Operation::TransactionCommitted { tx_id, .. } | Operation::TransactionRolledBack { tx_id, .. } => {
+                open.remove(tx_id);
+            }
+            _ => {
+                if let Some(tx) = entry.tx_id.as_ref().and_then(|id| open.get_mut(id)) {
+                    tx.applied.push(entry);
+                }
+            }
+        }
+    }
+    let mut incomplete: Vec<IncompleteTransaction> = open.into_values().collect();
+    incomplete.sort_by_key(|tx| tx.begin.seq);
+    Ok(incomplete)
+}
@@ -0,0 +1,344 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Redaction — Shareable Logs Without Paths or Messages.
+//!
+//! A [`Redactor`] rewrites the path and message fields of an `Operation`
+//! (every `PathBuf`, commit messages and rollback errors, including those
+//! inside a transaction's planned operations) so a log can be shared
+//! without revealing directory layout or commit contents. Each kind of
+//! field is kept, replaced by a salted keyed hash, or truncated:
+//!
+//! | Policy          | Paths                               | Messages                  |
+//! |-----------------|-------------------------------------|---------------------------|
+//! | `KeyedHash`     | `hmac-sha256:<32 hex>`              | `hmac-sha256:<32 hex>`    |
+//! | `Truncate(n)`   | last `n` components, after `…/`     | first `n` characters, `…` |
+//!
+//! Keyed hashes are HMAC-SHA256 under a secret salt, so equal values still
+//! compare equal across entries, but they cannot be guessed by hashing
+//! candidate paths without the salt. Custom operation details and sealed
+//! (`Encrypted`) entries are left as they are; a sealed entry is bound to
+//! its position in the original chain, so it no longer opens in a copy.
+//!
+//! WHEN:
+//! Set `AuditLogOptions::redaction` to redact at write time; the original
+//! values are then never stored. Or keep the full log and produce a
+//! redacted copy to share with [`redact_log`].
+//!
+//! PROOF:
+//! `redact_log` verifies the original and returns a [`RedactionProof`]
+//! pairing each original entry hash with the hash of its redacted copy.
+//! The recipient checks the copy against the proof with
+//! [`RedactionProof::check_redacted`], which ties every entry they hold to
+//! an entry of the original chain and to its head hash (compare it with an
+//! anchored head). Whoever holds the original and the salt can confirm the
+//! pairing entry by entry with [`RedactionProof::check_original`], which
+//! redacts the original again and must reproduce the copy exactly.
+//!
+//! COMPACTED LOGS:
+//! The copy of a compacted log keeps the original's sequence numbers, and
+//! its first entry chains to the same checkpoint, which is stored beside
+//! the copy. A checkpoint holds only counts and hashes, so it is shared
+//! as it is.
+
+use std::fmt;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use ring::digest::{digest, SHA256};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+
+use super::{compact, hex, AuditLog, AuditLogOptions, EntryMeta, IntegrityError, LogEntry, Operation, GENESIS_HASH};
+
+/// Version of the [`RedactionProof`] layout.
+const PROOF_VERSION: u8 = 1;
+
+/// How one kind of field is redacted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldPolicy {
+    /// Leave the field as it is.
+    Keep,
+    /// Replace the field with a salted keyed hash.
+    KeyedHash,
+    /// Keep only the last `n` path components, or the first `n` characters
+    /// of a message.
+    Truncate(usize),
+}
+
+/// Rewrites the path and message fields of operations; see the module docs.
+#[derive(Clone)]
+pub struct Redactor {
+    salt: [u8; 32],
+    paths: FieldPolicy,
+    messages: FieldPolicy,
+}
+
+impl Redactor {
+    /// A redactor that replaces paths and messages with keyed hashes
+    /// under `salt`.
+    pub fn new(salt: [u8; 32]) -> Self {
+        Self { salt, paths: FieldPolicy::KeyedHash, messages: FieldPolicy::KeyedHash }
+    }
+
+    /// A redactor with a fresh random salt.
+    pub fn generate() -> io::Result<Self> {
+        let mut salt = [0u8; 32];
+        SystemRandom::new().fill(&mut salt).map_err(|_| io::Error::other("no randomness available for a salt"))?;
+        Ok(Self::new(salt))
+    }
+
+    /// A redactor using the salt in 64 lowercase hex digits.
+    pub fn from_hex(text: &str) -> io::Result<Self> {
+        hex::decode(text.trim())
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .map(Self::new)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "redaction salt must be 64 hex digits"))
+    }
+
+    /// The salt as 64 lowercase hex digits.
+    pub fn to_hex(&self) -> String {
+        hex::encode(&self.salt)
+    }
+
+    /// Redact paths with `policy`.
+    pub fn with_paths(mut self, policy: FieldPolicy) -> Self {
+        self.paths = policy;
+        self
+    }
+
+    /// Redact messages with `policy`.
+    pub fn with_messages(mut self, policy: FieldPolicy) -> Self {
+        self.messages = policy;
+        self
+    }
+
+    /// SHA-256 of the salt, recorded in proofs to identify it without
+    /// revealing it.
+    pub fn fingerprint(&self) -> String {
+        hex::encode(digest(&SHA256, &self.salt).as_ref())
+    }
+
+    /// `operation` with its path and message fields redacted.
+    pub fn redact(&self, operation: &Operation) -> Operation {
+        let p = |path: &PathBuf| self.path(path);
+        let m = |message: &String| self.message(message);
+        match operation {
+            Operation::FileRead { path } => Operation::FileRead { path: p(path) },
+            Operation::FileWrite { path } => Operation::FileWrite { path: p(path) },
+            Operation::FileMove { from, to } => Operation::FileMove { from: p(from), to: p(to) },
+            Operation::FileDelete { path } => Operation::FileDelete { path: p(path) },
+            Operation::CapabilityCreated { root } => Operation::CapabilityCreated { root: p(root) },
+            Operation::CapabilityResolved { relative, canonical } => {
+                Operation::CapabilityResolved { relative: p(relative), canonical: p(canonical) }
+            }
+            Operation::GitStatusChecked { repo_path } => Operation::GitStatusChecked { repo_path: p(repo_path) },
+            Operation::GitCommitCreated { repo_path, message } => {
+                Operation::GitCommitCreated { repo_path: p(repo_path), message: m(message) }
+            }
+            Operation::RecoveredFromCrash { quarantine, offset, bytes } => {
+                Operation::RecoveredFromCrash { quarantine: p(quarantine), offset: *offset, bytes: *bytes }
+            }
+            Operation::LegacyMigrated { source, legacy_head, entries } => {
+                Operation::LegacyMigrated { source: p(source), legacy_head: legacy_head.clone(), entries: *entries }
+            }
+            Operation::DirCreate { path } => Operation::DirCreate { path: p(path) },
+            Operation::TransactionBegin { tx_id, planned_ops } => Operation::TransactionBegin {
+                tx_id: tx_id.clone(),
+                planned_ops: planned_ops.iter().map(|op| self.redact(op)).collect(),
+            },
+            Operation::TransactionRolledBack { tx_id, applied, error } => {
+                Operation::TransactionRolledBack { tx_id: tx_id.clone(), applied: *applied, error: m(error) }
+            }
+            Operation::Custom { .. }
+            | Operation::TransactionCommitted { .. }
+            | Operation::Encrypted { .. }
+            | Operation::HashAlgorithmChanged { .. } => operation.clone(),
+        }
+    }
+
+    fn path(&self, path: &Path) -> PathBuf {
+        match self.paths {
+            FieldPolicy::Keep => path.to_path_buf(),
+            FieldPolicy::KeyedHash => PathBuf::from(self.keyed_hash(path.to_string_lossy().as_bytes())),
+            FieldPolicy::Truncate(keep) => {
+                let components: Vec<Component> = path.components().collect();
+                if components.len() <= keep { return path.to_path_buf(); }
+                let tail: PathBuf = components[components.len() - keep..].iter().collect();
+                Path::new("…").join(tail)
+            }
+        }
+    }
+
+    fn message(&self, message: &str) -> String {
+        match self.messages {
+            FieldPolicy::Keep => message.to_owned(),
+            FieldPolicy::KeyedHash => self.keyed_hash(message.as_bytes()),
+            FieldPolicy::Truncate(keep) if message.chars().count() <= keep => message.to_owned(),
+            FieldPolicy::Truncate(keep) => message.chars().take(keep).chain(['…']).collect(),
+        }
+    }
+
+    fn keyed_hash(&self, value: &[u8]) -> String {
+        let tag = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, &self.salt), value);
+        format!("hmac-sha256:{}", hex::encode(&tag.as_ref()[..16]))
+    }
+
+    /// The redacted copy of `entry`, chained to `prev_hash`.
+    fn redact_entry(&self, entry: &LogEntry, prev_hash: String) -> LogEntry {
+        LogEntry { prev_hash, operation: self.redact(&entry.operation), ..entry.clone() }
+    }
+}
+
+impl fmt::Debug for Redactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Redactor")
+            .field("paths", &self.paths)
+            .field("messages", &self.messages)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Evidence that a redacted log was derived entry by entry from an
+/// original chain; see the module docs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RedactionProof {
+    /// Layout version of the proof.
+    pub version: u8,
+    /// SHA-256 of the salt the copy was redacted with.
+    pub salt_fingerprint: String,
+    /// Hash of the original log's last entry.
+    pub original_head: String,
+    /// Hash of the redacted log's last entry.
+    pub redacted_head: String,
+    /// One link per entry, in order.
+    pub links: Vec<ProofLink>,
+}
+
+/// The original and redacted hashes of one entry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofLink {
+    /// Sequence number, the same in both logs.
+    pub seq: u64,
+    /// Hash of the original entry.
+    pub original: String,
+    /// Hash of the redacted entry.
+    pub redacted: String,
+}
+
+impl RedactionProof {
+    /// Check that the log at `redacted` is intact and is exactly the copy
+    /// this proof describes. Needs neither the original nor the salt.
+    pub fn check_redacted<P: AsRef<Path>>(&self, redacted: P) -> Result<(), IntegrityError> {
+        let redacted = redacted.as_ref();
+        AuditLog::verify(redacted)?;
+        let mut count = 0;
+        for entry in AuditLog::entries(redacted)? {
+            let entry = entry?;
+            let link = self.link(count, entry.seq)?;
+            if entry.hash() != link.redacted {
+                return Err(mismatch(entry.seq, "redacted"));
+            }
+            count += 1;
+        }
+        self.check_end(count)
+    }
+
+    /// Check that redacting the log at `original` with `redactor`
+    /// reproduces every redacted hash in this proof, and that the original
+    /// is intact and ends at `original_head`.
+    pub fn check_original<P: AsRef<Path>>(&self, original: P, redactor: &Redactor) -> Result<(), IntegrityError> {
+        let original = original.as_ref();
+        if redactor.fingerprint() != self.salt_fingerprint {
+            return Err(mismatch(0, "salt_fingerprint"));
+        }
+        AuditLog::verify(original)?;
+        let mut prev = None;
+        let mut count = 0;
+        for entry in AuditLog::entries(original)? {
+            let entry = entry?;
+            let link = self.link(count, entry.seq)?;
+            if entry.hash() != link.original {
+                return Err(mismatch(entry.seq, "original"));
+            }
+            // The copy starts where the original does: at genesis or at
+            // its checkpoint.
+            let redacted = redactor.redact_entry(&entry, prev.unwrap_or_else(|| entry.prev_hash.clone())).hash();
+            prev = Some(redacted.clone());
+            if redacted != link.redacted {
+                return Err(mismatch(entry.seq, "redacted"));
+            }
+            count += 1;
+        }
+        self.check_end(count)
+    }
+
+    fn link(&self, position: usize, seq: u64) -> Result<&ProofLink, IntegrityError> {
+        match self.links.get(position) {
+            Some(link) if link.seq == seq => Ok(link),
+            _ => Err(mismatch(seq, "seq")),
+        }
+    }
+
+    fn check_end(&self, count: usize) -> Result<(), IntegrityError> {
+        if count != self.links.len() {
+            return Err(mismatch(count as u64, "entries"));
+        }
+        let last = self.links.last();
+        if last.map_or(GENESIS_HASH, |l| l.original.as_str()) != self.original_head {
+            return Err(mismatch(count as u64, "original_head"));
+        }
+        if last.map_or(GENESIS_HASH, |l| l.redacted.as_str()) != self.redacted_head {
+            return Err(mismatch(count as u64, "redacted_head"));
+        }
+        Ok(())
+    }
+}
+
+fn mismatch(seq: u64, field: &'static str) -> IntegrityError {
+    IntegrityError::ProofMismatch { seq, field }
+}
+
+/// Write a redacted copy of the log at `src` to a new log at `dst`.
+///
+/// The original is verified first. Each entry keeps its sequence number,
+/// timestamp, context and metadata; only its operation is redacted, and the
+/// copy is re-chained from the same start: genesis, or the checkpoint of a
+/// compacted original, which is stored beside `dst`. `dst` must not exist.
+pub fn redact_log<P: AsRef<Path>, Q: AsRef<Path>>(
+    src: P,
+    dst: Q,
+    redactor: &Redactor,
+) -> Result<RedactionProof, IntegrityError> {
+    let (src, dst) = (src.as_ref(), dst.as_ref());
+    if dst.exists() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", dst.display())).into());
+    }
+    AuditLog::verify(src)?;
+    if let Some(checkpoint) = compact::active_checkpoint(src)? {
+        checkpoint.store(dst)?;
+    }
+
+    let entries = AuditLog::entries(src)?;
+    let hash_algorithm = entries.reader.header().map(|h| h.hash_algorithm).unwrap_or_default();
+    let mut log = AuditLog::open_with(dst, AuditLogOptions { hash_algorithm, ..AuditLogOptions::default() })?;
+    let mut links = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let expected = redactor.redact_entry(&entry, log.last_hash.clone());
+        log.context = entry.context.clone();
+        let meta = EntryMeta { outcome: entry.outcome.clone(), change: entry.change.clone(), tx_id: entry.tx_id.clone() };
+        log.write_entry(entry.timestamp, entry.clamped_from, expected.operation.clone(), meta)?;
+        debug_assert_eq!(log.last_hash, expected.hash());
+        links.push(ProofLink { seq: entry.seq, original: entry.hash(), redacted: log.last_hash.clone() });
+    }
+    log.flush()?;
+
+    Ok(RedactionProof {
+        version: PROOF_VERSION,
+        salt_fingerprint: redactor.fingerprint(),
+        original_head: links.last().map_or_else(|| GENESIS_HASH.to_owned(), |l| l.original.clone()),
+        redacted_head: log.last_hash.clone(),
+        links,
+    })
+}
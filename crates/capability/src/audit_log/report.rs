@@ -0,0 +1,376 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Verification Reports — Every Problem in One Pass.
+//!
+//! `AuditLog::verify` stops at the first broken link, which is the right
+//! answer for "can this log be trusted?" but not for "how badly is it
+//! damaged, and what is still good?". A `VerifyReport` scans every record
+//! of every file to the end and lists each problem found, together with the
+//! ranges of entries that are still internally consistent.
+//!
+//! SCAN RULES:
+//! - An unparseable record ends the current range and stands in for one
+//!   entry: the next entry is expected at the following sequence number,
+//!   but its link cannot be checked.
+//! - An entry whose hash was already seen is a duplicate and is skipped
+//!   without disturbing the range it interrupts.
+//! - A broken link, a skipped sequence number (gap) or a repeated or
+//!   decreasing one (out of order) ends the current range; a new range
+//!   starts at the offending entry.
+//! - Timestamp regressions, clamped timestamps, invalid custom details and
+//!   entries or headers using another hash algorithm than the one in effect
+//!   are reported but do not end a range, since the chain itself is intact.
+//! - A sealed segment missing from disk is reported and the scan resumes
+//!   from the manifest's record of where it ended.
+//! - A compacted log is scanned from its checkpoint; a checkpoint whose
+//!   signature does not check is reported against the checkpoint file.
+//!
+//! The report is plain data and serialises to JSON for the TUI and CI.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{compact, Checkpoint, HashAlgorithm, IntegrityError, LogEntry, Operation, SegmentInfo, SegmentManifest, VerifyOptions};
+use super::format::{Record, RecordReader};
+use super::segment::{file_name, sibling};
+
+/// Everything a full scan of a log found.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerifyReport {
+    /// Entries that could be read, duplicates included.
+    pub entries: u64,
+    /// Problems, in the order they were found.
+    pub findings: Vec<Finding>,
+    /// Maximal runs of entries that chain correctly, in log order.
+    pub consistent_ranges: Vec<ConsistentRange>,
+}
+
+impl VerifyReport {
+    /// Whether the scan found no problems at all.
+    pub fn is_clean(&self) -> bool {
+        self.findings.is_empty()
+    }
+}
+
+/// One problem and where it was found.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Finding {
+    /// File name of the segment or active file, relative to the log's directory.
+    pub file: String,
+    /// 1-based record number in the file (the line number for NDJSON), or 0
+    /// for a problem with the file as a whole.
+    pub record: usize,
+    /// Byte offset of the record in the file.
+    pub offset: u64,
+    /// Sequence number of the entry, if the record could be read.
+    pub seq: Option<u64>,
+    /// What is wrong.
+    #[serde(flatten)]
+    pub problem: Problem,
+}
+
+/// The kinds of problem a [`VerifyReport`] lists.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "problem", rename_all = "snake_case")]
+pub enum Problem {
+    /// The entry's `prev_hash` is not the hash of the entry before it.
+    BrokenLink { expected: String, found: String },
+    /// The record could not be decoded into an entry.
+    Unparseable { cause: String },
+    /// Sequence numbers were skipped: entries are missing.
+    Gap { expected_seq: u64, found_seq: u64 },
+    /// The sequence number repeats or goes backwards.
+    OutOfOrder { expected_seq: u64, found_seq: u64 },
+    /// The entry is an exact copy of one seen earlier.
+    Duplicate { first_file: String, first_record: usize },
+    /// The entry's timestamp is earlier than the previous entry's.
+    TimestampRegression { previous: DateTime<Utc>, found: DateTime<Utc> },
+    /// The writer's clock was behind the previous entry and the timestamp
+    /// was clamped; `clock` is what it read.
+    ClampedTimestamp { clock: DateTime<Utc>, recorded: DateTime<Utc> },
+    /// A custom operation's details fail their schema.
+    InvalidCustomDetails { kind: String, reason: String },
+    /// The file ends in a partially written record.
+    TornTail { bytes: u64 },
+    /// A sealed segment does not match its manifest record in `field`.
+    ManifestMismatch { segment: u32, field: String },
+    /// A sealed segment listed in the manifest is not on disk.
+    MissingFile,
+    /// The file could not be read past this point.
+    Unreadable { cause: String },
+    /// The compaction checkpoint's signature does not check.
+    InvalidCheckpoint { cause: String },
+    /// An entry, or a file's header, uses another hash algorithm than the
+    /// header and migration entries before it call for.
+    HashAlgorithmMismatch { expected: HashAlgorithm, found: HashAlgorithm },
+}
+
+/// A run of consecutive entries whose links and sequence numbers all check.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConsistentRange {
+    /// Sequence number of the first entry.
+    pub first_seq: u64,
+    /// Sequence number of the last entry.
+    pub last_seq: u64,
+    /// Number of entries in the range.
+    pub entries: u64,
+    /// `prev_hash` of the first entry.
+    pub start_hash: String,
+    /// Hash of the last entry.
+    pub head_hash: String,
+}
+
+/// Scan the log at `path` to the end; see the module docs.
+pub(crate) fn scan(path: &Path, options: &VerifyOptions) -> Result<VerifyReport, IntegrityError> {
+    let mut scanner = Scanner::new(options);
+    if let Some(checkpoint) = compact::active_checkpoint(path)? {
+        scanner.checkpoint(path, checkpoint);
+    }
+    match SegmentManifest::load(path)? {
+        Some(manifest) => {
+            for info in &manifest.segments {
+                scanner.segment(&sibling(path, &info.file), info);
+            }
+            if path.exists() {
+                scanner.file(path);
+            }
+        }
+        None => {
+            if !path.exists() {
+                return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} not found", path.display())).into());
+            }
+            scanner.file(path);
+        }
+    }
+    Ok(scanner.finish())
+}
+
+/// Running state of a report scan.
+struct Scanner<'a> {
+    options: &'a VerifyOptions,
+    report: VerifyReport,
+    /// The range being extended.
+    range: Option<ConsistentRange>,
+    /// Expected `prev_hash` of the next entry, if known.
+    prev_hash: Option<String>,
+    /// Expected sequence number of the next entry, if known.
+    next_seq: Option<u64>,
+    /// Timestamp of the last entry read.
+    last_timestamp: Option<DateTime<Utc>>,
+    /// Hash algorithm of the next entry, if known.
+    hash_alg: Option<HashAlgorithm>,
+    /// Where each entry hash was first seen.
+    seen: HashMap<String, (String, usize)>,
+}
+
+/// What the scan saw in one file, for comparison with the manifest.
+#[derive(Default)]
+struct FileSummary {
+    entries: u64,
+    first_prev_hash: Option<String>,
+    head_hash: Option<String>,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(options: &'a VerifyOptions) -> Self {
+        Self {
+            options,
+            report: VerifyReport { entries: 0, findings: Vec::new(), consistent_ranges: Vec::new() },
+            range: None,
+            prev_hash: None,
+            next_seq: Some(0),
+            last_timestamp: None,
+            hash_alg: Some(HashAlgorithm::default()),
+            seen: HashMap::new(),
+        }
+    }
+
+    fn finding(&mut self, file: &str, record: usize, offset: u64, seq: Option<u64>, problem: Problem) {
+        self.report.findings.push(Finding { file: file.to_owned(), record, offset, seq, problem });
+    }
+
+    /// Close the current range, if any.
+    fn end_range(&mut self) {
+        if let Some(range) = self.range.take() {
+            self.report.consistent_ranges.push(range);
+        }
+    }
+
+    /// Start from `checkpoint` instead of genesis.
+    fn checkpoint(&mut self, path: &Path, checkpoint: Checkpoint) {
+        if let Err(e) = checkpoint.verify_signature(&self.options.checkpoint_keys) {
+            let file = file_name(&Checkpoint::path_for(path));
+            self.finding(&file, 0, 0, None, Problem::InvalidCheckpoint { cause: e.to_string() });
+        }
+        self.prev_hash = Some(checkpoint.head_hash);
+        self.next_seq = Some(checkpoint.entries);
+        self.last_timestamp = checkpoint.last_timestamp;
+        self.hash_alg = None;
+    }
+
+    /// Scan sealed segment `info` and compare it with its manifest record.
+    fn segment(&mut self, path: &Path, info: &SegmentInfo) {
+        if !path.exists() {
+            self.finding(&info.file, 0, 0, None, Problem::MissingFile);
+            self.end_range();
+            self.prev_hash = Some(info.head_hash.clone());
+            self.next_seq = Some(info.first_seq + info.entries);
+            self.hash_alg = None;
+            return;
+        }
+        let summary = self.file(path);
+        let mismatch = if summary.entries != info.entries {
+            Some("entries")
+        } else if summary.first_prev_hash.as_deref() != Some(info.start_hash.as_str()) {
+            Some("start_hash")
+        } else if summary.head_hash.as_deref() != Some(info.head_hash.as_str()) {
+            Some("head_hash")
+        } else {
+            None
+        };
+        if let Some(field) = mismatch {
+            let problem = Problem::ManifestMismatch { segment: info.index, field: field.to_owned() };
+            self.finding(&info.file, 0, 0, None, problem);
+        }
+    }
+
+    /// Scan every record of the file at `path`.
+    fn file(&mut self, path: &Path) -> FileSummary {
+        let name = file_name(path);
+        let mut summary = FileSummary::default();
+        let mut reader = match RecordReader::open(path) {
+            Ok(reader) => reader,
+            Err(e) => {
+                self.finding(&name, 0, 0, None, Problem::Unreadable { cause: e.to_string() });
+                self.end_range();
+                self.prev_hash = None;
+                self.next_seq = None;
+                return summary;
+            }
+        };
+        if let Some(found) = reader.header().map(|h| h.hash_algorithm) {
+            if let Some(expected) = self.hash_alg.filter(|&alg| alg != found) {
+                self.finding(&name, 0, 0, None, Problem::HashAlgorithmMismatch { expected, found });
+            }
+            self.hash_alg = Some(found);
+        }
+        loop {
+            match reader.next_record() {
+                Ok(None) => break,
+                Ok(Some(Record::Blank)) => {}
+                Ok(Some(Record::Torn)) => {
+                    self.finding(&name, reader.line_no, reader.offset, None, Problem::TornTail { bytes: reader.len });
+                    break;
+                }
+                Ok(Some(Record::Entry(entry))) => {
+                    let hash = entry.hash();
+                    if summary.first_prev_hash.is_none() {
+                        summary.first_prev_hash = Some(entry.prev_hash.clone());
+                    }
+                    summary.entries += 1;
+                    summary.head_hash = Some(hash.clone());
+                    self.entry(&name, reader.line_no, reader.offset, &entry, hash);
+                }
+                Err(IntegrityError::Io(e)) => {
+                    self.finding(&name, reader.line_no, reader.offset, None, Problem::Unreadable { cause: e.to_string() });
+                    self.end_range();
+                    self.prev_hash = None;
+                    self.next_seq = None;
+                    break;
+                }
+                Err(e) => {
+                    self.finding(&name, reader.line_no, reader.offset, None, Problem::Unparseable { cause: e.to_string() });
+                    self.end_range();
+                    self.prev_hash = None;
+                    self.next_seq = self.next_seq.map(|seq| seq + 1);
+                    self.hash_alg = None;
+                    if reader.lost_sync {
+                        self.finding(&name, reader.line_no, reader.offset, None, Problem::Unreadable {
+                            cause: "record framing lost; rest of file skipped".into(),
+                        });
+                        break;
+                    }
+                }
+            }
+        }
+        summary
+    }
+
+    /// Check one readable entry and extend or restart the current range.
+    fn entry(&mut self, file: &str, record: usize, offset: u64, entry: &LogEntry, hash: String) {
+        self.report.entries += 1;
+        let seq = Some(entry.seq);
+        if let Some((first_file, first_record)) = self.seen.get(&hash).cloned() {
+            self.finding(file, record, offset, seq, Problem::Duplicate { first_file, first_record });
+            return;
+        }
+        self.seen.insert(hash.clone(), (file.to_owned(), record));
+
+        let mut linked = true;
+        if let Some(expected) = self.prev_hash.clone() {
+            if entry.prev_hash != expected {
+                let found = entry.prev_hash.clone();
+                self.finding(file, record, offset, seq, Problem::BrokenLink { expected, found });
+                linked = false;
+            }
+        }
+        if let Some(expected_seq) = self.next_seq {
+            if entry.seq != expected_seq {
+                let problem = if entry.seq > expected_seq {
+                    Problem::Gap { expected_seq, found_seq: entry.seq }
+                } else {
+                    Problem::OutOfOrder { expected_seq, found_seq: entry.seq }
+                };
+                self.finding(file, record, offset, seq, problem);
+                linked = false;
+            }
+        }
+        if let Some(previous) = self.last_timestamp.filter(|&t| entry.timestamp < t) {
+            self.finding(file, record, offset, seq, Problem::TimestampRegression { previous, found: entry.timestamp });
+        }
+        if let Some(clock) = entry.clamped_from {
+            self.finding(file, record, offset, seq, Problem::ClampedTimestamp { clock, recorded: entry.timestamp });
+        }
+        if let Some((kind, reason)) = self.options.custom_violation(&entry.operation) {
+            self.finding(file, record, offset, seq, Problem::InvalidCustomDetails { kind, reason });
+        }
+        let found = match entry.operation {
+            Operation::HashAlgorithmChanged { from, .. } if from != entry.hash_algorithm() => from,
+            _ => entry.hash_algorithm(),
+        };
+        if let Some(expected) = self.hash_alg.filter(|&alg| alg != found) {
+            self.finding(file, record, offset, seq, Problem::HashAlgorithmMismatch { expected, found });
+        }
+        self.hash_alg = Some(entry.next_hash_algorithm());
+
+        match &mut self.range {
+            Some(range) if linked => {
+                range.last_seq = entry.seq;
+                range.entries += 1;
+                range.head_hash = hash.clone();
+            }
+            _ => {
+                self.end_range();
+                self.range = Some(ConsistentRange {
+                    first_seq: entry.seq,
+                    last_seq: entry.seq,
+                    entries: 1,
+                    start_hash: entry.prev_hash.clone(),
+                    head_hash: hash.clone(),
+                });
+            }
+        }
+        self.prev_hash = Some(hash);
+        self.next_seq = Some(entry.seq + 1);
+        self.last_timestamp = Some(entry.timestamp);
+    }
+
+    fn finish(mut self) -> VerifyReport {
+        self.end_range();
+        self.report
+    }
+}
@@ -0,0 +1,275 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Custom Operation Schemas — Validating Component-Defined Events.
+//!
+//! Components outside this crate (the Elixir supervisor, Haskell and Idris
+//! checkers) log their own events as `Operation::Custom` with a namespaced
+//! `kind` and free-form JSON `details`. A `SchemaRegistry` maps each kind to
+//! a JSON Schema so that verification can check the details as well as the
+//! chain.
+//!
+//! KIND NAMES:
+//! A kind is two or more dot-separated segments of lowercase ASCII letters,
+//! digits, `_` or `-`, the first naming the component: `elixir.repo_fixed`,
+//! `idris.proof-checked`.
+//!
+//! SUPPORTED KEYWORDS:
+//! The validator implements the subset of JSON Schema that event payloads
+//! need: `type` (a name or list of names), `enum`, `const`, `properties`,
+//! `required`, `additionalProperties` (boolean or schema), `items`,
+//! `minItems`, `maxItems`, `minLength`, `maxLength`, `minimum`, `maximum`,
+//! plus the annotations `$schema`, `$id`, `title`, `description`. A schema
+//! using any other keyword is rejected when registered rather than being
+//! silently under-enforced.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde_json::{Map, Value};
+
+/// Keywords the validator understands.
+const KEYWORDS: &[&str] = &[
+    "type", "enum", "const", "properties", "required", "additionalProperties", "items",
+    "minItems", "maxItems", "minLength", "maxLength", "minimum", "maximum",
+    "$schema", "$id", "title", "description",
+];
+
+/// Errors raised when registering a schema.
+#[derive(Debug, thiserror::Error)]
+pub enum SchemaError {
+    /// The kind is not a valid namespaced name.
+    #[error("invalid custom operation kind {0:?}: expected `component.event`")]
+    InvalidKind(String),
+
+    /// The schema is not one this validator can enforce.
+    #[error("unsupported schema for {kind} at {pointer}: {reason}")]
+    Unsupported { kind: String, pointer: String, reason: String },
+
+    /// A schema file could not be read or parsed.
+    #[error("schema file {file}: {cause}")]
+    File { file: String, cause: String },
+}
+
+/// Whether `kind` is a valid namespaced custom operation kind.
+pub fn is_valid_kind(kind: &str) -> bool {
+    let mut segments = 0;
+    for segment in kind.split('.') {
+        let ok = !segment.is_empty()
+            && segment.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_' || b == b'-');
+        if !ok { return false; }
+        segments += 1;
+    }
+    segments >= 2
+}
+
+/// JSON Schemas for custom operation details, keyed by kind.
+#[derive(Debug, Clone, Default)]
+pub struct SchemaRegistry {
+    schemas: BTreeMap<String, Value>,
+}
+
+impl SchemaRegistry {
+    /// An empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `schema` for custom operations of `kind`, replacing any
+    /// schema registered for it before.
+    pub fn register(&mut self, kind: &str, schema: Value) -> Result<(), SchemaError> {
+        if !is_valid_kind(kind) {
+            return Err(SchemaError::InvalidKind(kind.to_owned()));
+        }
+        check_schema(&schema, "").map_err(|(pointer, reason)| SchemaError::Unsupported {
+            kind: kind.to_owned(),
+            pointer,
+            reason,
+        })?;
+        self.schemas.insert(kind.to_owned(), schema);
+        Ok(())
+    }
+
+    /// Register every `<kind>.schema.json` file in `dir`.
+    ///
+    /// This lets components written in other languages ship their schemas
+    /// as plain files next to the log. Returns the number registered.
+    pub fn load_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result<usize, SchemaError> {
+        let file_error = |file: &Path, cause: String| SchemaError::File { file: file.display().to_string(), cause };
+        let dir = dir.as_ref();
+        let mut count = 0;
+        for entry in fs::read_dir(dir).map_err(|e| file_error(dir, e.to_string()))? {
+            let path = entry.map_err(|e| file_error(dir, e.to_string()))?.path();
+            let Some(kind) = path.file_name().and_then(|n| n.to_str()).and_then(|n| n.strip_suffix(".schema.json")) else {
+                continue;
+            };
+            let text = fs::read_to_string(&path).map_err(|e| file_error(&path, e.to_string()))?;
+            let schema = serde_json::from_str(&text).map_err(|e| file_error(&path, e.to_string()))?;
+            self.register(kind, schema)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Whether a schema is registered for `kind`.
+    pub fn contains(&self, kind: &str) -> bool {
+        self.schemas.contains_key(kind)
+    }
+
+    /// Check `details` against the schema for `kind`.
+    ///
+    /// Returns `Ok(false)` if no schema is registered for `kind`, and a
+    /// description of the first violation if validation fails.
+    pub fn validate(&self, kind: &str, details: &Value) -> Result<bool, String> {
+        match self.schemas.get(kind) {
+            Some(schema) => validate(schema, details, "").map(|()| true),
+            None => Ok(false),
+        }
+    }
+}
+
+/// Reject schemas using keywords or shapes the validator does not enforce.
+fn check_schema(schema: &Value, pointer: &str) -> Result<(), (String, String)> {
+    let Value::Object(map) = schema else {
+        return match schema {
+            Value::Bool(_) => Ok(()),
+            _ => Err((pointer.to_owned(), "a schema must be an object or boolean".into())),
+        };
+    };
+    for (key, value) in map {
+        let here = format!("{pointer}/{key}");
+        if !KEYWORDS.contains(&key.as_str()) {
+            return Err((here, "keyword not supported".into()));
+        }
+        match key.as_str() {
+            "properties" => {
+                let Value::Object(props) = value else { return Err((here, "must be an object".into())) };
+                for (name, sub) in props {
+                    check_schema(sub, &format!("{here}/{name}"))?;
+                }
+            }
+            "items" | "additionalProperties" => check_schema(value, &here)?,
+            "required" if !value.as_array().is_some_and(|a| a.iter().all(Value::is_string)) => {
+                return Err((here, "must be an array of strings".into()));
+            }
+            "enum" if !value.is_array() => return Err((here, "must be an array".into())),
+            "type" => {
+                let names: Vec<&Value> = match value {
+                    Value::Array(names) => names.iter().collect(),
+                    other => vec![other],
+                };
+                if !names.iter().all(|n| n.as_str().is_some_and(|n| type_matches(n, &Value::Null).is_some())) {
+                    return Err((here, "unknown type name".into()));
+                }
+            }
+            "minItems" | "maxItems" | "minLength" | "maxLength" if !value.is_u64() => {
+                return Err((here, "must be a non-negative integer".into()));
+            }
+            "minimum" | "maximum" if !value.is_number() => return Err((here, "must be a number".into())),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Whether `value` has JSON Schema type `name`; `None` for an unknown name.
+fn type_matches(name: &str, value: &Value) -> Option<bool> {
+    Some(match name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|f| f.fract() == 0.0),
+        _ => return None,
+    })
+}
+
+/// Validate `value` against a schema that passed [`check_schema`].
+fn validate(schema: &Value, value: &Value, pointer: &str) -> Result<(), String> {
+    let at = if pointer.is_empty() { "/" } else { pointer };
+    let fail = |what: String| Err(format!("{at}: {what}"));
+    let map = match schema {
+        Value::Object(map) => map,
+        Value::Bool(false) => return fail("no value is allowed here".into()),
+        _ => return Ok(()),
+    };
+
+    if let Some(types) = map.get("type") {
+        let names: Vec<&str> = match types {
+            Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+            other => other.as_str().into_iter().collect(),
+        };
+        if !names.iter().any(|n| type_matches(n, value) == Some(true)) {
+            return fail(format!("expected type {}", names.join(" or ")));
+        }
+    }
+    if let Some(Value::Array(allowed)) = map.get("enum") {
+        if !allowed.contains(value) { return fail("value is not one of the allowed values".into()); }
+    }
+    if let Some(expected) = map.get("const") {
+        if expected != value { return fail(format!("expected {expected}")); }
+    }
+
+    match value {
+        Value::Object(object) => validate_object(map, object, pointer)?,
+        Value::Array(items) => {
+            if let Some(min) = map.get("minItems").and_then(Value::as_u64) {
+                if (items.len() as u64) < min { return fail(format!("fewer than {min} items")); }
+            }
+            if let Some(max) = map.get("maxItems").and_then(Value::as_u64) {
+                if items.len() as u64 > max { return fail(format!("more than {max} items")); }
+            }
+            if let Some(item_schema) = map.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    validate(item_schema, item, &format!("{pointer}/{i}"))?;
+                }
+            }
+        }
+        Value::String(s) => {
+            let len = s.chars().count() as u64;
+            if let Some(min) = map.get("minLength").and_then(Value::as_u64) {
+                if len < min { return fail(format!("shorter than {min} characters")); }
+            }
+            if let Some(max) = map.get("maxLength").and_then(Value::as_u64) {
+                if len > max { return fail(format!("longer than {max} characters")); }
+            }
+        }
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or(f64::NAN);
+            if let Some(min) = map.get("minimum").and_then(Value::as_f64) {
+                if n < min { return fail(format!("less than {min}")); }
+            }
+            if let Some(max) = map.get("maximum").and_then(Value::as_f64) {
+                if n > max { return fail(format!("greater than {max}")); }
+            }
+        }
+        Value::Null | Value::Bool(_) => {}
+    }
+    Ok(())
+}
+
+fn validate_object(schema: &Map<String, Value>, object: &Map<String, Value>, pointer: &str) -> Result<(), String> {
+    if let Some(Value::Array(required)) = schema.get("required") {
+        for name in required.iter().filter_map(Value::as_str) {
+            if !object.contains_key(name) {
+                return Err(format!("{pointer}/{name}: required property is missing"));
+            }
+        }
+    }
+    let properties = schema.get("properties").and_then(Value::as_object);
+    for (name, value) in object {
+        let here = format!("{pointer}/{name}");
+        if let Some(sub) = properties.and_then(|p| p.get(name)).or_else(|| schema.get("additionalProperties")) {
+            validate(sub, value, &here)?;
+        }
+    }
+    Ok(())
+}
+
+/// Error for appending a custom operation with an invalid kind.
+pub(crate) fn invalid_kind(kind: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, SchemaError::InvalidKind(kind.to_owned()))
+}
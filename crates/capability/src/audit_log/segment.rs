@@ -0,0 +1,247 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Segmentation — Rotation With Cross-Segment Chaining.
+//!
+//! A long-lived log is split into numbered, sealed segments plus the active
+//! file. For an active log at `audit.log`:
+//!
+//! ```text
+//! audit.log.000001   sealed segment 1 (entries 0..n)
+//! audit.log.000002   sealed segment 2, first entry chains to segment 1's head
+//! audit.log          active segment, first entry chains to segment 2's head
+//! audit.log.manifest JSON list of sealed segments and their boundary hashes
+//! ```
+//!
+//! The hash chain and sequence numbers simply continue across segment
+//! boundaries, so the whole set verifies as one log. The manifest records
+//! each segment's start and head hash, which lets verification resume from
+//! any segment boundary without reading — or even having — the segments
+//! before it. Sealed segments can therefore be compressed or archived, and
+//! a restored copy can be checked on its own with
+//! [`SegmentManifest::verify_segment`].
+//!
+//! Rotation renames the active file first and records it in the manifest
+//! second. A crash in between leaves a numbered segment the manifest does
+//! not list; [`SegmentManifest::load`] detects and re-adds it.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{ChainWalk, FileSpan, IntegrityError, LogFormat, Scan, GENESIS_HASH};
+
+/// When the active segment is sealed and a new one started.
+///
+/// Both limits are checked before each append; whichever is reached first
+/// triggers rotation. An empty active segment is never rotated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rotation {
+    /// Seal the active segment once it holds at least this many bytes.
+    pub max_bytes: Option<u64>,
+    /// Seal the active segment once its first entry is this old.
+    pub max_age: Option<Duration>,
+}
+
+impl Rotation {
+    /// Never rotate automatically.
+    pub fn never() -> Self {
+        Self::default()
+    }
+
+    /// Rotate once the active segment reaches `bytes`.
+    pub fn by_size(bytes: u64) -> Self {
+        Self { max_bytes: Some(bytes), max_age: None }
+    }
+
+    /// Rotate once the active segment's first entry is `age` old.
+    pub fn by_age(age: Duration) -> Self {
+        Self { max_bytes: None, max_age: Some(age) }
+    }
+
+    /// Whether a segment of `len` bytes opened at `since` is due at `now`.
+    pub(crate) fn is_due(&self, len: u64, since: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        let by_size = self.max_bytes.is_some_and(|max| len >= max);
+        let by_age = self.max_age.is_some_and(|max| {
+            (now - since).to_std().is_ok_and(|age| age >= max)
+        });
+        by_size || by_age
+    }
+}
+
+/// One sealed segment as recorded in the manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SegmentInfo {
+    /// 1-based segment number.
+    pub index: u32,
+    /// File name of the segment, relative to the log's directory.
+    pub file: String,
+    /// Storage format of the segment.
+    pub format: LogFormat,
+    /// Sequence number of the segment's first entry.
+    pub first_seq: u64,
+    /// Number of entries in the segment.
+    pub entries: u64,
+    /// `prev_hash` of the segment's first entry.
+    pub start_hash: String,
+    /// Hash of the segment's last entry.
+    pub head_hash: String,
+    /// When the segment was sealed.
+    pub sealed_at: DateTime<Utc>,
+}
+
+/// Ordered list of sealed segments belonging to one active log.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SegmentManifest {
+    /// Sealed segments, oldest first.
+    pub segments: Vec<SegmentInfo>,
+}
+
+impl SegmentManifest {
+    /// Load the manifest for the active log at `path`.
+    ///
+    /// Returns `None` for an unsegmented log. Sealed segment files present
+    /// on disk but missing from the manifest (a crash mid-rotation) are
+    /// scanned and appended to the returned manifest.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<Self>, IntegrityError> {
+        let path = path.as_ref();
+        let manifest_path = manifest_path(path);
+        let mut manifest = match fs::read(&manifest_path) {
+            Ok(bytes) => serde_json::from_slice::<Self>(&bytes).map_err(|e| {
+                IntegrityError::Deserialisation { line: 0, cause: format!("segment manifest: {e}") }
+            })?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        loop {
+            let index = manifest.next_index();
+            let orphan = segment_path(path, index);
+            if !orphan.exists() { break; }
+            let scan = Scan::read(&orphan)?;
+            // The first segment of a compacted log does not start at 0.
+            let (start_hash, first_seq) = manifest.segments.last()
+                .map(|s| (s.head_hash.clone(), s.first_seq + s.entries))
+                .or_else(|| scan.first.as_ref().map(|(seq, prev_hash, _)| (prev_hash.clone(), *seq)))
+                .unwrap_or_else(|| (GENESIS_HASH.to_owned(), 0));
+            manifest.segments.push(SegmentInfo {
+                index,
+                file: file_name(&orphan),
+                format: scan.format,
+                first_seq,
+                entries: scan.next_seq.saturating_sub(first_seq),
+                start_hash,
+                head_hash: scan.last_hash,
+                sealed_at: Utc::now(),
+            });
+        }
+
+        Ok((!manifest.segments.is_empty()).then_some(manifest))
+    }
+
+    /// Atomically replace the manifest for the active log at `path`.
+    pub(crate) fn store(&self, path: &Path) -> io::Result<()> {
+        let target = manifest_path(path);
+        let temp = target.with_extension("manifest.tmp");
+        let mut out = File::create(&temp)?;
+        out.write_all(&serde_json::to_vec_pretty(self).map_err(io::Error::other)?)?;
+        out.sync_all()?;
+        fs::rename(&temp, &target)?;
+        sync_parent(&target)
+    }
+
+    /// Number the next sealed segment will receive.
+    pub(crate) fn next_index(&self) -> u32 {
+        self.segments.last().map_or(1, |s| s.index + 1)
+    }
+
+    /// Hash and sequence number the active segment continues from.
+    pub(crate) fn tip(&self) -> Option<(String, u64)> {
+        self.segments.last().map(|s| (s.head_hash.clone(), s.first_seq + s.entries))
+    }
+
+    /// Verify a single sealed segment file — for example a copy restored
+    /// from an archive — against its manifest record.
+    ///
+    /// Returns the number of entries verified.
+    pub fn verify_segment<P: AsRef<Path>>(&self, index: u32, file: P) -> Result<usize, IntegrityError> {
+        let info = self.segments.iter()
+            .find(|s| s.index == index)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("segment {index} not in manifest")))?;
+        let mut walk = ChainWalk::anchored(info.start_hash.clone(), info.first_seq);
+        let span = walk.walk_file(file.as_ref())?;
+        check_boundary(info, &span, &walk)?;
+        Ok(walk.count)
+    }
+}
+
+/// Verify every sealed segment and then the active file as one chain,
+/// starting from the unanchored `walk`.
+pub(crate) fn verify_all(path: &Path, manifest: &SegmentManifest, walk: ChainWalk<'_>) -> Result<usize, IntegrityError> {
+    verify_range(path, manifest, 0, walk)
+}
+
+/// Verify from segment `index` onward, trusting the manifest's record of
+/// where that segment starts. Earlier segments are not read.
+pub(crate) fn verify_from(path: &Path, manifest: &SegmentManifest, index: u32) -> Result<usize, IntegrityError> {
+    let Some(pos) = manifest.segments.iter().position(|s| s.index == index) else {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("segment {index} not in manifest")).into());
+    };
+    let info = &manifest.segments[pos];
+    verify_range(path, manifest, pos, ChainWalk::anchored(info.start_hash.clone(), info.first_seq))
+}
+
+fn verify_range(path: &Path, manifest: &SegmentManifest, from: usize, mut walk: ChainWalk<'_>) -> Result<usize, IntegrityError> {
+    for info in &manifest.segments[from..] {
+        let span = walk.walk_file(&sibling(path, &info.file))?;
+        check_boundary(info, &span, &walk)?;
+    }
+    if path.exists() {
+        walk.walk_file(path)?;
+    }
+    Ok(walk.count)
+}
+
+/// Compare a walk over one segment file with its manifest record.
+fn check_boundary(info: &SegmentInfo, span: &FileSpan, walk: &ChainWalk<'_>) -> Result<(), IntegrityError> {
+    let mismatch = |field| Err(IntegrityError::ManifestMismatch { segment: info.index, field });
+    if span.entries != info.entries {
+        return mismatch("entries");
+    }
+    if span.first_prev_hash.as_deref() != Some(info.start_hash.as_str()) {
+        return mismatch("start_hash");
+    }
+    if walk.head_hash() != info.head_hash {
+        return mismatch("head_hash");
+    }
+    Ok(())
+}
+
+/// Path of sealed segment `index` for the active log at `path`.
+pub(crate) fn segment_path(path: &Path, index: u32) -> PathBuf {
+    path.with_file_name(format!("{}.{index:06}", file_name(path)))
+}
+
+/// Path of the segment manifest for the active log at `path`.
+pub(crate) fn manifest_path(path: &Path) -> PathBuf {
+    path.with_file_name(format!("{}.manifest", file_name(path)))
+}
+
+pub(crate) fn sibling(path: &Path, name: &str) -> PathBuf {
+    path.with_file_name(name)
+}
+
+pub(crate) fn file_name(path: &Path) -> String {
+    path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+/// `fsync` the directory containing `path` so a rename is durable.
+pub(crate) fn sync_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
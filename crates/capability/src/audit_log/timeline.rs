@@ -0,0 +1,204 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Timeline — Replaying File Operations per Path.
+//!
+//! `Timeline::replay` reads the `FileWrite`, `FileMove` and `FileDelete`
+//! entries of a log, with the transaction entries around them, into a
+//! history per path. It answers "what did the tool last do to this path, and
+//! in which transaction" and, from the content hashes in `Change::Content`,
+//! "which action produced the bytes this file holds now".
+//!
+//! PATHS:
+//! Paths are compared exactly as they were logged; nothing is canonicalised.
+//! A move appears in the history of both paths: as `MovedOut` for the
+//! source, whose content afterwards is "no file", and as `MovedIn` for the
+//! destination.
+//!
+//! EFFECT:
+//! An event took effect unless its outcome is a failure or a rollback, or
+//! its transaction was rolled back. Events of a transaction that began but
+//! never finished count as taking effect, since whatever it applied is still
+//! on disk (see `incomplete_transactions`). Only events that took effect
+//! can have produced a file's current bytes.
+//!
+//! Like the query functions, replay does not check chain links; run
+//! `AuditLog::verify` first when the answer has to be trusted.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{content_hash, AuditLog, Change, IntegrityError, Keyring, LogEntry, Operation, Outcome};
+
+/// What an entry did to one path.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PathAction {
+    /// The path was written.
+    Written,
+    /// The path was deleted.
+    Deleted,
+    /// A file was moved to the path from `from`.
+    MovedIn { from: PathBuf },
+    /// The file at the path was moved to `to`.
+    MovedOut { to: PathBuf },
+}
+
+/// How a transaction ended, as far as the log shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TxState {
+    /// Began but was neither committed nor rolled back.
+    Open,
+    /// Committed.
+    Committed,
+    /// Rolled back.
+    RolledBack,
+}
+
+/// One entry's action on one path.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathEvent {
+    /// Sequence number of the entry.
+    pub seq: u64,
+    /// When the entry was logged.
+    pub timestamp: DateTime<Utc>,
+    /// What happened to the path.
+    pub action: PathAction,
+    /// Transaction the entry belongs to.
+    pub tx_id: Option<String>,
+    /// Outcome the entry recorded.
+    pub outcome: Option<Outcome>,
+    /// Content of this path before and after the action, if the entry
+    /// recorded it.
+    pub change: Option<Change>,
+}
+
+impl PathEvent {
+    /// SHA-256 of the path's content after the action: `Some(None)` for "no
+    /// file", `None` if the entry did not record content hashes.
+    pub fn content_after(&self) -> Option<Option<&str>> {
+        match &self.change {
+            Some(Change::Content { after, .. }) => Some(after.as_deref()),
+            _ => None,
+        }
+    }
+}
+
+/// Per-path history of the file operations in a log; see the module docs.
+#[derive(Debug, Clone, Default)]
+pub struct Timeline {
+    paths: BTreeMap<PathBuf, Vec<PathEvent>>,
+    transactions: BTreeMap<String, TxState>,
+}
+
+impl Timeline {
+    /// Replay every entry of the log at `path`. Sealed entries are skipped;
+    /// use [`Timeline::replay_decrypted`] for an encrypted log.
+    pub fn replay<P: AsRef<Path>>(path: P) -> Result<Self, IntegrityError> {
+        Self::from_entries(AuditLog::entries(path)?)
+    }
+
+    /// Replay every entry of the log at `path`, opening sealed entries with
+    /// `keyring`.
+    pub fn replay_decrypted<P: AsRef<Path>>(path: P, keyring: &Keyring) -> Result<Self, IntegrityError> {
+        Self::from_entries(AuditLog::decrypted_entries(path, keyring)?)
+    }
+
+    fn from_entries(entries: impl Iterator<Item = Result<LogEntry, IntegrityError>>) -> Result<Self, IntegrityError> {
+        let mut timeline = Self::default();
+        for entry in entries {
+            timeline.add(entry?);
+        }
+        Ok(timeline)
+    }
+
+    fn add(&mut self, entry: LogEntry) {
+        let event = |action, change| PathEvent {
+            seq: entry.seq,
+            timestamp: entry.timestamp,
+            action,
+            tx_id: entry.tx_id.clone(),
+            outcome: entry.outcome.clone(),
+            change,
+        };
+        match &entry.operation {
+            Operation::TransactionBegin { tx_id, .. } => {
+                self.transactions.insert(tx_id.clone(), TxState::Open);
+            }
+            Operation::TransactionCommitted { tx_id, .. } => {
+                self.transactions.insert(tx_id.clone(), TxState::Committed);
+            }
+            Operation::TransactionRolledBack { tx_id, .. } => {
+                self.transactions.insert(tx_id.clone(), TxState::RolledBack);
+            }
+            Operation::FileWrite { path } => self.push(path, event(PathAction::Written, entry.change.clone())),
+            Operation::FileDelete { path } => self.push(path, event(PathAction::Deleted, entry.change.clone())),
+            Operation::FileMove { from, to } => {
+                // The source held what was moved and afterwards holds nothing.
+                let moved_out = match &entry.change {
+                    Some(Change::Content { after, .. }) => Some(Change::Content { before: after.clone(), after: None }),
+                    _ => None,
+                };
+                self.push(from, event(PathAction::MovedOut { to: to.clone() }, moved_out));
+                self.push(to, event(PathAction::MovedIn { from: from.clone() }, entry.change.clone()));
+            }
+            _ => {}
+        }
+    }
+
+    fn push(&mut self, path: &Path, event: PathEvent) {
+        self.paths.entry(path.to_path_buf()).or_default().push(event);
+    }
+
+    /// Every path the log touched, sorted.
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.paths.keys().map(PathBuf::as_path)
+    }
+
+    /// Everything the log records happening to `path`, oldest first.
+    pub fn history(&self, path: &Path) -> &[PathEvent] {
+        self.paths.get(path).map_or(&[], Vec::as_slice)
+    }
+
+    /// The last thing the tool did to `path`, whether or not it took effect.
+    /// Its `tx_id` names the transaction.
+    pub fn last_action(&self, path: &Path) -> Option<&PathEvent> {
+        self.history(path).last()
+    }
+
+    /// How transaction `tx_id` ended; `None` if the log has no
+    /// `TransactionBegin` or outcome for it.
+    pub fn transaction_state(&self, tx_id: &str) -> Option<TxState> {
+        self.transactions.get(tx_id).copied()
+    }
+
+    /// Whether `event` took effect; see the module docs.
+    pub fn took_effect(&self, event: &PathEvent) -> bool {
+        let undone = matches!(event.outcome, Some(Outcome::Failure { .. } | Outcome::RolledBack));
+        let rolled_back = event.tx_id.as_deref().and_then(|id| self.transaction_state(id)) == Some(TxState::RolledBack);
+        !undone && !rolled_back
+    }
+
+    /// The latest action that took effect and left `path` holding `content`
+    /// (a SHA-256 hex digest, or `None` for "no file"). `None` if no logged
+    /// action did, meaning something outside the tool produced that state.
+    ///
+    /// A match older than the path's last action means a later action was
+    /// undone, or the path was changed outside the tool and then restored.
+    pub fn producer_of(&self, path: &Path, content: Option<&str>) -> Option<&PathEvent> {
+        self.history(path)
+            .iter()
+            .rev()
+            .find(|event| self.took_effect(event) && event.content_after() == Some(content))
+    }
+
+    /// The action that produced the current bytes of `path` (or its absence),
+    /// hashing the file as it is now; see [`Timeline::producer_of`].
+    /// A relative logged path is resolved against the working directory.
+    pub fn current_producer(&self, path: &Path) -> io::Result<Option<&PathEvent>> {
+        let content = content_hash(path)?;
+        Ok(self.producer_of(path, content.as_deref()))
+    }
+}
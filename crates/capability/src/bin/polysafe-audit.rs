@@ -0,0 +1,351 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! `polysafe-audit` — maintenance commands for hash-chained audit logs.
+//!
+//! USAGE:
+//!   polysafe-audit verify <log> [--schemas <dir>]
+//!   polysafe-audit verify <log> --archive <archive>
+//!   polysafe-audit report <log> [--schemas <dir>]
+//!   polysafe-audit migrate <legacy-log> <new-log>
+//!   polysafe-audit convert <log> <new-log> <ndjson|binary>
+//!   polysafe-audit export <log> [--correlation <id>] [--keys <dir>] [--format <ndjson|syslog|csv|jsonl>]
+//!   polysafe-audit redact <log> <new-log> --salt <file>
+//!   polysafe-audit check-redaction <redacted-log> <proof>
+//!   polysafe-audit correlations <log>
+//!   polysafe-audit incomplete <log>
+//!   polysafe-audit timeline <log> <path>
+//!   polysafe-audit follow <log> [--from-start]
+//!   polysafe-audit reconcile <log> <mirror>... [--repair]
+//!   polysafe-audit compact <log> <entries> <archive> --key <file>
+//!   polysafe-audit header <log>
+//!   polysafe-audit rehash <log> <sha-256|sha-384|sha-512>
+
+#![forbid(unsafe_code)]
+use std::io::Write;
+use std::process::ExitCode;
+use capability::audit_log::{self, AuditLog, CheckpointKey, ExportFormat, Exporter, FollowEvent, Follower, HashAlgorithm, IntegrityError, Keyring, LogEntry, LogFormat, LogHeader, RedactionProof, Redactor, SchemaRegistry, Timeline, VerifyOptions};
+
+const USAGE: &str = "\
+usage:
+  polysafe-audit verify <log> [--schemas <dir>]
+  polysafe-audit verify <log> --archive <archive>
+  polysafe-audit report <log> [--schemas <dir>]
+  polysafe-audit migrate <legacy-log> <new-log>
+  polysafe-audit convert <log> <new-log> <ndjson|binary>
+  polysafe-audit export <log> [--correlation <id>] [--keys <dir>] [--format <ndjson|syslog|csv|jsonl>]
+  polysafe-audit redact <log> <new-log> --salt <file>
+  polysafe-audit check-redaction <redacted-log> <proof>
+  polysafe-audit correlations <log>
+  polysafe-audit incomplete <log>
+  polysafe-audit timeline <log> <path>
+  polysafe-audit follow <log> [--from-start]
+  polysafe-audit reconcile <log> <mirror>... [--repair]
+  polysafe-audit compact <log> <entries> <archive> --key <file>
+  polysafe-audit header <log>
+  polysafe-audit rehash <log> <sha-256|sha-384|sha-512>";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let argv: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let result = match argv.as_slice() {
+        ["verify", log] => AuditLog::verify(log)
+            .map(|n| format!("{log}: {n} entries, chain intact")),
+        ["verify", log, "--schemas", dir] => verify_schemas(log, dir)
+            .map(|n| format!("{log}: {n} entries, chain intact, custom details valid")),
+        ["verify", log, "--archive", archive] => AuditLog::verify_with_archive(log, archive)
+            .map(|n| format!("{log}: {n} entries including {archive}, chain intact")),
+        ["report", log] => return report(log, None),
+        ["report", log, "--schemas", dir] => return report(log, Some(dir)),
+        ["migrate", src, dst] => audit_log::migrate_legacy(src, dst)
+            .map(|n| format!("migrated {n} legacy entries from {src} to {dst}")),
+        ["convert", src, dst, "ndjson"] => audit_log::convert(src, dst, LogFormat::Ndjson)
+            .map(|n| format!("converted {n} entries from {src} to NDJSON {dst}")),
+        ["convert", src, dst, "binary"] => audit_log::convert(src, dst, LogFormat::Binary)
+            .map(|n| format!("converted {n} entries from {src} to binary {dst}")),
+        ["export", log, flags @ ..] => match ExportArgs::parse(flags) {
+            Some(args) => export(log, &args).map(|n| args.summary(n)),
+            None => {
+                eprintln!("{USAGE}");
+                return ExitCode::from(2);
+            }
+        },
+        ["redact", src, dst, "--salt", salt] => redact(src, dst, salt)
+            .map(|n| format!("redacted {n} entries into {dst}; proof in {dst}.redaction.json")),
+        ["check-redaction", log, proof] => check_redaction(log, proof)
+            .map(|n| format!("{log}: {n} entries match the redaction proof")),
+        ["correlations", log] => correlations(log).map(|n| format!("{n} correlation ids")),
+        ["incomplete", log] => incomplete(log).map(|n| format!("{n} incomplete transactions")),
+        ["timeline", log, path] => timeline(log, path),
+        ["follow", log] => follow(log, false).map(|n| format!("{log}: followed {n} entries")),
+        ["follow", log, "--from-start"] => follow(log, true).map(|n| format!("{log}: followed {n} entries")),
+        ["reconcile", log, mirrors @ .., "--repair"] if !mirrors.is_empty() => return reconcile(log, mirrors, true),
+        ["reconcile", log, mirrors @ ..] if !mirrors.is_empty() => return reconcile(log, mirrors, false),
+        ["compact", log, entries, archive, "--key", key] => match entries.parse() {
+            Ok(entries) => compact(log, entries, archive, key)
+                .map(|n| format!("{log}: compacted {n} entries into {archive}")),
+            Err(_) => {
+                eprintln!("{USAGE}");
+                return ExitCode::from(2);
+            }
+        },
+        ["header", log] => header(log).map(|alg| format!("{log}: starts with {alg}")),
+        ["rehash", log, alg] => match alg.parse() {
+            Ok(alg) => rehash(log, alg).map(|seq| format!("{log}: entries from {seq} are hashed with {alg}")),
+            Err(e) => {
+                eprintln!("polysafe-audit: {e}");
+                return ExitCode::from(2);
+            }
+        },
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    match result {
+        Ok(msg) => {
+            eprintln!("{msg}");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("polysafe-audit: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Verify `log`, validating custom operations against the
+/// `<kind>.schema.json` files in `dir`.
+fn verify_schemas(log: &str, dir: &str) -> Result<usize, IntegrityError> {
+    let schemas = load_schemas(dir)?;
+    AuditLog::verify_with(log, &VerifyOptions { schemas: Some(schemas), ..Default::default() })
+}
+
+/// Load the `<kind>.schema.json` files in `dir`.
+fn load_schemas(dir: &str) -> Result<SchemaRegistry, IntegrityError> {
+    let mut schemas = SchemaRegistry::new();
+    schemas.load_dir(dir).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    Ok(schemas)
+}
+
+/// Print a full-scan report of `log` to stdout as JSON, failing if it lists
+/// any problem.
+fn report(log: &str, schema_dir: Option<&str>) -> ExitCode {
+    let options = match schema_dir.map(load_schemas).transpose() {
+        Ok(schemas) => VerifyOptions { schemas, ..Default::default() },
+        Err(e) => {
+            eprintln!("polysafe-audit: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let report = match AuditLog::verify_report_with(log, &options) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("polysafe-audit: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    println!("{}", serde_json::to_string_pretty(&report).expect("VerifyReport must serialise"));
+    eprintln!("{log}: {} entries, {} problems, {} consistent ranges",
+        report.entries, report.findings.len(), report.consistent_ranges.len());
+    if report.is_clean() { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
+
+/// Compare `log` with `mirrors`, optionally repairing lagging ones, and
+/// print the reconcile report as JSON. Fails unless every copy ends up
+/// holding the longest chain.
+fn reconcile(log: &str, mirrors: &[&str], repair: bool) -> ExitCode {
+    let mirrors: Vec<std::path::PathBuf> = mirrors.iter().map(Into::into).collect();
+    let report = match audit_log::reconcile(log, &mirrors, repair) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("polysafe-audit: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    println!("{}", serde_json::to_string_pretty(&report).expect("ReconcileReport must serialise"));
+    eprintln!("{log}: longest valid chain in {}", report.longest.display());
+    if report.is_consistent() { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
+
+/// Compact the first `entries` entries of `log` into `archive`, signing the
+/// checkpoint with the `<key-id>.key` seed file `key`. Prints the
+/// checkpoint as JSON on stdout.
+fn compact(log: &str, entries: u64, archive: &str, key: &str) -> Result<u64, IntegrityError> {
+    let key_path = std::path::Path::new(key);
+    let id = key_path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let key = CheckpointKey::from_hex(id, &std::fs::read_to_string(key_path)?)?;
+    let checkpoint = AuditLog::compact(log, entries, &key, archive)?;
+    println!("{}", serde_json::to_string_pretty(&checkpoint).expect("Checkpoint must serialise"));
+    Ok(checkpoint.entries)
+}
+
+/// Print the header of the log file `log` as JSON on stdout (`null` for a
+/// file written before headers). Returns the hash algorithm it starts with.
+fn header(log: &str) -> Result<HashAlgorithm, IntegrityError> {
+    let header = LogHeader::read(log)?;
+    println!("{}", serde_json::to_string_pretty(&header).expect("LogHeader must serialise"));
+    Ok(header.map(|h| h.hash_algorithm).unwrap_or_default())
+}
+
+/// Switch `log` to hashing new entries with `alg`. Returns the sequence
+/// number of the first entry hashed with it.
+fn rehash(log: &str, alg: HashAlgorithm) -> Result<u64, IntegrityError> {
+    let mut audit = AuditLog::open(log)?;
+    let ticket = audit.change_hash_algorithm(alg)?;
+    ticket.wait_durable()?;
+    Ok(ticket.seq() + 1)
+}
+
+/// Write every entry of `log`, in either format, to stdout as NDJSON,
+/// optionally only those with correlation id `only`. Sealed entries are
+/// opened with the `<key-id>.key` files in `key_dir`, if given. Formats
+/// other than NDJSON go through an `Exporter`.
+fn export(log: &str, args: &ExportArgs<'_>) -> Result<usize, IntegrityError> {
+    let ExportArgs { only, key_dir, format } = *args;
+    let mut keyring = Keyring::read_only();
+    if let Some(dir) = key_dir {
+        keyring.load_dir(dir)?;
+    }
+    if let Some(format) = format {
+        let exporter = Exporter::new(format);
+        let exporter = if key_dir.is_some() { exporter.with_keyring(&keyring) } else { exporter };
+        // Filter on the opened entry so sealed correlation ids still match.
+        let entries = AuditLog::entries(log)?.filter(|entry| match (only, entry) {
+            (Some(id), Ok(e)) => keyring.open(e).map_or(true, |e| e.correlation_id() == Some(id)),
+            _ => true,
+        });
+        return exporter.write(entries, std::io::stdout().lock());
+    }
+    let entries: Box<dyn Iterator<Item = Result<LogEntry, IntegrityError>>> = match key_dir {
+        Some(_) => Box::new(AuditLog::decrypted_entries(log, &keyring)?),
+        None => Box::new(AuditLog::entries(log)?),
+    };
+    let mut stdout = std::io::stdout().lock();
+    let mut count = 0;
+    for entry in entries {
+        let entry = entry?;
+        if only.is_some_and(|id| entry.correlation_id() != Some(id)) { continue; }
+        let line = serde_json::to_string(&entry).expect("LogEntry must serialise");
+        writeln!(stdout, "{line}")?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Print entries appended to `log` as JSON lines until interrupted, with
+/// an alert on stderr for anything that breaks the chain. Starts at the
+/// current end of the log unless `from_start`.
+fn follow(log: &str, from_start: bool) -> Result<usize, IntegrityError> {
+    let follower = if from_start { Follower::from_start(log)? } else { Follower::from_end(log)? };
+    let mut count = 0;
+    for event in follower {
+        match event? {
+            FollowEvent::Entry(entry) => {
+                let line = serde_json::to_string(&entry).expect("LogEntry must serialise");
+                writeln!(std::io::stdout().lock(), "{line}")?;
+                count += 1;
+            }
+            FollowEvent::Rotated { segment } => eprintln!("polysafe-audit: {log} sealed as segment {segment}"),
+            alert => eprintln!("polysafe-audit: ALERT {log}: {alert:?}"),
+        }
+    }
+    Ok(count)
+}
+
+/// Flags of the `export` command. `format` is `None` for raw NDJSON.
+#[derive(Clone, Copy, Default)]
+struct ExportArgs<'a> {
+    only: Option<&'a str>,
+    key_dir: Option<&'a str>,
+    format: Option<ExportFormat>,
+}
+
+impl<'a> ExportArgs<'a> {
+    fn parse(mut flags: &[&'a str]) -> Option<Self> {
+        let mut args = Self::default();
+        while let [flag, value, rest @ ..] = flags {
+            match *flag {
+                "--correlation" => args.only = Some(value),
+                "--keys" => args.key_dir = Some(value),
+                "--format" => args.format = match *value {
+                    "ndjson" => None,
+                    "syslog" => Some(ExportFormat::Syslog),
+                    "csv" => Some(ExportFormat::Csv),
+                    "jsonl" => Some(ExportFormat::JsonLines),
+                    _ => return None,
+                },
+                _ => return None,
+            }
+            flags = rest;
+        }
+        flags.is_empty().then_some(args)
+    }
+
+    fn summary(&self, n: usize) -> String {
+        let decrypted = if self.key_dir.is_some() { " decrypted" } else { "" };
+        match self.only {
+            Some(id) => format!("exported {n}{decrypted} entries with correlation id {id}"),
+            None => format!("exported {n}{decrypted} entries"),
+        }
+    }
+}
+
+/// Write a redacted copy of `src` to `dst` using the hex salt in
+/// `salt_file`, with the proof beside it as `<dst>.redaction.json`.
+fn redact(src: &str, dst: &str, salt_file: &str) -> Result<usize, IntegrityError> {
+    let redactor = Redactor::from_hex(&std::fs::read_to_string(salt_file)?)?;
+    let proof = audit_log::redact_log(src, dst, &redactor)?;
+    let json = serde_json::to_vec_pretty(&proof).expect("RedactionProof must serialise");
+    std::fs::write(format!("{dst}.redaction.json"), json)?;
+    Ok(proof.links.len())
+}
+
+/// Check the redacted log `log` against the proof file `proof`.
+fn check_redaction(log: &str, proof: &str) -> Result<usize, IntegrityError> {
+    let proof: RedactionProof = serde_json::from_slice(&std::fs::read(proof)?)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    proof.check_redacted(log)?;
+    Ok(proof.links.len())
+}
+
+/// List each correlation id in `log` with its entry count and time span.
+fn correlations(log: &str) -> Result<usize, IntegrityError> {
+    let mut stdout = std::io::stdout().lock();
+    let groups = audit_log::group_by_correlation(log)?;
+    for (id, entries) in &groups {
+        let (first, last) = (&entries[0], &entries[entries.len() - 1]);
+        writeln!(stdout, "{id}\t{}\t{}\t{}", entries.len(), first.timestamp.to_rfc3339(), last.timestamp.to_rfc3339())?;
+    }
+    Ok(groups.len())
+}
+
+/// List transactions in `log` that began but never committed or rolled back.
+fn incomplete(log: &str) -> Result<usize, IntegrityError> {
+    let mut stdout = std::io::stdout().lock();
+    let open = audit_log::incomplete_transactions(log)?;
+    for tx in &open {
+        writeln!(stdout, "{}\tbegan at entry {}\t{}/{} operations applied",
+            tx.tx_id, tx.begin.seq, tx.applied.len(), tx.planned_ops.len())?;
+    }
+    Ok(open.len())
+}
+
+/// List every logged action on `path` in `log` and name the one that
+/// produced the file's current bytes.
+fn timeline(log: &str, path: &str) -> Result<String, IntegrityError> {
+    let mut stdout = std::io::stdout().lock();
+    let timeline = Timeline::replay(log)?;
+    let path = std::path::Path::new(path);
+    for event in timeline.history(path) {
+        let effect = if timeline.took_effect(event) { "applied" } else { "undone" };
+        writeln!(stdout, "{}\t{}\t{:?}\t{}\t{effect}",
+            event.seq, event.timestamp.to_rfc3339(), event.action, event.tx_id.as_deref().unwrap_or("-"))?;
+    }
+    Ok(match timeline.current_producer(path)? {
+        Some(event) => format!("{}: current content produced by entry {}", path.display(), event.seq),
+        None => format!("{}: current content not produced by any logged action", path.display()),
+    })
+}
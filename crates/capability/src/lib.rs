@@ -19,4 +19,4 @@
 pub mod audit_log;
 
 pub use dir_capability::{DirCapability, Permissions, CapabilityError};
-pub use audit_log::{AuditLog, LogEntry, IntegrityError, Operation};
+pub use audit_log::{AppendTicket, AuditContext, AuditHandle, AuditLayer, AuditLog, AuditLogOptions, Change, Checkpoint, CheckpointKey, Durability, EntryMeta, HashAlgorithm, LogAnchor, LogEntry, LogFormat, LogHeader, IntegrityError, Operation, Outcome, RecoveryMode, Rotation, SchemaRegistry, VerifyOptions, VerifyReport};
@@ -0,0 +1,1780 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+// Integration tests for the `capability::audit_log` module.
+// Covers: crash recovery of torn tails, corruption detection, canonical
+// versioned encoding with sequence numbers, legacy migration, durability
+// modes, the binary storage format, segment rotation, the tail index,
+// audit context with correlation queries, outcomes with content hashes,
+// schema-validated custom operations, full-scan verification reports,
+// injectable clocks with monotonic timestamps, encryption at rest,
+// redaction with proofs, the tracing layer, shared writer handles, live
+// following, SIEM exports, mirrors with reconciliation, compaction into
+// signed checkpoints, log headers with hash-algorithm migration, and
+// per-path timelines.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing_subscriber::layer::SubscriberExt;
+use capability::{AuditLog, AuditLogOptions, Durability, IntegrityError, LogEntry, LogFormat, Operation, RecoveryMode};
+use capability::audit_log::{self, AuditContext, AuditHandle, AuditLayer, Change, Checkpoint, CheckpointKey, Cipher, ClockRegression, ClockSkew, CopyState, EncryptionError, EncryptionKey, EntryMeta, ExportFormat, Exporter, FieldPolicy, FollowEvent, Follower, HashAlgorithm, Keyring, LogAnchor, LogHeader, ManualClock, Outcome, PathAction, Problem, RedactionProof, Redactor, Rotation, SchemaRegistry, Timeline, TxState, VerifyOptions, VerifyReport, SegmentManifest, BINARY_MAGIC, ENTRY_VERSION, EXPORT_COLUMNS, EXPORT_SCHEMA};
+
+// ─── Helpers ────────────────────────────────────────────────────────────────
+
+fn scratch() -> tempfile::TempDir {
+    tempfile::tempdir().expect("create temp dir")
+}
+
+/// Write `n` FileRead entries to a fresh log at `path`.
+fn populate(path: &Path, n: usize) {
+    let mut log = AuditLog::open(path).expect("open audit log");
+    for i in 0..n {
+        log.append(Operation::FileRead { path: format!("file_{i}.txt").into() })
+            .expect("append entry");
+    }
+}
+
+/// Split an NDJSON log into its header line and its entries.
+fn read_entries(path: &Path) -> (String, Vec<LogEntry>) {
+    let content = fs::read_to_string(path).expect("read log");
+    let (header, body) = content.split_once('\n').expect("header line");
+    let entries = body.lines().map(|l| serde_json::from_str(l).expect("parse entry")).collect();
+    (header.to_owned(), entries)
+}
+
+/// Rewrite an NDJSON log as `header` followed by `entries`.
+fn write_entries(path: &Path, header: &str, entries: &[LogEntry]) {
+    let body: String = entries.iter().map(|e| serde_json::to_string(e).expect("serialise") + "\n").collect();
+    fs::write(path, format!("{header}\n{body}")).expect("write log");
+}
+
+/// Write `bytes` to `path` and log it as a FileWrite with content hashes.
+fn logged_write(log: &mut AuditLog, path: &Path, bytes: &str, meta: EntryMeta) {
+    let before = audit_log::content_hash(path).expect("hash before");
+    fs::write(path, bytes).expect("write file");
+    let after = audit_log::content_hash(path).expect("hash after");
+    log.append_with(Operation::FileWrite { path: path.into() }, meta.with_change(Change::Content { before, after }))
+        .expect("append write");
+}
+
+/// Simulate a crash mid-append by writing a partial JSON line.
+fn tear(path: &Path) {
+    let mut f = OpenOptions::new().append(true).open(path).expect("open for tearing");
+    f.write_all(br#"{"timestamp":"2025-01-01T00:00:00Z","prev_ha"#).expect("write torn bytes");
+}
+
+// ─── Crash recovery ─────────────────────────────────────────────────────────
+
+/// Strict mode (the default) must refuse a log with a torn final line.
+#[test]
+fn audit_log_strict_open_rejects_torn_tail() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    populate(&log_path, 2);
+    tear(&log_path);
+
+    match AuditLog::open(&log_path) {
+        Err(IntegrityError::TornTail { bytes, .. }) => assert!(bytes > 0),
+        Err(other) => panic!("expected TornTail, got: {other:?}"),
+        Ok(_) => panic!("strict open must reject a torn tail"),
+    }
+}
+
+/// Quarantine mode moves the torn bytes aside, records the recovery and
+/// leaves a log that verifies cleanly.
+#[test]
+fn audit_log_quarantine_recovers_torn_tail() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    populate(&log_path, 2);
+    let valid_len = fs::metadata(&log_path).expect("stat log").len();
+    tear(&log_path);
+
+    let options = AuditLogOptions { recovery: RecoveryMode::Quarantine, ..Default::default() };
+    let mut log = AuditLog::open_with(&log_path, options).expect("recovering open");
+    log.append(Operation::FileRead { path: "after.txt".into() }).expect("append after recovery");
+    drop(log);
+
+    // Two originals + RecoveredFromCrash + one new entry, all chained.
+    assert_eq!(AuditLog::verify(&log_path).expect("verify recovered log"), 4);
+
+    let quarantined: Vec<_> = fs::read_dir(tmp.path())
+        .expect("list dir")
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_string_lossy().starts_with("audit.log.quarantine-"))
+        .collect();
+    assert_eq!(quarantined.len(), 1, "exactly one quarantine file expected");
+    let torn = fs::read(quarantined[0].path()).expect("read quarantine");
+    assert!(torn.starts_with(b"{\"timestamp\""));
+
+    let recovery_line = fs::read_to_string(&log_path).expect("read log")
+        [valid_len as usize..]
+        .lines()
+        .next()
+        .expect("recovery entry")
+        .to_owned();
+    assert!(recovery_line.contains("RecoveredFromCrash"), "got: {recovery_line}");
+}
+
+/// A corrupt line in the middle of the log is an error in every mode.
+#[test]
+fn audit_log_mid_file_corruption_is_an_error() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    populate(&log_path, 3);
+
+    let content = fs::read_to_string(&log_path).expect("read log");
+    let mut lines: Vec<&str> = content.lines().collect();
+    lines[2] = "{ this is not an entry }";
+    fs::write(&log_path, lines.join("\n") + "\n").expect("rewrite log");
+
+    let options = AuditLogOptions { recovery: RecoveryMode::Quarantine, ..Default::default() };
+    match AuditLog::open_with(&log_path, options) {
+        Err(IntegrityError::Deserialisation { line, .. }) => assert_eq!(line, 3),
+        Err(other) => panic!("expected Deserialisation, got: {other:?}"),
+        Ok(_) => panic!("mid-file corruption must not be skipped"),
+    }
+    assert!(matches!(
+        AuditLog::verify(&log_path),
+        Err(IntegrityError::Deserialisation { line: 3, .. })
+    ));
+}
+
+// ─── Canonical encoding ─────────────────────────────────────────────────────
+
+/// The hash input starts with the version byte and big-endian sequence
+/// number, and does not depend on JSON field order.
+#[test]
+fn audit_log_hash_input_is_canonical() {
+    let line = r#"{"version":1,"seq":7,"timestamp":"2025-01-01T00:00:00Z","prev_hash":"ab","operation":{"FileRead":{"path":"x"}}}"#;
+    let reordered = r#"{"operation":{"FileRead":{"path":"x"}},"prev_hash":"ab","timestamp":"2025-01-01T00:00:00.000+00:00","seq":7,"version":1}"#;
+    let a: LogEntry = serde_json::from_str(line).expect("parse entry");
+    let b: LogEntry = serde_json::from_str(reordered).expect("parse reordered entry");
+
+    let input = a.hash_input();
+    assert_eq!(input[0], ENTRY_VERSION);
+    assert_eq!(&input[1..9], &7u64.to_be_bytes());
+    assert_eq!(&input[9..17], &1_735_689_600i64.to_be_bytes());
+    assert_eq!(input, b.hash_input());
+    assert_eq!(a.hash(), b.hash());
+
+    // A clamped reading is hashed as seconds and nanoseconds too.
+    let with_clamp = |clamped_from: &str| -> LogEntry {
+        let line = line.replace(r#""seq":7,"#, &format!(r#""seq":7,"clamped_from":"{clamped_from}","#));
+        serde_json::from_str(&line).expect("parse clamped entry")
+    };
+    let clamped = with_clamp("2024-12-31T23:59:59.5Z");
+    assert_eq!(clamped.hash_input(), with_clamp("2025-01-01T00:59:59.500+01:00").hash_input());
+    let mut nanos = Vec::new();
+    nanos.extend_from_slice(&[0x06, 0, 0, 0, 2, 0x02]);
+    nanos.extend_from_slice(&1_735_689_599i64.to_be_bytes());
+    nanos.push(0x02);
+    nanos.extend_from_slice(&500_000_000i64.to_be_bytes());
+    assert!(clamped.hash_input().windows(nanos.len()).any(|w| w == nanos.as_slice()));
+}
+
+/// Deleting a line and re-chaining the survivors is caught by the
+/// sequence numbers even though every prev_hash link is valid.
+#[test]
+fn audit_log_deleted_and_rechained_line_is_detected() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    populate(&log_path, 3);
+
+    let (header, mut entries) = read_entries(&log_path);
+    entries.remove(1);
+    entries[1].prev_hash = entries[0].hash();
+    write_entries(&log_path, &header, &entries);
+
+    match AuditLog::verify(&log_path) {
+        Err(IntegrityError::SequenceMismatch { index, expected, found }) => {
+            assert_eq!((index, expected, found), (1, 1, 2));
+        }
+        other => panic!("expected SequenceMismatch, got: {other:?}"),
+    }
+}
+
+/// A legacy (unversioned) log is rejected with a pointer to migration, and
+/// migrating it yields a verifiable version-1 log.
+#[test]
+fn audit_log_legacy_log_migrates_to_current_format() {
+    use ring::digest::{digest, SHA256};
+
+    let tmp = scratch();
+    let legacy = tmp.path().join("legacy.log");
+    let first = r#"{"timestamp":"2024-06-01T12:00:00Z","prev_hash":"0000000000000000000000000000000000000000000000000000000000000000","operation":{"FileRead":{"path":"a.txt"}}}"#;
+    let first_hash: String = digest(&SHA256, first.as_bytes()).as_ref()
+        .iter().map(|b| format!("{b:02x}")).collect();
+    let second = format!(
+        r#"{{"timestamp":"2024-06-01T12:00:01Z","prev_hash":"{first_hash}","operation":{{"FileDelete":{{"path":"a.txt"}}}}}}"#
+    );
+    fs::write(&legacy, format!("{first}\n{second}\n")).expect("write legacy log");
+
+    assert!(matches!(AuditLog::verify(&legacy), Err(IntegrityError::LegacyEntry { line: 1 })));
+
+    let migrated = tmp.path().join("audit.log");
+    let count = audit_log::migrate_legacy(&legacy, &migrated).expect("migrate");
+    assert_eq!(count, 2);
+    assert_eq!(AuditLog::verify(&migrated).expect("verify migrated log"), 3);
+
+    let last = fs::read_to_string(&migrated).expect("read migrated").lines().last().unwrap().to_owned();
+    let entry: LogEntry = serde_json::from_str(&last).expect("parse last entry");
+    match entry.operation {
+        Operation::LegacyMigrated { legacy_head, entries, .. } => {
+            assert_eq!(entries, 2);
+            assert_eq!(legacy_head.len(), 64);
+        }
+        other => panic!("expected LegacyMigrated, got: {other:?}"),
+    }
+}
+
+// ─── Durability modes ───────────────────────────────────────────────────────
+
+/// In flush-only mode tickets stay non-durable until `flush` is called.
+#[test]
+fn audit_log_on_flush_tickets_become_durable_after_flush() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let options = AuditLogOptions { durability: Durability::OnFlush, ..Default::default() };
+    let mut log = AuditLog::open_with(&log_path, options).expect("open audit log");
+
+    let tickets: Vec<_> = (0..3)
+        .map(|i| log.append(Operation::FileRead { path: format!("{i}").into() }).expect("append"))
+        .collect();
+    assert!(tickets.iter().all(|t| !t.is_durable()));
+    assert_eq!(tickets[2].seq(), 2);
+
+    log.flush().expect("flush");
+    assert!(tickets.iter().all(|t| t.is_durable()));
+    drop(log);
+    assert_eq!(AuditLog::verify(&log_path).expect("verify"), 3);
+}
+
+/// Group commit syncs once the batch is full, and the background writer
+/// syncs a partial batch after the delay elapses.
+#[test]
+fn audit_log_group_commit_syncs_by_count_and_by_delay() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let options = AuditLogOptions {
+        durability: Durability::GroupCommit { max_entries: 4, max_delay: Duration::from_millis(20) },
+        ..Default::default()
+    };
+    let mut log = AuditLog::open_with(&log_path, options).expect("open audit log");
+
+    let batch: Vec<_> = (0..4)
+        .map(|i| log.append(Operation::FileRead { path: format!("{i}").into() }).expect("append"))
+        .collect();
+    batch[3].wait_durable().expect("full batch becomes durable");
+    assert!(batch.iter().all(|t| t.is_durable()));
+
+    let straggler = log.append(Operation::FileRead { path: "late".into() }).expect("append");
+    straggler.wait_durable().expect("partial batch becomes durable after delay");
+    assert_eq!(straggler.hash().len(), 64);
+    drop(log);
+    assert_eq!(AuditLog::verify(&log_path).expect("verify"), 5);
+}
+
+// ─── Binary format ──────────────────────────────────────────────────────────
+
+/// Converting NDJSON → binary → NDJSON is lossless, the binary file is
+/// smaller, and verify agrees on both.
+#[test]
+fn audit_log_binary_conversion_round_trips_losslessly() {
+    let tmp = scratch();
+    let ndjson = tmp.path().join("audit.log");
+    let binary = tmp.path().join("audit.bin");
+    let back = tmp.path().join("audit.back.log");
+    populate(&ndjson, 20);
+
+    assert_eq!(audit_log::convert(&ndjson, &binary, LogFormat::Binary).expect("to binary"), 20);
+    assert_eq!(audit_log::convert(&binary, &back, LogFormat::Ndjson).expect("to ndjson"), 20);
+
+    assert_eq!(LogFormat::detect(&binary).expect("detect"), Some(LogFormat::Binary));
+    assert!(fs::read(&binary).expect("read binary").starts_with(BINARY_MAGIC));
+    assert_eq!(fs::read(&ndjson).expect("read"), fs::read(&back).expect("read back"));
+    assert!(fs::metadata(&binary).unwrap().len() * 2 < fs::metadata(&ndjson).unwrap().len());
+
+    assert_eq!(AuditLog::verify(&binary).expect("verify binary"), 20);
+
+    // Appending to the binary log keeps it binary and chained.
+    let mut log = AuditLog::open(&binary).expect("reopen binary log");
+    log.append(Operation::FileDelete { path: "gone.txt".into() }).expect("append");
+    drop(log);
+    assert_eq!(AuditLog::verify(&binary).expect("verify binary"), 21);
+}
+
+/// A broken chain produces the same verify error in either format, and a
+/// flipped payload byte is caught by the record checksum.
+#[test]
+fn audit_log_binary_verify_matches_ndjson_and_checks_records() {
+    let tmp = scratch();
+    let ndjson = tmp.path().join("audit.log");
+    let binary = tmp.path().join("audit.bin");
+    populate(&ndjson, 3);
+
+    let (header, mut entries) = read_entries(&ndjson);
+    entries[2].prev_hash = "00".repeat(32);
+    write_entries(&ndjson, &header, &entries);
+    audit_log::convert(&ndjson, &binary, LogFormat::Binary).expect("convert");
+
+    let a = AuditLog::verify(&ndjson).expect_err("ndjson chain is broken");
+    let b = AuditLog::verify(&binary).expect_err("binary chain is broken");
+    assert_eq!(a.to_string(), b.to_string());
+
+    let mut bytes = fs::read(&binary).expect("read binary");
+    let first_payload = BINARY_MAGIC.len() + 4;
+    bytes[first_payload + 2] ^= 0x40;
+    fs::write(&binary, bytes).expect("write corrupted binary");
+    assert!(matches!(
+        AuditLog::verify(&binary),
+        Err(IntegrityError::ChecksumMismatch { record: 1, .. })
+    ));
+}
+
+/// A binary log with a torn final record is recovered like an NDJSON one.
+#[test]
+fn audit_log_binary_torn_record_is_quarantined() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.bin");
+    let options = AuditLogOptions { format: LogFormat::Binary, ..Default::default() };
+    let mut log = AuditLog::open_with(&log_path, options).expect("open binary log");
+    for i in 0..3 {
+        log.append(Operation::FileRead { path: format!("{i}").into() }).expect("append");
+    }
+    drop(log);
+
+    let len = fs::metadata(&log_path).expect("stat").len();
+    OpenOptions::new().write(true).open(&log_path).expect("open")
+        .set_len(len - 5).expect("truncate mid-record");
+    assert!(matches!(AuditLog::open(&log_path), Err(IntegrityError::TornTail { .. })));
+
+    let options = AuditLogOptions { recovery: RecoveryMode::Quarantine, ..Default::default() };
+    drop(AuditLog::open_with(&log_path, options).expect("recovering open"));
+    assert_eq!(AuditLog::verify(&log_path).expect("verify recovered log"), 3);
+}
+
+// ─── Segmentation ───────────────────────────────────────────────────────────
+
+/// Open a log that seals a segment roughly every two entries.
+fn open_rotating(path: &Path) -> AuditLog {
+    let options = AuditLogOptions { rotation: Rotation::by_size(300), ..Default::default() };
+    AuditLog::open_with(path, options).expect("open rotating log")
+}
+
+/// Size-based rotation splits the log into numbered segments whose chain
+/// continues across boundaries, including after a reopen.
+#[test]
+fn audit_log_rotation_chains_across_segments() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let mut log = open_rotating(&log_path);
+    for i in 0..6 {
+        log.append(Operation::FileRead { path: format!("file_{i}.txt").into() }).expect("append");
+    }
+    drop(log);
+    let mut log = open_rotating(&log_path);
+    let ticket = log.append(Operation::FileRead { path: "after_reopen.txt".into() }).expect("append");
+    assert_eq!(ticket.seq(), 6);
+    drop(log);
+
+    let manifest = SegmentManifest::load(&log_path).expect("load").expect("log is segmented");
+    assert!(manifest.segments.len() >= 2, "expected several segments, got {manifest:?}");
+    assert!(tmp.path().join("audit.log.000001").exists());
+    for pair in manifest.segments.windows(2) {
+        assert_eq!(pair[1].start_hash, pair[0].head_hash);
+        assert_eq!(pair[1].first_seq, pair[0].first_seq + pair[0].entries);
+    }
+    assert_eq!(AuditLog::verify(&log_path).expect("verify segmented log"), 7);
+    assert_eq!(AuditLog::entries(&log_path).expect("entries").count(), 7);
+}
+
+/// Old segments can be archived: verification resumes from a later
+/// boundary, and an archived copy still verifies against the manifest.
+#[test]
+fn audit_log_segments_can_be_archived() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let mut log = open_rotating(&log_path);
+    for i in 0..6 {
+        log.append(Operation::FileRead { path: format!("file_{i}.txt").into() }).expect("append");
+    }
+    drop(log);
+
+    let manifest = SegmentManifest::load(&log_path).expect("load").expect("log is segmented");
+    let archived = tmp.path().join("archived.000001");
+    fs::rename(tmp.path().join("audit.log.000001"), &archived).expect("archive segment 1");
+
+    assert!(AuditLog::verify(&log_path).is_err(), "full verification needs segment 1");
+    let first = &manifest.segments[0];
+    let resumed = AuditLog::verify_from_segment(&log_path, 2).expect("resume from segment 2");
+    assert_eq!(resumed as u64, 6 - first.entries);
+    assert_eq!(manifest.verify_segment(1, &archived).expect("verify archive"), first.entries as usize);
+}
+
+/// A sealed segment that was rewritten with a valid internal chain is
+/// still caught by the manifest's boundary hashes.
+#[test]
+fn audit_log_tampered_segment_fails_manifest_check() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let mut log = open_rotating(&log_path);
+    for i in 0..6 {
+        log.append(Operation::FileRead { path: format!("file_{i}.txt").into() }).expect("append");
+    }
+    drop(log);
+
+    // Rebuild segment 2 as a self-consistent chain over altered content.
+    let segment = tmp.path().join("audit.log.000002");
+    let original: Vec<LogEntry> = AuditLog::entries(&segment).expect("read segment")
+        .map(|e| e.expect("entry"))
+        .collect();
+    let mut prev = original[0].prev_hash.clone();
+    let mut forged = String::new();
+    for entry in &original {
+        let mut entry = entry.clone();
+        entry.prev_hash = prev;
+        entry.operation = Operation::FileRead { path: "forged.txt".into() };
+        prev = entry.hash();
+        forged += &(serde_json::to_string(&entry).expect("serialise") + "\n");
+    }
+    fs::write(&segment, forged).expect("write forged segment");
+
+    assert!(matches!(
+        AuditLog::verify(&log_path),
+        Err(IntegrityError::ManifestMismatch { segment: 2, field: "head_hash" })
+    ));
+}
+
+// ─── Tail index ─────────────────────────────────────────────────────────────
+
+/// Open trusts an index that agrees with the log's tail without reading the
+/// body, and rebuilds one that does not.
+#[test]
+fn audit_log_open_uses_index_and_rebuilds_stale_one() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let idx_path = tmp.path().join("audit.log.idx");
+    populate(&log_path, 3);
+    assert!(idx_path.exists());
+
+    // Same-length tampering in the middle is invisible to open (only the
+    // tail is read) but not to verify.
+    let content = fs::read_to_string(&log_path).expect("read log");
+    fs::write(&log_path, content.replacen("file_1.txt", "file_X.txt", 1)).expect("tamper");
+    let ticket = AuditLog::open(&log_path).expect("open via index")
+        .append(Operation::FileRead { path: "next.txt".into() }).expect("append");
+    assert_eq!(ticket.seq(), 3);
+    assert!(matches!(AuditLog::verify(&log_path), Err(IntegrityError::ChainBroken { index: 2, .. })));
+
+    // A garbage index is discarded and rebuilt from the log.
+    fs::write(&idx_path, b"not an index").expect("clobber index");
+    let ticket = AuditLog::open(&log_path).expect("open with rebuilt index")
+        .append(Operation::FileRead { path: "again.txt".into() }).expect("append");
+    assert_eq!(ticket.seq(), 4);
+    let last = AuditLog::entry(&log_path, 4).expect("read entry").expect("entry 4 exists");
+    assert_eq!(last.hash(), ticket.hash());
+}
+
+/// Entries written after the last indexed one are picked up on open.
+#[test]
+fn audit_log_index_catches_up_with_unindexed_entries() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let idx_path = tmp.path().join("audit.log.idx");
+    populate(&log_path, 4);
+
+    // Drop the last two index records, as if the index writes were lost.
+    let len = fs::metadata(&idx_path).expect("stat index").len();
+    OpenOptions::new().write(true).open(&idx_path).expect("open index")
+        .set_len(len - 2 * 52).expect("truncate index");
+
+    let ticket = AuditLog::open(&log_path).expect("open")
+        .append(Operation::FileRead { path: "next.txt".into() }).expect("append");
+    assert_eq!(ticket.seq(), 4);
+    assert_eq!(fs::metadata(&idx_path).expect("stat index").len(), len + 52);
+    assert_eq!(AuditLog::verify(&log_path).expect("verify"), 5);
+}
+
+/// Random access by sequence number works across segments, with or
+/// without index files.
+#[test]
+fn audit_log_entry_random_access_across_segments() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let mut log = open_rotating(&log_path);
+    for i in 0..6 {
+        log.append(Operation::FileRead { path: format!("file_{i}.txt").into() }).expect("append");
+    }
+    drop(log);
+
+    let all: Vec<LogEntry> = AuditLog::entries(&log_path).expect("entries")
+        .map(|e| e.expect("entry"))
+        .collect();
+    let check = || {
+        for (seq, expected) in all.iter().enumerate() {
+            let found = AuditLog::entry(&log_path, seq as u64).expect("lookup").expect("entry exists");
+            assert_eq!(found.hash(), expected.hash(), "entry {seq}");
+        }
+        assert!(AuditLog::entry(&log_path, 6).expect("lookup").is_none());
+    };
+    check();
+
+    for file in fs::read_dir(tmp.path()).expect("list dir") {
+        let file = file.expect("dir entry").path();
+        if file.extension().is_some_and(|ext| ext == "idx") {
+            fs::remove_file(file).expect("remove index");
+        }
+    }
+    check();
+}
+
+// ─── Audit context ──────────────────────────────────────────────────────────
+
+/// A context set on the log is stamped on (and hashed into) each entry; a
+/// scoped context applies only until the guard is dropped.
+#[test]
+fn audit_log_context_is_stamped_and_scoped() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let base = AuditContext::current().with_actor("alice").with_session_id("s-1");
+    let options = AuditLogOptions { context: Some(base.clone()), ..Default::default() };
+    let mut log = AuditLog::open_with(&log_path, options).expect("open");
+
+    log.append(Operation::FileRead { path: "a".into() }).expect("append");
+    {
+        let mut run = log.scoped(base.clone().with_correlation_id("fix-1"));
+        run.append(Operation::GitStatusChecked { repo_path: "repo".into() }).expect("append");
+        run.append(Operation::FileWrite { path: "repo/x".into() }).expect("append");
+    }
+    log.append(Operation::FileRead { path: "b".into() }).expect("append");
+    drop(log);
+
+    let entries: Vec<LogEntry> = AuditLog::entries(&log_path).expect("entries")
+        .map(|e| e.expect("entry"))
+        .collect();
+    let context = entries[0].context.as_ref().expect("context stamped");
+    assert_eq!(context.actor.as_deref(), Some("alice"));
+    assert_eq!(context.pid, Some(std::process::id()));
+    assert_eq!(entries[1].correlation_id(), Some("fix-1"));
+    assert_eq!(entries[3].context.as_ref(), Some(&base), "scope restores the base context");
+
+    // The context is covered by the chain.
+    let tampered = fs::read_to_string(&log_path).expect("read").replacen("alice", "mallory", 1);
+    fs::write(&log_path, tampered).expect("write");
+    assert!(matches!(AuditLog::verify(&log_path), Err(IntegrityError::ChainBroken { index: 1, .. })));
+}
+
+/// Entries are grouped by correlation id, in either storage format.
+#[test]
+fn audit_log_groups_entries_by_correlation_id() {
+    let tmp = scratch();
+    for format in [LogFormat::Ndjson, LogFormat::Binary] {
+        let log_path = tmp.path().join(format!("audit-{format:?}.log"));
+        let mut log = AuditLog::open_with(&log_path, AuditLogOptions { format, ..Default::default() }).expect("open");
+        for (i, run) in ["run-a", "run-b", "run-a"].iter().enumerate() {
+            log.set_context(AuditContext::default().with_correlation_id(*run));
+            log.append(Operation::FileRead { path: format!("{i}").into() }).expect("append");
+        }
+        log.set_context(AuditContext::default());
+        log.append(Operation::FileRead { path: "uncorrelated".into() }).expect("append");
+        drop(log);
+
+        let groups = audit_log::group_by_correlation(&log_path).expect("group");
+        assert_eq!(groups.keys().collect::<Vec<_>>(), ["run-a", "run-b"]);
+        assert_eq!(groups["run-a"].iter().map(|e| e.seq).collect::<Vec<_>>(), [0, 2]);
+        assert_eq!(audit_log::correlated_entries(&log_path, "run-b").expect("query").len(), 1);
+        assert!(AuditLog::entries(&log_path).expect("entries").last().expect("entry").expect("entry").context.is_none());
+    }
+}
+
+// ─── Outcomes and changes ───────────────────────────────────────────────────
+
+/// Outcome and before/after content hashes survive both formats and are
+/// covered by the chain; plain appends carry neither.
+#[test]
+fn audit_log_records_outcome_and_content_change() {
+    let tmp = scratch();
+    let target = tmp.path().join("notes.txt");
+    fs::write(&target, "old").expect("write old");
+    let before = audit_log::content_hash(&target).expect("hash before");
+    fs::write(&target, "new").expect("write new");
+    let after = audit_log::content_hash(&target).expect("hash after");
+    assert_ne!(before, after);
+    assert_eq!(audit_log::content_hash(tmp.path().join("missing")).expect("hash missing"), None);
+
+    let write = EntryMeta::outcome(Outcome::Success)
+        .with_change(Change::Content { before: before.clone(), after: after.clone() });
+    let failed = EntryMeta::outcome(Outcome::Failure { error: "permission denied".into() });
+    for format in [LogFormat::Ndjson, LogFormat::Binary] {
+        let log_path = tmp.path().join(format!("audit-{format:?}.log"));
+        let mut log = AuditLog::open_with(&log_path, AuditLogOptions { format, ..Default::default() }).expect("open");
+        log.append_with(Operation::FileWrite { path: target.clone() }, write.clone()).expect("append");
+        log.append_with(Operation::FileDelete { path: target.clone() }, failed.clone()).expect("append");
+        log.append(Operation::FileRead { path: target.clone() }).expect("append");
+        drop(log);
+
+        let entries: Vec<LogEntry> = AuditLog::entries(&log_path).expect("entries")
+            .map(|e| e.expect("entry"))
+            .collect();
+        assert_eq!(entries[0].outcome, Some(Outcome::Success));
+        assert_eq!(entries[0].change, Some(Change::Content { before: before.clone(), after: after.clone() }));
+        assert_eq!(entries[1].outcome, failed.outcome);
+        assert_eq!((entries[2].outcome.clone(), entries[2].change.clone()), (None, None));
+        assert_eq!(AuditLog::verify(&log_path).expect("verify"), 3);
+    }
+
+    let ndjson = tmp.path().join("audit-Ndjson.log");
+    let tampered = fs::read_to_string(&ndjson).expect("read").replacen("permission denied", "ok", 1);
+    fs::write(&ndjson, tampered).expect("write");
+    assert!(matches!(AuditLog::verify(&ndjson), Err(IntegrityError::ChainBroken { index: 2, .. })));
+}
+
+// ─── Custom operations ──────────────────────────────────────────────────────
+
+/// Custom operations round-trip their JSON details in both formats, and a
+/// kind without a component namespace is refused.
+#[test]
+fn audit_log_custom_operations_round_trip() {
+    let tmp = scratch();
+    let details = serde_json::json!({ "repo": "alpha", "fixed": ["a.txt"], "score": 0.5, "ok": true });
+    for format in [LogFormat::Ndjson, LogFormat::Binary] {
+        let log_path = tmp.path().join(format!("audit-{format:?}.log"));
+        let mut log = AuditLog::open_with(&log_path, AuditLogOptions { format, ..Default::default() }).expect("open");
+        log.append(Operation::Custom { kind: "elixir.repo_fixed".into(), details: details.clone() }).expect("append");
+        let err = log.append(Operation::Custom { kind: "NoNamespace".into(), details: details.clone() })
+            .expect_err("kind must be namespaced");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        drop(log);
+
+        let entry = AuditLog::entry(&log_path, 0).expect("read").expect("entry exists");
+        match entry.operation {
+            Operation::Custom { kind, details: found } => {
+                assert_eq!(kind, "elixir.repo_fixed");
+                assert_eq!(found, details);
+            }
+            other => panic!("expected Custom, got {other:?}"),
+        }
+        assert_eq!(AuditLog::verify(&log_path).expect("verify"), 1);
+    }
+}
+
+/// Floats in custom details read back exactly, so the chain after them
+/// still verifies.
+#[test]
+fn audit_log_custom_float_details_keep_chain_intact() {
+    let tmp = scratch();
+    let details = serde_json::json!({ "v": 1.0715660391465826e-75, "w": 0.1 + 0.2 });
+    for format in [LogFormat::Ndjson, LogFormat::Binary] {
+        let log_path = tmp.path().join(format!("audit-{format:?}.log"));
+        let mut log = AuditLog::open_with(&log_path, AuditLogOptions { format, ..Default::default() }).expect("open");
+        log.append(Operation::Custom { kind: "elixir.measured".into(), details: details.clone() }).expect("append");
+        log.append(Operation::FileDelete { path: "after.txt".into() }).expect("append");
+        drop(log);
+
+        assert_eq!(AuditLog::verify(&log_path).expect("verify"), 2);
+        let entry = AuditLog::entry(&log_path, 0).expect("read").expect("entry exists");
+        assert!(matches!(entry.operation, Operation::Custom { details: found, .. } if found == details));
+    }
+}
+
+/// `verify_with` checks custom details against registered schemas.
+#[test]
+fn audit_log_verify_validates_custom_details() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let mut log = AuditLog::open(&log_path).expect("open");
+    log.append(Operation::Custom { kind: "idris.proof_checked".into(), details: serde_json::json!({ "module": "Safe", "ok": true }) })
+        .expect("append");
+    log.append(Operation::Custom { kind: "idris.proof_checked".into(), details: serde_json::json!({ "module": 7 }) })
+        .expect("append");
+    log.append(Operation::Custom { kind: "haskell.lint".into(), details: serde_json::json!(null) }).expect("append");
+    drop(log);
+
+    let schema = serde_json::json!({
+        "type": "object",
+        "required": ["module"],
+        "properties": { "module": { "type": "string" }, "ok": { "type": "boolean" } },
+        "additionalProperties": false,
+    });
+    let schema_dir = tmp.path().join("schemas");
+    fs::create_dir(&schema_dir).expect("mkdir");
+    fs::write(schema_dir.join("idris.proof_checked.schema.json"), schema.to_string()).expect("write schema");
+    let mut schemas = SchemaRegistry::new();
+    assert_eq!(schemas.load_dir(&schema_dir).expect("load schemas"), 1);
+
+    assert_eq!(AuditLog::verify(&log_path).expect("chain alone is intact"), 3);
+    let options = VerifyOptions { schemas: Some(schemas.clone()), ..Default::default() };
+    match AuditLog::verify_with(&log_path, &options) {
+        Err(IntegrityError::InvalidCustomDetails { index, kind, reason }) => {
+            assert_eq!((index, kind.as_str()), (1, "idris.proof_checked"));
+            assert!(reason.contains("/module"), "got: {reason}");
+        }
+        other => panic!("expected InvalidCustomDetails, got {other:?}"),
+    }
+
+    // Unsupported keywords are refused rather than silently ignored.
+    let err = schemas.register("idris.other", serde_json::json!({ "pattern": "^a" })).expect_err("unsupported");
+    assert!(err.to_string().contains("/pattern"), "got: {err}");
+}
+
+// ─── Verification reports ───────────────────────────────────────────────────
+
+/// One scan reports an unparseable line, a timestamp regression, a
+/// duplicate, a broken link and a gap, and the ranges between them.
+#[test]
+fn audit_log_report_lists_every_problem_and_consistent_ranges() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    populate(&log_path, 8);
+
+    let (header, mut entries) = read_entries(&log_path);
+    // A correctly re-chained entry whose clock went backwards.
+    entries[3].timestamp = entries[0].timestamp - chrono::Duration::hours(1);
+    for i in 4..entries.len() {
+        entries[i].prev_hash = entries[i - 1].hash();
+    }
+    let mut lines: Vec<String> = entries.iter().map(|e| serde_json::to_string(e).expect("serialise")).collect();
+    lines[1] = "{ this is not an entry }".into();
+    lines.insert(5, lines[4].clone());
+    lines.remove(7); // seq 6
+    lines.insert(0, header);
+    fs::write(&log_path, lines.join("\n") + "\n").expect("rewrite log");
+
+    let report = AuditLog::verify_report(&log_path).expect("report");
+    assert!(!report.is_clean());
+    assert_eq!(report.entries, 7);
+    let problems: Vec<(usize, &Problem)> = report.findings.iter().map(|f| (f.record, &f.problem)).collect();
+    assert!(matches!(problems[0], (3, Problem::Unparseable { .. })));
+    assert!(matches!(problems[1], (5, Problem::TimestampRegression { .. })));
+    assert!(matches!(problems[2], (7, Problem::Duplicate { first_record: 6, .. })));
+    assert!(matches!(problems[3], (9, Problem::BrokenLink { .. })));
+    assert!(matches!(problems[4], (9, Problem::Gap { expected_seq: 6, found_seq: 7 })));
+    assert_eq!(problems.len(), 5);
+
+    let ranges: Vec<(u64, u64)> = report.consistent_ranges.iter().map(|r| (r.first_seq, r.last_seq)).collect();
+    assert_eq!(ranges, [(0, 0), (2, 5), (7, 7)]);
+    assert_eq!(report.consistent_ranges[1].head_hash, entries[5].hash());
+}
+
+/// A clean segmented log reports one range covering every entry; with a
+/// segment missing the scan resumes from the manifest, and the report
+/// round-trips through JSON.
+#[test]
+fn audit_log_report_is_serialisable_and_resumes_past_missing_segment() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let mut log = open_rotating(&log_path);
+    for i in 0..12 {
+        log.append(Operation::FileRead { path: format!("file_{i}.txt").into() }).expect("append");
+    }
+    drop(log);
+
+    let clean = AuditLog::verify_report(&log_path).expect("report");
+    assert!(clean.is_clean(), "unexpected findings: {:?}", clean.findings);
+    assert_eq!(clean.consistent_ranges.len(), 1);
+    assert_eq!(clean.consistent_ranges[0].entries, 12);
+
+    let manifest = SegmentManifest::load(&log_path).expect("load").expect("segmented");
+    let first = &manifest.segments[0];
+    fs::remove_file(tmp.path().join(&first.file)).expect("remove segment");
+
+    let report = AuditLog::verify_report(&log_path).expect("report");
+    assert_eq!(report.findings.len(), 1);
+    assert_eq!(report.findings[0].file, first.file);
+    assert_eq!(report.findings[0].problem, Problem::MissingFile);
+    assert_eq!(report.consistent_ranges.len(), 1);
+    assert_eq!(report.consistent_ranges[0].first_seq, first.entries);
+    assert_eq!(report.consistent_ranges[0].start_hash, first.head_hash);
+
+    let json = serde_json::to_value(&report).expect("serialise");
+    assert_eq!(json["findings"][0]["problem"], "missing_file");
+    let back: VerifyReport = serde_json::from_value(json).expect("deserialise");
+    assert_eq!(back, report);
+}
+
+// ─── Clocks and timestamps ──────────────────────────────────────────────────
+
+fn at(secs: i64) -> chrono::DateTime<chrono::Utc> {
+    chrono::DateTime::from_timestamp(1_750_000_000 + secs, 0).expect("valid timestamp")
+}
+
+/// A clock that steps backwards is clamped to the last entry's time, the
+/// reading is kept on the entry, and the clamp survives reopening in both
+/// formats and shows up in the report.
+#[test]
+fn audit_log_backwards_clock_is_clamped_and_flagged() {
+    let tmp = scratch();
+    for format in [LogFormat::Ndjson, LogFormat::Binary] {
+        let log_path = tmp.path().join(format!("audit-{format:?}.log"));
+        let clock = ManualClock::new(at(100));
+        let options = || AuditLogOptions { format, clock: Some(Arc::new(clock.clone())), ..Default::default() };
+
+        let mut log = AuditLog::open_with(&log_path, options()).expect("open");
+        log.append(Operation::FileRead { path: "a".into() }).expect("append");
+        drop(log);
+
+        clock.set(at(40));
+        let mut log = AuditLog::open_with(&log_path, options()).expect("reopen");
+        log.append(Operation::FileRead { path: "b".into() }).expect("append behind the clock");
+        clock.advance(chrono::Duration::seconds(100));
+        log.append(Operation::FileRead { path: "c".into() }).expect("append after catching up");
+        drop(log);
+
+        let entries: Vec<LogEntry> = AuditLog::entries(&log_path).expect("entries").map(|e| e.expect("entry")).collect();
+        let stamps: Vec<_> = entries.iter().map(|e| (e.timestamp, e.clamped_from)).collect();
+        assert_eq!(stamps, [(at(100), None), (at(100), Some(at(40))), (at(140), None)]);
+        assert_eq!(AuditLog::verify(&log_path).expect("verify"), 3);
+
+        let report = AuditLog::verify_report(&log_path).expect("report");
+        assert_eq!(report.findings.len(), 1);
+        assert_eq!(report.findings[0].problem, Problem::ClampedTimestamp { clock: at(40), recorded: at(100) });
+    }
+}
+
+/// With `ClockSkew::Reject` a backwards clock fails the append and writes
+/// nothing; a log whose timestamps regress anyway fails `verify`.
+#[test]
+fn audit_log_rejects_clock_regression_and_verify_detects_it() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let clock = ManualClock::new(at(10));
+    let options = AuditLogOptions {
+        clock: Some(Arc::new(clock.clone())),
+        clock_skew: ClockSkew::Reject,
+        ..Default::default()
+    };
+    let mut log = AuditLog::open_with(&log_path, options).expect("open");
+    log.append(Operation::FileRead { path: "a".into() }).expect("append");
+    log.append(Operation::FileRead { path: "b".into() }).expect("append at the same instant");
+    clock.set(at(5));
+    let err = log.append(Operation::FileRead { path: "c".into() }).expect_err("clock went backwards");
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    let regression = err.get_ref().and_then(|e| e.downcast_ref::<ClockRegression>()).expect("ClockRegression");
+    assert_eq!((regression.last, regression.now), (at(10), at(5)));
+    drop(log);
+    assert_eq!(AuditLog::verify(&log_path).expect("verify"), 2);
+
+    // A correctly chained log whose timestamps regress.
+    let (header, mut entries) = read_entries(&log_path);
+    entries[1].timestamp = at(0);
+    write_entries(&log_path, &header, &entries);
+    fs::remove_file(tmp.path().join("audit.log.idx")).expect("remove index");
+
+    match AuditLog::verify(&log_path) {
+        Err(IntegrityError::TimestampRegression { index, previous, found }) => {
+            assert_eq!((index, previous, found), (1, at(10), at(0)));
+        }
+        other => panic!("expected TimestampRegression, got {other:?}"),
+    }
+}
+
+// ─── Encryption at rest ─────────────────────────────────────────────────────
+
+/// Sealed entries keep paths out of the file, verify without the key and
+/// open with it, in both formats and with both ciphers.
+#[test]
+fn audit_log_encrypted_entries_verify_without_key() {
+    let tmp = scratch();
+    for (format, cipher) in [(LogFormat::Ndjson, Cipher::Aes256Gcm), (LogFormat::Binary, Cipher::ChaCha20Poly1305)] {
+        let log_path = tmp.path().join(format!("audit-{format:?}.log"));
+        let key = EncryptionKey::generate("k1").expect("generate key");
+        let keyring = Keyring::new(cipher, key);
+        let options = AuditLogOptions { format, encryption: Some(keyring.clone()), ..Default::default() };
+        let mut log = AuditLog::open_with(&log_path, options).expect("open");
+        log.append(Operation::GitCommitCreated { repo_path: "customer-secret-repo".into(), message: "fix".into() })
+            .expect("append");
+        log.append_with(Operation::FileWrite { path: "customer-secret-file".into() }, EntryMeta::outcome(Outcome::Success))
+            .expect("append");
+        drop(log);
+
+        let bytes = fs::read(&log_path).expect("read log");
+        assert!(!bytes.windows(15).any(|w| w == b"customer-secret"), "plaintext leaked into {format:?} log");
+        assert_eq!(AuditLog::verify(&log_path).expect("verify without key"), 2);
+        assert!(AuditLog::verify_report(&log_path).expect("report").is_clean());
+        let sealed = AuditLog::entry(&log_path, 1).expect("read").expect("entry");
+        assert!(matches!(&sealed.operation, Operation::Encrypted { key_id, .. } if key_id == "k1"));
+
+        let opened: Vec<LogEntry> = AuditLog::decrypted_entries(&log_path, &keyring).expect("entries")
+            .map(|e| e.expect("decrypt"))
+            .collect();
+        assert!(matches!(&opened[0].operation, Operation::GitCommitCreated { repo_path, .. } if repo_path.ends_with("customer-secret-repo")));
+        assert!(matches!(&opened[1].operation, Operation::FileWrite { .. }));
+        assert_eq!(opened[1].outcome, Some(Outcome::Success));
+        assert_eq!(opened[1].seq, sealed.seq);
+    }
+}
+
+/// After a key rotation each entry opens with the key that sealed it, and
+/// ciphertext moved to another entry fails to decrypt even when re-chained.
+#[test]
+fn audit_log_key_rotation_and_ciphertext_binding() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let (k1, k2) = (EncryptionKey::generate("k1").expect("key"), EncryptionKey::generate("k2").expect("key"));
+    let options = AuditLogOptions { encryption: Some(Keyring::new(Cipher::Aes256Gcm, k1.clone())), ..Default::default() };
+    let mut log = AuditLog::open_with(&log_path, options).expect("open");
+    log.append(Operation::FileRead { path: "one".into() }).expect("append");
+    log.rotate_key(k2.clone());
+    log.append(Operation::FileRead { path: "two".into() }).expect("append");
+    drop(log);
+
+    let mut only_new = Keyring::read_only();
+    only_new.add(EncryptionKey::from_hex("k2", &k2.to_hex()).expect("reload key"));
+    let results: Vec<_> = AuditLog::decrypted_entries(&log_path, &only_new).expect("entries").collect();
+    assert_eq!(results.len(), 1, "iteration stops at the first error");
+    assert!(matches!(&results[0], Err(IntegrityError::Encryption(EncryptionError::UnknownKey(id))) if id == "k1"));
+
+    let key_dir = tmp.path().join("keys");
+    fs::create_dir(&key_dir).expect("mkdir");
+    for key in [&k1, &k2] {
+        fs::write(key_dir.join(format!("{}.key", key.id())), key.to_hex()).expect("write key file");
+    }
+    let mut both = Keyring::read_only();
+    assert_eq!(both.load_dir(&key_dir).expect("load keys"), 2);
+    let paths: Vec<_> = AuditLog::decrypted_entries(&log_path, &both).expect("entries")
+        .map(|e| match e.expect("decrypt").operation {
+            Operation::FileRead { path } => path,
+            other => panic!("expected FileRead, got {other:?}"),
+        })
+        .collect();
+    assert_eq!(paths, [Path::new("one"), Path::new("two")]);
+
+    // Transplant entry 1's ciphertext into entry 0 and re-chain.
+    let (header, mut entries) = read_entries(&log_path);
+    entries[0].operation = entries[1].operation.clone();
+    entries[1].prev_hash = entries[0].hash();
+    write_entries(&log_path, &header, &entries);
+    assert_eq!(AuditLog::verify(&log_path).expect("chain re-forged"), 2);
+    assert!(matches!(both.open(&entries[0]), Err(EncryptionError::Decrypt { seq: 0, .. })));
+}
+
+/// A sealed entry whose clock was clamped opens, and its clamped reading is
+/// authenticated: changing it fails decryption.
+#[test]
+fn audit_log_sealed_entry_binds_clamped_reading() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let clock = ManualClock::new(at(100));
+    let keyring = Keyring::new(Cipher::Aes256Gcm, EncryptionKey::generate("k1").expect("key"));
+    let options = AuditLogOptions { clock: Some(Arc::new(clock.clone())), encryption: Some(keyring.clone()), ..Default::default() };
+    let mut log = AuditLog::open_with(&log_path, options).expect("open");
+    log.append(Operation::FileRead { path: "a".into() }).expect("append");
+    clock.set(at(40) + chrono::Duration::milliseconds(500));
+    log.append(Operation::FileRead { path: "b".into() }).expect("append behind the clock");
+    drop(log);
+
+    let (_, entries) = read_entries(&log_path);
+    assert_eq!(entries[1].clamped_from, Some(at(40) + chrono::Duration::milliseconds(500)));
+    assert!(matches!(keyring.open(&entries[1]).expect("open").operation, Operation::FileRead { path } if path == Path::new("b")));
+
+    let mut moved = entries[1].clone();
+    moved.clamped_from = Some(at(40));
+    assert!(matches!(keyring.open(&moved), Err(EncryptionError::Decrypt { seq: 1, .. })));
+}
+
+// ─── Redaction ──────────────────────────────────────────────────────────────
+
+/// A redacted copy hides paths and messages, keeps equal values equal, and
+/// is tied to the original by a proof both sides can check.
+#[test]
+fn audit_log_redacted_copy_is_proven_against_original() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let mut log = AuditLog::open(&log_path).expect("open");
+    log.set_context(AuditContext::default().with_correlation_id("run-1"));
+    log.append(Operation::GitCommitCreated { repo_path: "/home/acme/secret-repo".into(), message: "leak plans".into() })
+        .expect("append");
+    log.append(Operation::TransactionBegin {
+        tx_id: "tx".into(),
+        planned_ops: vec![Operation::FileDelete { path: "/home/acme/secret-repo".into() }],
+    }).expect("append");
+    drop(log);
+
+    let redactor = Redactor::generate().expect("salt");
+    let copy = tmp.path().join("shared.log");
+    let proof = audit_log::redact_log(&log_path, &copy, &redactor).expect("redact");
+    assert_eq!(proof.links.len(), 2);
+
+    let text = fs::read_to_string(&copy).expect("read copy");
+    assert!(!text.contains("secret-repo") && !text.contains("leak plans"), "copy leaks: {text}");
+    let entries: Vec<LogEntry> = AuditLog::entries(&copy).expect("entries").map(|e| e.expect("entry")).collect();
+    assert_eq!(entries[0].correlation_id(), Some("run-1"));
+    let (Operation::GitCommitCreated { repo_path, .. }, Operation::TransactionBegin { planned_ops, .. }) =
+        (&entries[0].operation, &entries[1].operation) else { panic!("operations changed kind") };
+    assert!(matches!(&planned_ops[0], Operation::FileDelete { path } if path == repo_path));
+
+    let json = serde_json::to_string(&proof).expect("serialise proof");
+    let proof: RedactionProof = serde_json::from_str(&json).expect("parse proof");
+    proof.check_redacted(&copy).expect("copy matches proof");
+    proof.check_original(&log_path, &Redactor::from_hex(&redactor.to_hex()).expect("salt")).expect("original matches proof");
+
+    let other = Redactor::generate().expect("salt");
+    assert!(matches!(proof.check_original(&log_path, &other),
+        Err(IntegrityError::ProofMismatch { field: "salt_fingerprint", .. })));
+
+    // A re-chained copy with an altered entry no longer matches the proof.
+    let mut forged = entries.clone();
+    forged[1].operation = Operation::FileRead { path: "innocent".into() };
+    let lines: String = forged.iter().map(|e| serde_json::to_string(e).expect("serialise") + "\n").collect();
+    fs::write(&copy, lines).expect("write forged copy");
+    assert!(matches!(proof.check_redacted(&copy), Err(IntegrityError::ProofMismatch { seq: 1, field: "redacted" })));
+}
+
+/// The copy of a compacted log keeps its sequence numbers and starts from
+/// the same checkpoint, so it verifies and matches its proof.
+#[test]
+fn audit_log_redacted_copy_of_compacted_log_keeps_its_start() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    populate(&log_path, 5);
+    let key = CheckpointKey::generate("ops").expect("key");
+    let checkpoint = AuditLog::compact(&log_path, 3, &key, tmp.path().join("archive.log")).expect("compact");
+
+    let redactor = Redactor::generate().expect("salt");
+    let copy = tmp.path().join("shared.log");
+    let proof = audit_log::redact_log(&log_path, &copy, &redactor).expect("redact");
+
+    let seqs: Vec<u64> = AuditLog::entries(&copy).expect("entries").map(|e| e.expect("entry").seq).collect();
+    assert_eq!(seqs, [3, 4]);
+    assert_eq!(Checkpoint::load(&copy).expect("load"), Some(checkpoint));
+    assert!(!fs::read_to_string(&copy).expect("read copy").contains("file_"));
+    assert_eq!(AuditLog::verify(&copy).expect("copy verifies from the checkpoint"), 2);
+    proof.check_redacted(&copy).expect("copy matches proof");
+    proof.check_original(&log_path, &redactor).expect("original matches proof");
+}
+
+/// Write-time redaction truncates paths and messages before they are stored.
+#[test]
+fn audit_log_redacts_at_write_time() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let redactor = Redactor::generate().expect("salt")
+        .with_paths(FieldPolicy::Truncate(1))
+        .with_messages(FieldPolicy::Truncate(4));
+    let options = AuditLogOptions { redaction: Some(redactor), ..Default::default() };
+    let mut log = AuditLog::open_with(&log_path, options).expect("open");
+    log.append(Operation::GitCommitCreated { repo_path: "/srv/clients/acme".into(), message: "Fix the thing".into() })
+        .expect("append");
+    log.append(Operation::FileRead { path: "notes.txt".into() }).expect("append");
+    drop(log);
+
+    let entries: Vec<LogEntry> = AuditLog::entries(&log_path).expect("entries").map(|e| e.expect("entry")).collect();
+    match &entries[0].operation {
+        Operation::GitCommitCreated { repo_path, message } => {
+            assert_eq!(repo_path, Path::new("…/acme"));
+            assert_eq!(message, "Fix …");
+        }
+        other => panic!("expected GitCommitCreated, got {other:?}"),
+    }
+    assert!(matches!(&entries[1].operation, Operation::FileRead { path } if path == Path::new("notes.txt")));
+    assert!(!fs::read_to_string(&log_path).expect("read").contains("clients"));
+    assert_eq!(AuditLog::verify(&log_path).expect("verify"), 2);
+}
+
+// ─── Tracing layer ──────────────────────────────────────────────────────────
+
+/// Run `f` with a subscriber that sends audited events to a new log at `path`.
+fn with_audit_layer(path: &Path, f: impl FnOnce()) -> AuditLayer {
+    let layer = AuditLayer::new(AuditLog::open(path).expect("open"));
+    let subscriber = tracing_subscriber::registry().with(layer.clone());
+    tracing::subscriber::with_default(subscriber, f);
+    layer
+}
+
+/// Events with the audit target or marker become entries: typed operations
+/// where the fields name one, custom entries otherwise. Other events are
+/// ignored.
+#[test]
+fn tracing_layer_appends_audited_events() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let layer = with_audit_layer(&log_path, || {
+        tracing::info!(target: "audit", op = "file_move", from = "a.txt", to = "b.txt");
+        tracing::info!("not audited");
+        tracing::warn!(audit = true, kind = "elixir.repo_fixed", repo = "acme", attempts = 2u64, "fixed");
+        tracing::error!(target: "audit::git", op = "file_delete", path = "gone.txt", error = "permission denied");
+        tracing::info!(target: "auditing", op = "file_read", path = "ignored.txt");
+    });
+    assert_eq!(layer.failed_appends(), 0);
+    drop(layer);
+
+    let entries: Vec<LogEntry> = AuditLog::entries(&log_path).expect("entries").map(|e| e.expect("entry")).collect();
+    assert_eq!(entries.len(), 3);
+    assert!(matches!(&entries[0].operation,
+        Operation::FileMove { from, to } if from == Path::new("a.txt") && to == Path::new("b.txt")));
+    match &entries[1].operation {
+        Operation::Custom { kind, details } => {
+            assert_eq!(kind, "elixir.repo_fixed");
+            assert_eq!(details["repo"], "acme");
+            assert_eq!(details["attempts"], 2);
+            assert_eq!(details["message"], "fixed");
+            assert_eq!(details["level"], "WARN");
+        }
+        other => panic!("expected Custom, got {other:?}"),
+    }
+    assert!(matches!(&entries[2].operation, Operation::FileDelete { path } if path == Path::new("gone.txt")));
+    assert_eq!(entries[2].outcome, Some(Outcome::Failure { error: "permission denied".into() }));
+    assert_eq!(AuditLog::verify(&log_path).expect("verify"), 3);
+}
+
+/// An audited span is recorded when created and lends its `tx_id` to the
+/// events inside it.
+#[test]
+fn tracing_layer_records_spans_and_inherits_tx_id() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    with_audit_layer(&log_path, || {
+        let span = tracing::info_span!(target: "audit", "fix_repo", tx_id = "tx-7", repo = "acme");
+        let _guard = span.enter();
+        tracing::info!(target: "audit", op = "file_write", path = "README.md", outcome = "success");
+        tracing::info!(target: "audit", op = "file_read", path = "x", tx_id = "tx-8");
+    });
+
+    let entries: Vec<LogEntry> = AuditLog::entries(&log_path).expect("entries").map(|e| e.expect("entry")).collect();
+    assert_eq!(entries.len(), 3);
+    match &entries[0].operation {
+        Operation::Custom { kind, details } => {
+            assert_eq!(kind, "tracing.span");
+            assert_eq!(details["span"], "fix_repo");
+            assert_eq!(details["repo"], "acme");
+        }
+        other => panic!("expected Custom, got {other:?}"),
+    }
+    let tx_ids: Vec<Option<&str>> = entries.iter().map(|e| e.tx_id.as_deref()).collect();
+    assert_eq!(tx_ids, [Some("tx-7"), Some("tx-7"), Some("tx-8")]);
+    assert_eq!(entries[1].outcome, Some(Outcome::Success));
+    assert!(matches!(&entries[1].operation, Operation::FileWrite { path } if path == Path::new("README.md")));
+}
+
+// ─── Shared handles ─────────────────────────────────────────────────────────
+
+/// Clones of a handle append from many threads into one valid chain that
+/// keeps each thread's order; shutdown flushes and stops every clone.
+#[test]
+fn audit_handle_serialises_concurrent_appends() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let handle = AuditHandle::spawn(AuditLog::open(&log_path).expect("open"), 4).expect("spawn writer");
+
+    let workers: Vec<_> = (0..4).map(|t| {
+        let handle = handle.clone();
+        std::thread::spawn(move || {
+            for n in 0..25 {
+                handle.append(Operation::FileRead { path: format!("t{t}/{n:02}").into() }).expect("append");
+            }
+        })
+    }).collect();
+    for worker in workers {
+        worker.join().expect("worker");
+    }
+    handle.shutdown().expect("shutdown");
+    assert_eq!(handle.append(Operation::FileRead { path: "late".into() }).unwrap_err().kind(),
+        std::io::ErrorKind::BrokenPipe);
+
+    assert_eq!(AuditLog::verify(&log_path).expect("verify"), 100);
+    let paths: Vec<String> = AuditLog::entries(&log_path).expect("entries")
+        .map(|e| match e.expect("entry").operation {
+            Operation::FileRead { path } => path.display().to_string(),
+            other => panic!("unexpected {other:?}"),
+        })
+        .collect();
+    for t in 0..4 {
+        let mine: Vec<&String> = paths.iter().filter(|p| p.starts_with(&format!("t{t}/"))).collect();
+        assert_eq!(mine.len(), 25);
+        assert!(mine.windows(2).all(|w| w[0] < w[1]), "thread {t} out of order: {mine:?}");
+    }
+}
+
+/// `append_wait` returns each entry's ticket, `flush` makes them durable,
+/// and dropping the last handle writes whatever is still queued.
+#[test]
+fn audit_handle_waits_flushes_and_drains_on_drop() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let options = AuditLogOptions { durability: Durability::OnFlush, ..Default::default() };
+    let handle = AuditHandle::spawn(AuditLog::open_with(&log_path, options).expect("open"), 16).expect("spawn writer");
+
+    let ticket = handle.append_wait(Operation::FileRead { path: "a".into() }, EntryMeta::default()).expect("append");
+    assert_eq!(ticket.seq(), 0);
+    handle.append_with(Operation::FileWrite { path: "b".into() }, EntryMeta::outcome(Outcome::Success))
+        .expect("append");
+    handle.flush().expect("flush");
+    assert!(ticket.is_durable());
+    assert_eq!(AuditLog::verify(&log_path).expect("verify"), 2);
+
+    let clone = handle.clone();
+    drop(handle);
+    for n in 0..10 {
+        clone.append(Operation::FileRead { path: format!("q{n}").into() }).expect("append");
+    }
+    drop(clone);
+    assert_eq!(AuditLog::verify(&log_path).expect("verify"), 12);
+}
+
+// ─── Following ──────────────────────────────────────────────────────────────
+
+const WAIT: Duration = Duration::from_secs(5);
+
+/// A follower yields existing and new entries in order, waits for a record
+/// that is still being written, and flags an appended entry that does not
+/// link to the chain.
+#[test]
+fn follower_yields_entries_and_flags_broken_links() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    populate(&log_path, 2);
+    let mut follower = Follower::from_start(&log_path).expect("follow").with_poll_interval(Duration::from_millis(20));
+    for seq in 0..2 {
+        assert!(matches!(follower.next_timeout(WAIT).expect("event"), Some(FollowEvent::Entry(e)) if e.seq == seq));
+    }
+    assert!(follower.poll().expect("poll").is_none());
+
+    let mut log = AuditLog::open(&log_path).expect("open");
+    log.append(Operation::FileWrite { path: "live.txt".into() }).expect("append");
+    assert!(matches!(follower.next_timeout(WAIT).expect("event"), Some(FollowEvent::Entry(e)) if e.seq == 2));
+    drop(log);
+
+    // A forged entry, written in two parts like a slow writer would.
+    let mut forged = AuditLog::entry(&log_path, 1).expect("read").expect("entry 1");
+    forged.seq = 3;
+    let line = serde_json::to_string(&forged).expect("serialise") + "\n";
+    let (head, tail) = line.split_at(line.len() / 2);
+    let mut file = OpenOptions::new().append(true).open(&log_path).expect("open for append");
+    file.write_all(head.as_bytes()).expect("write");
+    assert!(follower.poll().expect("poll").is_none());
+    file.write_all(tail.as_bytes()).expect("write");
+    match follower.next_timeout(WAIT).expect("event") {
+        Some(FollowEvent::ChainBroken { expected_seq: 3, entry, .. }) => assert_eq!(entry.seq, 3),
+        other => panic!("expected ChainBroken, got {other:?}"),
+    }
+}
+
+/// Sealing the active file as a segment is reported as a rotation and the
+/// chain continues; shrinking the file is reported as truncation.
+#[test]
+fn follower_reports_rotation_and_truncation() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let mut log = AuditLog::open(&log_path).expect("open");
+    for n in 0..3 {
+        log.append(Operation::FileRead { path: format!("f{n}").into() }).expect("append");
+    }
+    let mut follower = Follower::from_end(&log_path).expect("follow").with_poll_interval(Duration::from_millis(20));
+    assert!(follower.poll().expect("poll").is_none());
+
+    log.rotate().expect("rotate");
+    log.append(Operation::FileRead { path: "after".into() }).expect("append");
+    assert!(matches!(follower.next_timeout(WAIT).expect("event"), Some(FollowEvent::Rotated { segment: 1 })));
+    assert!(matches!(follower.next_timeout(WAIT).expect("event"), Some(FollowEvent::Entry(e)) if e.seq == 3));
+
+    OpenOptions::new().write(true).open(&log_path).expect("open").set_len(10).expect("truncate");
+    match follower.next_timeout(WAIT).expect("event") {
+        Some(FollowEvent::Truncated { read, len: 10 }) => assert!(read > 10),
+        other => panic!("expected Truncated, got {other:?}"),
+    }
+}
+
+// ─── Exports ────────────────────────────────────────────────────────────────
+
+/// Write a small log covering typed, custom and failed operations.
+fn export_fixture(path: &Path) -> Vec<LogEntry> {
+    let mut log = AuditLog::open(path).expect("open");
+    log.set_context(AuditContext::default().with_actor("ci").with_correlation_id("run-9"));
+    log.append_with(
+        Operation::FileMove { from: "a.txt".into(), to: "b.txt".into() },
+        EntryMeta::outcome(Outcome::Failure { error: "disk full".into() }),
+    ).expect("append");
+    log.append(Operation::Custom {
+        kind: "elixir.repo_fixed".into(),
+        details: serde_json::json!({ "note": "one, \"two\"" }),
+    }).expect("append");
+    log.append(Operation::GitCommitCreated { repo_path: "/srv/[repo]".into(), message: "say \"hi\"".into() })
+        .expect("append");
+    drop(log);
+    AuditLog::entries(path).expect("entries").map(|e| e.expect("entry")).collect()
+}
+
+/// JSON Lines and CSV exports carry flattened fields and the entry hashes
+/// of the source log.
+#[test]
+fn export_json_lines_and_csv_with_hashes() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let entries = export_fixture(&log_path);
+
+    let mut jsonl = Vec::new();
+    assert_eq!(Exporter::new(ExportFormat::JsonLines).write(AuditLog::entries(&log_path).expect("entries"), &mut jsonl)
+        .expect("export"), 3);
+    let records: Vec<serde_json::Value> = String::from_utf8(jsonl).expect("utf-8").lines()
+        .map(|line| serde_json::from_str(line).expect("json line"))
+        .collect();
+    for (record, entry) in records.iter().zip(&entries) {
+        assert_eq!(record["schema"], EXPORT_SCHEMA);
+        assert_eq!(record["hash"], entry.hash());
+        assert_eq!(record["prev_hash"], entry.prev_hash);
+        assert_eq!(record["correlation_id"], "run-9");
+    }
+    assert_eq!((&records[0]["op"], &records[0]["path"], &records[0]["to_path"]), (&"FileMove".into(), &"a.txt".into(), &"b.txt".into()));
+    assert_eq!((&records[0]["outcome"], &records[0]["error"]), (&"failure".into(), &"disk full".into()));
+    assert_eq!(records[1]["kind"], "elixir.repo_fixed");
+    assert_eq!(records[1]["details"]["note"], "one, \"two\"");
+    assert!(records[1].get("path").is_none(), "empty fields are omitted");
+
+    let mut csv = Vec::new();
+    Exporter::new(ExportFormat::Csv).write(AuditLog::entries(&log_path).expect("entries"), &mut csv).expect("export");
+    let csv = String::from_utf8(csv).expect("utf-8");
+    let rows: Vec<&str> = csv.split_terminator("\r\n").collect();
+    assert_eq!(rows.len(), 4);
+    assert_eq!(rows[0], EXPORT_COLUMNS.join(","));
+    assert!(rows[1].starts_with(&format!("0,{},{},{},FileMove,a.txt,b.txt,", records[0]["timestamp"].as_str().unwrap(),
+        entries[0].hash(), entries[0].prev_hash)));
+    assert!(rows[2].contains(r#","{""note"":""one, \""two\""""}","#), "details quoted: {}", rows[2]);
+}
+
+/// Syslog exports are RFC 5424 messages with escaped structured data, and
+/// sealed entries exported with their key keep the sealed entry's hash.
+#[test]
+fn export_syslog_and_sealed_entries() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let entries = export_fixture(&log_path);
+
+    let mut out = Vec::new();
+    Exporter::new(ExportFormat::Syslog).write(AuditLog::entries(&log_path).expect("entries"), &mut out).expect("export");
+    let lines: Vec<String> = String::from_utf8(out).expect("utf-8").lines().map(str::to_owned).collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("<108>1 "), "failure is a warning: {}", lines[0]);
+    assert!(lines[1].starts_with("<109>1 "), "success is a notice: {}", lines[1]);
+    let header: Vec<&str> = lines[0].splitn(7, ' ').collect();
+    assert_eq!(&header[2..6], ["-", "polysafe-gitfixer", "-", "FileMove"]);
+    assert!(header[6].starts_with(&format!("[polysafe@32473 seq=\"0\" hash=\"{}\"", entries[0].hash())));
+    assert!(lines[2].contains(r#"path="/srv/[repo\]""#) && lines[2].contains(r#"message="say \"hi\"""#), "{}", lines[2]);
+
+    let sealed_path = tmp.path().join("sealed.log");
+    let keyring = Keyring::new(Cipher::Aes256Gcm, EncryptionKey::generate("k1").expect("key"));
+    let options = AuditLogOptions { encryption: Some(keyring.clone()), ..Default::default() };
+    let mut sealed = AuditLog::open_with(&sealed_path, options).expect("open");
+    sealed.append(Operation::FileDelete { path: "secret.txt".into() }).expect("append");
+    drop(sealed);
+    let stored = AuditLog::entry(&sealed_path, 0).expect("read").expect("entry");
+
+    let mut out = Vec::new();
+    Exporter::new(ExportFormat::JsonLines).with_keyring(&keyring)
+        .write(AuditLog::entries(&sealed_path).expect("entries"), &mut out).expect("export");
+    let record: serde_json::Value = serde_json::from_slice(&out).expect("json");
+    assert_eq!((&record["op"], &record["path"]), (&"FileDelete".into(), &"secret.txt".into()));
+    assert_eq!(record["hash"], stored.hash());
+
+    let mut out = Vec::new();
+    Exporter::new(ExportFormat::JsonLines).write(AuditLog::entries(&sealed_path).expect("entries"), &mut out).expect("export");
+    let record: serde_json::Value = serde_json::from_slice(&out).expect("json");
+    assert_eq!((&record["op"], &record["details"]["key_id"]), (&"Encrypted".into(), &"k1".into()));
+}
+
+// ─── Mirrors ────────────────────────────────────────────────────────────────
+
+/// Every entry reaches each mirror; mirrors that fall behind are skipped
+/// until reconciliation appends what they missed.
+#[test]
+fn mirrors_receive_entries_and_lagging_ones_are_repaired() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    fs::create_dir(tmp.path().join("other-mount")).expect("mkdir");
+    let mirrors = vec![tmp.path().join("other-mount/audit.log"), tmp.path().join("audit.mirror")];
+    let options = || AuditLogOptions { mirrors: mirrors.clone(), ..Default::default() };
+
+    let mut log = AuditLog::open_with(&log_path, options()).expect("open");
+    for n in 0..3 {
+        log.append(Operation::FileRead { path: format!("f{n}").into() }).expect("append");
+    }
+    drop(log);
+    for mirror in &mirrors {
+        assert_eq!(fs::read(mirror).expect("read mirror"), fs::read(&log_path).expect("read log"));
+    }
+
+    // Lose the last entry of one mirror and the whole of the other.
+    let text = fs::read_to_string(&mirrors[0]).expect("read");
+    let keep: String = text.lines().take(3).map(|l| format!("{l}\n")).collect();
+    fs::write(&mirrors[0], keep).expect("truncate mirror");
+    fs::remove_file(&mirrors[1]).expect("remove mirror");
+
+    let mut log = AuditLog::open_with(&log_path, options()).expect("open");
+    assert_eq!(log.lagging_mirrors(), [mirrors[0].as_path(), mirrors[1].as_path()]);
+    log.append(Operation::FileWrite { path: "f3".into() }).expect("append still succeeds");
+
+    let report = log.reconcile_mirrors().expect("reconcile");
+    assert_eq!(report.primary.state, CopyState::InSync);
+    assert_eq!(report.mirrors[0].state, CopyState::Repaired { added: 2 });
+    assert_eq!(report.mirrors[1].state, CopyState::Repaired { added: 4 });
+    assert!(report.is_consistent());
+    assert!(log.lagging_mirrors().is_empty());
+
+    log.append(Operation::FileDelete { path: "f4".into() }).expect("append");
+    drop(log);
+    for mirror in &mirrors {
+        assert_eq!(fs::read(mirror).expect("read mirror"), fs::read(&log_path).expect("read log"));
+    }
+}
+
+/// Reconciliation names the copy with the longest valid chain and where a
+/// diverged copy departs from it, and leaves diverged copies untouched.
+#[test]
+fn reconcile_reports_longest_chain_and_divergence() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    populate(&log_path, 3);
+
+    // A mirror that holds one entry more than the primary.
+    let ahead = tmp.path().join("ahead.log");
+    fs::copy(&log_path, &ahead).expect("copy");
+    AuditLog::open(&ahead).expect("open").append(Operation::FileRead { path: "extra".into() }).expect("append");
+
+    // A mirror whose history differs from entry 1 on.
+    let forked = tmp.path().join("forked.log");
+    let first: String = fs::read_to_string(&log_path).expect("read").lines().take(2).map(|l| format!("{l}\n")).collect();
+    fs::write(&forked, first).expect("write");
+    AuditLog::open(&forked).expect("open").append(Operation::FileDelete { path: "other".into() }).expect("append");
+    let forked_before = fs::read(&forked).expect("read");
+
+    let report = audit_log::reconcile(&log_path, &[ahead.clone(), forked.clone()], true).expect("reconcile");
+    assert_eq!(report.longest, ahead);
+    assert_eq!(report.primary.state, CopyState::Lagging { behind: 1 });
+    assert_eq!(report.mirrors[0].state, CopyState::InSync);
+    assert_eq!(report.mirrors[0].valid_entries, 4);
+    assert_eq!(report.mirrors[1].state, CopyState::Diverged { at_seq: 1 });
+    assert!(!report.is_consistent());
+    assert_eq!(fs::read(&forked).expect("read"), forked_before, "diverged mirror must not be rewritten");
+}
+
+/// After compaction the primary is compared from its checkpoint: a full
+/// mirror stays in sync, and missing mirrors are rebuilt from the
+/// checkpoint, at once or by reconciliation.
+#[test]
+fn reconcile_compacted_log_rebuilds_mirrors_from_its_checkpoint() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let full = tmp.path().join("full.mirror");
+    let lost = tmp.path().join("lost.mirror");
+    let options = |mirrors: Vec<PathBuf>| AuditLogOptions { mirrors, ..Default::default() };
+    let mut log = AuditLog::open_with(&log_path, options(vec![full.clone(), lost.clone()])).expect("open");
+    for n in 0..5 {
+        log.append(Operation::FileRead { path: format!("f{n}").into() }).expect("append");
+    }
+    drop(log);
+    fs::remove_file(&lost).expect("lose mirror");
+    let key = CheckpointKey::generate("ops").expect("key");
+    AuditLog::compact(&log_path, 3, &key, tmp.path().join("archive.log")).expect("compact");
+
+    let report = audit_log::reconcile(&log_path, &[full.clone(), lost.clone()], true).expect("reconcile");
+    assert_eq!((report.primary.valid_entries, &report.primary.state), (2, &CopyState::InSync));
+    assert_eq!(report.longest, log_path);
+    assert_eq!(report.mirrors[0].state, CopyState::InSync);
+    assert_eq!(report.mirrors[1].state, CopyState::Repaired { added: 2 });
+    assert_eq!(AuditLog::verify(&lost).expect("rebuilt mirror verifies from the checkpoint"), 2);
+
+    // A mirror added to a log compacted down to nothing starts at once.
+    AuditLog::compact(&log_path, 5, &key, tmp.path().join("archive.log")).expect("compact again");
+    let late = tmp.path().join("late.mirror");
+    let mut log = AuditLog::open_with(&log_path, options(vec![full.clone(), lost.clone(), late.clone()])).expect("open");
+    assert!(log.lagging_mirrors().is_empty());
+    log.append(Operation::FileWrite { path: "f5".into() }).expect("append");
+    drop(log);
+    let report = audit_log::reconcile(&log_path, &[full, lost, late.clone()], false).expect("reconcile");
+    assert!(report.is_consistent(), "{report:?}");
+    assert_eq!(AuditLog::verify(&late).expect("late mirror verifies"), 1);
+}
+
+// ─── Compaction ─────────────────────────────────────────────────────────────
+
+/// A compacted log verifies from its checkpoint, with or without the
+/// archive, keeps chaining new entries, and can be compacted again.
+#[test]
+fn compaction_replaces_prefix_with_checkpoint() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let archive = tmp.path().join("archive.log");
+    populate(&log_path, 8);
+    AuditLog::open(&log_path).expect("open").append(Operation::FileWrite { path: "w".into() }).expect("append");
+    let head = AuditLog::entry(&log_path, 5).expect("read").expect("entry 5").hash();
+    let entry_lines = |path: &Path, skip: usize| fs::read_to_string(path).expect("read").lines().skip(1 + skip).collect::<Vec<_>>().join("\n");
+    let remainder = entry_lines(&log_path, 6);
+
+    let key = CheckpointKey::generate("ops-2026").expect("key");
+    let checkpoint = AuditLog::compact(&log_path, 6, &key, &archive).expect("compact");
+    assert_eq!((checkpoint.entries, checkpoint.head_hash.as_str()), (6, head.as_str()));
+    assert_eq!(checkpoint.op_counts.get("FileRead"), Some(&6));
+    assert_eq!(Checkpoint::load(&log_path).expect("load"), Some(checkpoint.clone()));
+    assert_eq!(entry_lines(&log_path, 0), remainder, "kept entries are unchanged");
+
+    assert_eq!(AuditLog::verify(&log_path).expect("checkpoint + remainder"), 3);
+    assert_eq!(AuditLog::verify_with_archive(&log_path, &archive).expect("full history"), 9);
+    assert_eq!(AuditLog::verify(&archive).expect("archive is a log"), 6);
+    assert!(AuditLog::verify_report(&log_path).expect("report").is_clean());
+    assert!(AuditLog::entry(&log_path, 5).expect("read").is_none());
+
+    let trusted = VerifyOptions { checkpoint_keys: vec![key.public_key()], ..Default::default() };
+    AuditLog::verify_with(&log_path, &trusted).expect("trusted signer");
+    let other = CheckpointKey::generate("other").expect("key");
+    let untrusted = VerifyOptions { checkpoint_keys: vec![other.public_key()], ..Default::default() };
+    assert!(matches!(AuditLog::verify_with(&log_path, &untrusted), Err(IntegrityError::InvalidCheckpoint { .. })));
+
+    let mut log = AuditLog::open(&log_path).expect("reopen");
+    assert_eq!(log.append(Operation::FileDelete { path: "d".into() }).expect("append").seq(), 9);
+    drop(log);
+
+    let again = AuditLog::compact(&log_path, 9, &key, &archive).expect("compact again");
+    assert_eq!(again.op_counts.get("FileWrite"), Some(&1));
+    assert_eq!(AuditLog::verify(&log_path).expect("verify"), 1);
+    assert_eq!(AuditLog::verify_with_archive(&log_path, &archive).expect("full history"), 10);
+    assert!(AuditLog::compact(&log_path, 9, &key, &archive).is_err(), "nothing left before entry 9");
+
+    // Compacting everything still leaves a log that continues the chain.
+    AuditLog::compact(&log_path, 10, &key, &archive).expect("compact all");
+    let mut log = AuditLog::open(&log_path).expect("reopen");
+    assert_eq!(log.append(Operation::FileRead { path: "r".into() }).expect("append").seq(), 10);
+    drop(log);
+    assert_eq!(AuditLog::verify(&log_path).expect("verify"), 1);
+}
+
+/// An edited checkpoint fails its signature, a damaged archive no longer
+/// matches the checkpoint, and a checkpoint left beside a log that was never
+/// rewritten is ignored.
+#[test]
+fn compaction_detects_tampering_and_interruption() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let archive = tmp.path().join("archive.log");
+    populate(&log_path, 5);
+    let original = fs::read(&log_path).expect("read");
+    let key = CheckpointKey::from_hex("k1", &"ab".repeat(32)).expect("key");
+    AuditLog::compact(&log_path, 3, &key, &archive).expect("compact");
+
+    let checkpoint_path = Checkpoint::path_for(&log_path);
+    let signed = fs::read_to_string(&checkpoint_path).expect("read checkpoint");
+    let mut forged: Checkpoint = serde_json::from_str(&signed).expect("parse");
+    forged.op_counts.insert("FileDelete".into(), 1);
+    fs::write(&checkpoint_path, serde_json::to_string(&forged).expect("serialise")).expect("write");
+    assert!(matches!(AuditLog::verify(&log_path), Err(IntegrityError::InvalidCheckpoint { .. })));
+    let report = AuditLog::verify_report(&log_path).expect("report");
+    assert!(matches!(report.findings[0].problem, Problem::InvalidCheckpoint { .. }));
+    fs::write(&checkpoint_path, &signed).expect("restore");
+
+    let archived = fs::read_to_string(&archive).expect("read archive");
+    let shortened: String = archived.lines().take(2).map(|l| format!("{l}\n")).collect();
+    fs::write(&archive, shortened).expect("drop an archived entry");
+    assert!(matches!(
+        AuditLog::verify_with_archive(&log_path, &archive),
+        Err(IntegrityError::CheckpointMismatch { field: "entries" })
+    ));
+    AuditLog::verify(&log_path).expect("the log itself is still intact");
+
+    // Crash after the checkpoint was stored but before the log was replaced.
+    fs::write(&log_path, &original).expect("put back the full log");
+    fs::write(&archive, &archived).expect("restore archive");
+    assert_eq!(AuditLog::verify(&log_path).expect("full log"), 5);
+    assert_eq!(AuditLog::entry(&log_path, 1).expect("read").map(|e| e.seq), Some(1));
+    AuditLog::compact(&log_path, 3, &key, &archive).expect("compacting again completes the job");
+    assert_eq!(AuditLog::verify_with_archive(&log_path, &archive).expect("full history"), 5);
+}
+
+/// Anchors taken before a compaction still verify against the archive,
+/// and a forged one does not.
+#[test]
+fn anchors_before_checkpoint_verify_against_archive() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let archive = tmp.path().join("archive.log");
+    populate(&log_path, 2);
+    let early = LogAnchor::of(&log_path).expect("anchor");
+    populate(&log_path, 3);
+    let key = CheckpointKey::generate("ops").expect("key");
+    AuditLog::compact(&log_path, 4, &key, &archive).expect("compact");
+    let late = LogAnchor::of(&log_path).expect("anchor");
+
+    let anchors = [early.clone(), late];
+    assert!(matches!(AuditLog::verify_anchored(&log_path, &anchors), Err(IntegrityError::AnchorMismatch { entries: 2, .. })));
+    assert_eq!(AuditLog::verify_anchored_with_archive(&log_path, &archive, &anchors).expect("verify"), 5);
+
+    let forged = LogAnchor { head_hash: "ab".repeat(32), ..early };
+    assert!(matches!(
+        AuditLog::verify_anchored_with_archive(&log_path, &archive, &[forged]),
+        Err(IntegrityError::AnchorMismatch { entries: 2, found: Some(_), .. })
+    ));
+}
+
+// ─── Headers and hash algorithms ────────────────────────────────────────────
+
+/// A new log states its algorithm and id in a header that every file it
+/// rotates into keeps, and entries are hashed with that algorithm in
+/// either format.
+#[test]
+fn log_header_names_algorithm_and_survives_rotation() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let binary = tmp.path().join("audit.bin");
+    let options = AuditLogOptions {
+        hash_algorithm: HashAlgorithm::Sha512,
+        log_id: Some("repo-42".into()),
+        ..Default::default()
+    };
+    let mut log = AuditLog::open_with(&log_path, options).expect("open");
+    for n in 0..3 {
+        log.append(Operation::FileRead { path: format!("f{n}").into() }).expect("append");
+    }
+    log.rotate().expect("rotate");
+    log.append(Operation::FileWrite { path: "w".into() }).expect("append");
+    drop(log);
+
+    let header = LogHeader::read(&log_path).expect("read").expect("active file has a header");
+    assert_eq!((header.hash_algorithm, header.log_id.as_str()), (HashAlgorithm::Sha512, "repo-42"));
+    assert_eq!(header.params.get("format").map(String::as_str), Some("ndjson"));
+    let segment = &SegmentManifest::load(&log_path).expect("load").expect("manifest").segments[0];
+    let sealed = LogHeader::read(tmp.path().join(&segment.file)).expect("read").expect("segment has a header");
+    assert_eq!(sealed.log_id, "repo-42");
+
+    let entry = AuditLog::entry(&log_path, 3).expect("read").expect("entry 3");
+    assert_eq!(entry.hash_algorithm(), HashAlgorithm::Sha512);
+    assert_eq!(entry.hash().len(), 128);
+    assert_eq!(entry.prev_hash, segment.head_hash);
+    assert_eq!(AuditLog::verify(&log_path).expect("verify"), 4);
+
+    audit_log::convert(tmp.path().join(&segment.file), &binary, LogFormat::Binary).expect("convert");
+    assert_eq!(LogHeader::read(&binary).expect("read"), Some(sealed));
+    assert_eq!(AuditLog::verify(&binary).expect("verify binary"), 3);
+
+    // A log written before headers is still read as SHA-256.
+    let legacy = tmp.path().join("legacy.log");
+    populate(&legacy, 2);
+    let (_, entries) = read_entries(&legacy);
+    let body: String = entries.iter().map(|e| serde_json::to_string(e).expect("serialise") + "\n").collect();
+    fs::write(&legacy, body).expect("strip header");
+    assert_eq!(LogHeader::read(&legacy).expect("read"), None);
+    assert_eq!(AuditLog::verify(&legacy).expect("verify legacy"), 2);
+}
+
+/// A logged migration switches algorithms mid-stream and stays verifiable;
+/// relabelling an entry's algorithm without one is caught even when the
+/// chain is re-forged around it.
+#[test]
+fn hash_algorithm_changes_only_through_a_migration_entry() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    populate(&log_path, 2);
+
+    let mut log = AuditLog::open(&log_path).expect("open");
+    assert_eq!(log.hash_algorithm(), HashAlgorithm::Sha256);
+    assert!(log.change_hash_algorithm(HashAlgorithm::Sha256).is_err(), "already in use");
+    let wrong = Operation::HashAlgorithmChanged { from: HashAlgorithm::Sha512, to: HashAlgorithm::Sha384 };
+    assert_eq!(log.append(wrong).expect_err("wrong from").kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(log.change_hash_algorithm(HashAlgorithm::Sha384).expect("switch").seq(), 2);
+    log.append(Operation::FileWrite { path: "w".into() }).expect("append");
+    drop(log);
+
+    let mut log = AuditLog::open(&log_path).expect("reopen");
+    assert_eq!(log.hash_algorithm(), HashAlgorithm::Sha384);
+    log.append(Operation::FileDelete { path: "d".into() }).expect("append");
+    drop(log);
+    assert_eq!(AuditLog::verify(&log_path).expect("verify across the switch"), 5);
+    assert!(AuditLog::verify_report(&log_path).expect("report").is_clean());
+
+    let (header, mut entries) = read_entries(&log_path);
+    assert_eq!(entries[2].hash().len(), 64, "the migration entry uses the old algorithm");
+    assert_eq!(entries[3].hash().len(), 96);
+
+    // Relabel entry 1 as SHA-512 and re-chain everything after it.
+    entries[1].hash_alg = Some(HashAlgorithm::Sha512);
+    for i in 2..entries.len() {
+        entries[i].prev_hash = entries[i - 1].hash();
+    }
+    write_entries(&log_path, &header, &entries);
+    fs::remove_file(tmp.path().join("audit.log.idx")).expect("remove index");
+    match AuditLog::verify(&log_path) {
+        Err(IntegrityError::HashAlgorithmMismatch { index, expected, found }) => {
+            assert_eq!((index, expected, found), (1, HashAlgorithm::Sha256, HashAlgorithm::Sha512));
+        }
+        other => panic!("expected HashAlgorithmMismatch, got: {other:?}"),
+    }
+    let report = AuditLog::verify_report(&log_path).expect("report");
+    assert!(report.findings.iter().any(|f| matches!(f.problem, Problem::HashAlgorithmMismatch { .. })));
+}
+
+// ─── Timelines ──────────────────────────────────────────────────────────────
+
+/// The last action on a path names its transaction even when it was rolled
+/// back, while the producer of the current bytes is the committed write.
+#[test]
+fn timeline_names_last_action_and_producer_of_current_bytes() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let file = tmp.path().join("merge.txt");
+    let mut log = AuditLog::open(&log_path).expect("open");
+
+    let begin = |tx: &str| Operation::TransactionBegin { tx_id: tx.into(), planned_ops: Vec::new() };
+    log.append(begin("tx-1")).expect("begin");
+    logged_write(&mut log, &file, "ours", EntryMeta::outcome(Outcome::Success).with_tx_id("tx-1"));
+    log.append(Operation::TransactionCommitted { tx_id: "tx-1".into(), applied: 1 }).expect("commit");
+
+    log.append(begin("tx-2")).expect("begin");
+    let committed = fs::read(&file).expect("read");
+    logged_write(&mut log, &file, "theirs", EntryMeta::outcome(Outcome::Success).with_tx_id("tx-2"));
+    fs::write(&file, committed).expect("undo write");
+    log.append(Operation::TransactionRolledBack { tx_id: "tx-2".into(), applied: 1, error: "conflict".into() })
+        .expect("roll back");
+    drop(log);
+
+    let timeline = Timeline::replay(&log_path).expect("replay");
+    assert_eq!(timeline.paths().collect::<Vec<_>>(), [file.as_path()]);
+    assert_eq!(timeline.history(&file).len(), 2);
+    let last = timeline.last_action(&file).expect("last action");
+    assert_eq!((last.seq, last.tx_id.as_deref(), &last.action), (4, Some("tx-2"), &PathAction::Written));
+    assert_eq!(timeline.transaction_state("tx-2"), Some(TxState::RolledBack));
+    assert!(!timeline.took_effect(last));
+
+    let producer = timeline.current_producer(&file).expect("hash").expect("producer");
+    assert_eq!((producer.seq, producer.tx_id.as_deref()), (1, Some("tx-1")));
+    assert_eq!(timeline.transaction_state("tx-1"), Some(TxState::Committed));
+
+    // Bytes no logged action produced have no producer.
+    fs::write(&file, "edited by hand").expect("edit");
+    assert!(timeline.current_producer(&file).expect("hash").is_none());
+    assert!(timeline.history(&tmp.path().join("untouched")).is_empty());
+}
+
+/// Moves appear in both paths' histories and a delete produces the absence
+/// of a file; sealed entries are replayed once opened.
+#[test]
+fn timeline_follows_moves_and_deletes_in_encrypted_logs() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let (draft, final_) = (tmp.path().join("draft.txt"), tmp.path().join("final.txt"));
+    let keyring = Keyring::new(Cipher::default(), EncryptionKey::generate("k1").expect("key"));
+    let options = AuditLogOptions { encryption: Some(keyring.clone()), ..Default::default() };
+    let mut log = AuditLog::open_with(&log_path, options).expect("open");
+
+    logged_write(&mut log, &draft, "text", EntryMeta::outcome(Outcome::Success));
+    let moved = audit_log::content_hash(&draft).expect("hash");
+    let before = audit_log::content_hash(&final_).expect("hash");
+    fs::rename(&draft, &final_).expect("move");
+    log.append_with(
+        Operation::FileMove { from: draft.clone(), to: final_.clone() },
+        EntryMeta::outcome(Outcome::Success).with_change(Change::Content { before, after: moved }),
+    ).expect("append move");
+    log.append_with(
+        Operation::FileDelete { path: final_.clone() },
+        EntryMeta::outcome(Outcome::Failure { error: "busy".into() }),
+    ).expect("append failed delete");
+    drop(log);
+
+    assert_eq!(Timeline::replay(&log_path).expect("replay").paths().count(), 0, "sealed entries are skipped");
+    let timeline = Timeline::replay_decrypted(&log_path, &keyring).expect("replay");
+    let actions: Vec<&PathAction> = timeline.history(&draft).iter().map(|e| &e.action).collect();
+    assert_eq!(actions, [&PathAction::Written, &PathAction::MovedOut { to: final_.clone() }]);
+    assert_eq!(timeline.current_producer(&draft).expect("hash").map(|e| e.seq), Some(1), "moved away");
+    assert_eq!(timeline.current_producer(&final_).expect("hash").map(|e| e.seq), Some(1), "moved in");
+    let last = timeline.last_action(&final_).expect("last action");
+    assert_eq!((&last.action, timeline.took_effect(last)), (&PathAction::Deleted, false));
+}
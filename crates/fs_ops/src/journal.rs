@@ -0,0 +1,235 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! Write-Ahead Journal — Surviving Crashes Mid-Commit.
+//!
+//! A transaction given a journal directory via
+//! [`FsTransaction::with_journal`](crate::FsTransaction::with_journal) keeps
+//! a journal file, `<tx-id>.journal`, for the length of its commit. Every
+//! record is fsynced before the change it describes is made, so after a
+//! crash the journal names everything that may have been done.
+//!
+//! FORMAT:
+//! One JSON record per line:
+//! - `Begin` — the transaction id and its planned operations.
+//! - `Temp` — a temp file about to be staged.
+//! - `Undo` — how to undo a change about to be made (an [`UndoStep`]).
+//! - `Applied` — operation `index` finished.
+//! - `Committed` / `RolledBack` — the commit finished one way or the other.
+//!
+//! A torn last line is a record that was never fsynced, so nothing acted
+//! on it and it is ignored. Any other unreadable line is an error.
+//!
+//! RECOVERY:
+//! [`recover`] runs at startup, before anything else touches the files, and
+//! settles every journal left in the directory:
+//! - `Committed` — the commit had finished; the backups and temps it left
+//!   behind are removed.
+//! - `RolledBack` — nothing left to do.
+//! - otherwise the commit was interrupted, and its temps are removed and
+//!   every recorded change is undone, most recent first.
+//!
+//! Each step checks what is on disk before acting, so a change the crash
+//! came before is skipped and recovery can itself be interrupted and run
+//! again. A journal is deleted once settled; one whose rollback could not
+//! be completed is kept, so the next run tries again. The audit log, if the
+//! transaction had one, still shows it as incomplete.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use capability::audit_log::Operation;
+use serde::{Deserialize, Serialize};
+
+use crate::transaction::{FsError, RollbackReport, UndoStep};
+
+/// Extension of journal files.
+const EXTENSION: &str = "journal";
+
+/// One line of a journal; see the module docs.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) enum Record {
+    Begin { tx_id: String, planned_ops: Vec<Operation> },
+    Temp { path: PathBuf },
+    Undo { step: UndoStep },
+    Applied { index: usize },
+    Committed,
+    RolledBack,
+}
+
+/// The open journal of a committing transaction.
+pub(crate) struct Journal {
+    path: PathBuf,
+    file: File,
+}
+
+impl Journal {
+    /// Create the journal of transaction `tx_id` in `dir` and record its
+    /// planned operations.
+    pub(crate) fn create(dir: &Path, tx_id: &str, planned_ops: Vec<Operation>) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(tx_id).with_extension(EXTENSION);
+        let file = OpenOptions::new().append(true).create_new(true).open(&path)?;
+        let mut journal = Self { path, file };
+        journal.record(&Record::Begin { tx_id: tx_id.to_string(), planned_ops })?;
+        sync_dir(dir)?;
+        Ok(journal)
+    }
+
+    /// Append `record` and wait until it is on disk.
+    pub(crate) fn record(&mut self, record: &Record) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record).map_err(io::Error::other)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()
+    }
+
+    /// Delete the journal of a settled transaction.
+    pub(crate) fn remove(self) -> io::Result<()> {
+        drop(self.file);
+        remove_journal(&self.path)
+    }
+}
+
+/// How [`recover`] settled an interrupted transaction.
+#[derive(Debug)]
+pub enum Recovery {
+    /// The commit had finished; its leftover backups and temps were removed.
+    Finished,
+    /// The commit was interrupted and has been rolled back as far as the
+    /// report says.
+    RolledBack(RollbackReport),
+}
+
+/// A transaction whose journal [`recover`] found.
+#[derive(Debug)]
+pub struct RecoveredTransaction {
+    /// Identifier of the transaction, as recorded in audit entries.
+    pub tx_id: String,
+    /// Operations the transaction planned.
+    pub planned_ops: Vec<Operation>,
+    /// How many of them had finished.
+    pub applied: usize,
+    /// What recovery did.
+    pub outcome: Recovery,
+}
+
+/// Finish or roll back every transaction with a journal in `journal_dir`;
+/// see the module docs. A missing directory has nothing to recover.
+///
+/// # Errors
+///
+/// Fails on the first journal that cannot be read. Journals settled before
+/// it are already gone, so the call can be repeated once it is dealt with.
+pub fn recover<P: AsRef<Path>>(journal_dir: P) -> Result<Vec<RecoveredTransaction>, FsError> {
+    let journal_dir = journal_dir.as_ref();
+    let mut journals = Vec::new();
+    match fs::read_dir(journal_dir) {
+        Ok(dir) => {
+            for entry in dir {
+                let path = entry?.path();
+                if path.extension().is_some_and(|ext| ext == EXTENSION) {
+                    journals.push(path);
+                }
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    }
+    journals.sort();
+
+    let mut recovered = Vec::new();
+    for path in journals {
+        if let Some(tx) = recover_one(&path)? {
+            recovered.push(tx);
+        }
+    }
+    Ok(recovered)
+}
+
+/// Settle the transaction journalled at `path`. `None` if it had already
+/// been rolled back and only the journal was left.
+fn recover_one(path: &Path) -> Result<Option<RecoveredTransaction>, FsError> {
+    let mut tx_id = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut planned_ops = Vec::new();
+    let mut temps = Vec::new();
+    let mut steps = Vec::new();
+    let mut applied = 0;
+    let mut committed = false;
+    let mut rolled_back = false;
+
+    let text = fs::read_to_string(path)?;
+    let lines: Vec<&str> = text.lines().collect();
+    for (i, line) in lines.iter().enumerate() {
+        let record = match serde_json::from_str(line) {
+            Ok(record) => record,
+            Err(_) if i + 1 == lines.len() => break,
+            Err(e) => {
+                return Err(FsError::Journal { path: path.to_path_buf(), line: i + 1, reason: e.to_string() });
+            }
+        };
+        match record {
+            Record::Begin { tx_id: id, planned_ops: ops } => {
+                tx_id = id;
+                planned_ops = ops;
+            }
+            Record::Temp { path } => temps.push(path),
+            Record::Undo { step } => steps.push(step),
+            Record::Applied { index } => applied = index + 1,
+            Record::Committed => committed = true,
+            Record::RolledBack => rolled_back = true,
+        }
+    }
+
+    if rolled_back {
+        remove_journal(path)?;
+        return Ok(None);
+    }
+    for temp in temps {
+        remove_if_present(&temp)?;
+    }
+    let outcome = if committed {
+        for step in steps {
+            if let UndoStep::Restore { backup, .. } = step {
+                remove_if_present(&backup)?;
+            }
+        }
+        Recovery::Finished
+    } else {
+        let mut report = RollbackReport::default();
+        for step in steps.into_iter().rev() {
+            match step.recover() {
+                Ok(()) => report.undone.push(step),
+                Err(e) => report.failed.push((step, e)),
+            }
+        }
+        Recovery::RolledBack(report)
+    };
+    if !matches!(&outcome, Recovery::RolledBack(report) if !report.is_complete()) {
+        remove_journal(path)?;
+    }
+    Ok(Some(RecoveredTransaction { tx_id, planned_ops, applied, outcome }))
+}
+
+/// Delete a journal, making sure it stays deleted: a settled journal that
+/// came back would be settled again.
+fn remove_journal(path: &Path) -> io::Result<()> {
+    remove_if_present(path)?;
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => sync_dir(dir),
+        _ => sync_dir(Path::new(".")),
+    }
+}
+
+/// Remove the file at `path`, if there is one.
+pub(crate) fn remove_if_present(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
+}
+
+/// Flush `dir`'s entries, so files created or removed in it stay so.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
@@ -8,13 +8,20 @@
 //!
 //! SAFETY GUARANTEES:
 //! 1. **RAII Rollback**: If a `FsTransaction` is dropped before being 
-//!    formally committed, all pending operations are undone.
+//!    formally committed, all pending operations are undone. If a commit
+//!    fails part way, the operations it already applied are undone and a
+//!    `RollbackReport` lists anything that could not be.
 //! 2. **Atomicity**: Files are written to temporary buffers and only 
 //!    renamed to the target path upon a successful commit.
 //! 3. **Isolation**: All paths are resolved through a `DirCapability`, 
 //!    eliminating path traversal vulnerabilities at the capability layer.
+//! 4. **Crash Recovery**: A transaction with a journal directory records
+//!    its changes there before making them, and `recover` finishes or
+//!    rolls back whatever a crash interrupted.
 
 #![forbid(unsafe_code)]
+mod journal;
 mod transaction;
 
-pub use transaction::{FsTransaction, FsError, FsOp};
+pub use journal::{recover, RecoveredTransaction, Recovery};
+pub use transaction::{FsTransaction, FsError, FsOp, RollbackReport, UndoStep};
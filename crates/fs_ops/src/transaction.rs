@@ -11,11 +11,48 @@
 //! 1. **RAII Rollback**: Dropped without `commit()` → all pending writes undone.
 //! 2. **Atomicity**: Files are written to temps then renamed on commit.
 //! 3. **Isolation**: All paths are resolved through a `DirCapability`.
+//!
+//! ROLLBACK:
+//! Operations are applied one at a time, so a failure part way through a
+//! commit would leave the earlier ones applied. Before each operation the
+//! commit saves what it is about to destroy: a file about to be overwritten
+//! is copied, and a file about to be deleted is renamed, to a backup beside
+//! it. Files and directories it creates are noted. If any operation (or its
+//! audit entry) fails, every applied change is undone in reverse order —
+//! originals restored from their backups, created files and directories
+//! removed — and the commit fails with [`FsError::RolledBack`], whose
+//! [`RollbackReport`] lists what was undone and what could not be. A change
+//! that could not be undone is left in place and its backup kept, so the
+//! original can still be recovered by hand. Backups are removed once the
+//! commit succeeds. The same rollback runs if a transaction is dropped part
+//! way through a commit, e.g. by a panic.
+//!
+//! JOURNAL:
+//! Rollback in memory does not survive the process dying. A transaction
+//! given a directory via [`FsTransaction::with_journal`] also writes each
+//! undo step, and each temp file it stages, to an fsynced journal there
+//! before making the change, so that [`recover`](crate::recover) can finish
+//! or roll back the commit at the next startup; see the `journal` module.
+//! The journal is deleted when the commit succeeds or is fully rolled back.
+//!
+//! AUDIT TRAIL:
+//! A transaction given an `AuditLog` via [`FsTransaction::with_audit`] logs
+//! `TransactionBegin` with the planned operations before touching the
+//! filesystem, one entry per applied operation (tagged with the transaction
+//! id, its outcome and before/after content hashes), and finally
+//! `TransactionCommitted` or `TransactionRolledBack`. A crash mid-commit
+//! therefore leaves a transaction that `incomplete_transactions` reports.
 
 use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use capability::audit_log::{self, AuditLog, Change, EntryMeta, Operation, Outcome};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use uuid::Uuid;
+
+use crate::journal::{self, Journal, Record};
 
 /// An individual filesystem operation queued in a transaction.
 #[derive(Debug)]
@@ -38,6 +75,102 @@ pub enum FsError {
     /// The transaction has already been committed or rolled back.
     #[error("transaction already finalised")]
     AlreadyFinalised,
+
+    /// The audit log could not record the transaction. Operations are not
+    /// applied unless their `TransactionBegin` entry was written.
+    #[error("audit log error: {0}")]
+    Audit(io::Error),
+
+    /// Every operation was applied and the commit is final, but its
+    /// `TransactionCommitted` entry could not be written, so the log still
+    /// shows the transaction as incomplete.
+    #[error("committed, but the audit log could not record it: {0}")]
+    CommittedUnaudited(io::Error),
+
+    /// The commit failed with `cause` and the operations it had applied
+    /// were rolled back as far as `report` says.
+    #[error("commit failed and was rolled back ({} undone, {} not undone): {cause}", .report.undone.len(), .report.failed.len())]
+    RolledBack { cause: Box<FsError>, report: RollbackReport },
+
+    /// Line `line` of the journal at `path` could not be read.
+    #[error("journal {} line {line} is unreadable: {reason}", .path.display())]
+    Journal { path: PathBuf, line: usize, reason: String },
+}
+
+/// One step of undoing a change made by a commit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UndoStep {
+    /// Put the original of `target`, saved at `backup`, back in place.
+    Restore { target: PathBuf, backup: PathBuf },
+    /// Remove the file `target`, which the commit created.
+    RemoveFile { target: PathBuf },
+    /// Remove the directory `target`, which the commit created.
+    RemoveDir { target: PathBuf },
+}
+
+impl UndoStep {
+    fn undo(&self) -> io::Result<()> {
+        match self {
+            UndoStep::Restore { target, backup } => fs::rename(backup, target),
+            UndoStep::RemoveFile { target } => match fs::remove_file(target) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                other => other,
+            },
+            UndoStep::RemoveDir { target } => fs::remove_dir(target),
+        }
+    }
+
+    /// Undo the change after a crash, which may have come before the change
+    /// was made or part way through undoing it.
+    pub(crate) fn recover(&self) -> io::Result<()> {
+        match self {
+            UndoStep::Restore { target, backup } => {
+                journal::remove_if_present(&partial_path(backup))?;
+                match fs::rename(backup, target) {
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                    other => other,
+                }
+            }
+            UndoStep::RemoveFile { target } => journal::remove_if_present(target),
+            UndoStep::RemoveDir { target } => match fs::remove_dir(target) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                other => other,
+            },
+        }
+    }
+}
+
+/// Where a backup is copied to before being renamed into place, so that a
+/// backup is never seen half-written.
+fn partial_path(backup: &Path) -> PathBuf {
+    backup.with_extension("partial_fs_tx")
+}
+
+/// What rolling back a failed commit did; see the module docs.
+#[derive(Debug, Default)]
+pub struct RollbackReport {
+    /// Steps that were undone, most recent change first.
+    pub undone: Vec<UndoStep>,
+    /// Steps that failed, with why. Their changes are still in place.
+    pub failed: Vec<(UndoStep, io::Error)>,
+}
+
+impl RollbackReport {
+    /// Whether every applied change was undone.
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+impl FsOp {
+    /// The audit operation describing this filesystem operation.
+    fn audit_operation(&self) -> Operation {
+        match self {
+            FsOp::WriteFile { target, .. } => Operation::FileWrite { path: target.clone() },
+            FsOp::DeleteFile { target } => Operation::FileDelete { path: target.clone() },
+            FsOp::CreateDir { target } => Operation::DirCreate { path: target.clone() },
+        }
+    }
 }
 
 /// A set of filesystem operations that are committed atomically.
@@ -45,10 +178,20 @@ pub enum FsError {
 /// Dropping a non-committed `FsTransaction` triggers automatic rollback:
 /// any temporary files that were staged are removed.
 pub struct FsTransaction {
+    /// Identifier recorded in audit entries.
+    id: Uuid,
+    /// Log receiving the transaction's audit trail, if any.
+    audit: Option<Arc<Mutex<AuditLog>>>,
+    /// Directory to keep the commit's journal in, if any.
+    journal_dir: Option<PathBuf>,
+    /// Journal of the commit in progress.
+    journal: Option<Journal>,
     /// Pending operations in the order they were enqueued.
     pending: Vec<FsOp>,
     /// Temporary files created during staging (cleaned up on rollback).
     staged_temps: Vec<PathBuf>,
+    /// How to undo the changes applied so far by `commit`, in order.
+    undo: Vec<UndoStep>,
     /// Whether the transaction has already been finalised.
     finalised: bool,
 }
@@ -57,12 +200,35 @@ impl FsTransaction {
     /// Create a new empty transaction.
     pub fn new() -> Self {
         Self {
+            id: Uuid::new_v4(),
+            audit: None,
+            journal_dir: None,
+            journal: None,
             pending: Vec::new(),
             staged_temps: Vec::new(),
+            undo: Vec::new(),
             finalised: false,
         }
     }
 
+    /// Record the transaction's commit in `log`; see the module docs.
+    pub fn with_audit(mut self, log: Arc<Mutex<AuditLog>>) -> Self {
+        self.audit = Some(log);
+        self
+    }
+
+    /// Journal the commit in `dir`, so that [`recover`](crate::recover) can
+    /// settle it after a crash; see the module docs.
+    pub fn with_journal<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.journal_dir = Some(dir.into());
+        self
+    }
+
+    /// Identifier of this transaction, as recorded in audit entries.
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
     /// Enqueue a write operation.  Content is not written to disk until `commit`.
     pub fn write_file(&mut self, target: PathBuf, content: Vec<u8>) -> Result<(), FsError> {
         if self.finalised { return Err(FsError::AlreadyFinalised); }
@@ -87,38 +253,232 @@ pub fn create_dir(&mut self, target: PathBuf) -> Result<(), FsError> {
     /// Commit all pending operations atomically.
     ///
     /// WriteFile ops are staged to a temp file in the same directory
-    /// and then atomically renamed to the target path.
+    /// and then atomically renamed to the target path. When audited, a
+    /// failure to write the `TransactionBegin` entry stops the commit with
+    /// [`FsError::Audit`] before anything is applied, as does a failure to
+    /// create the journal.
+    ///
+    /// # Errors
+    ///
+    /// If an operation or its audit entry fails, the operations applied so
+    /// far are undone and [`FsError::RolledBack`] reports how far that got;
+    /// see the module docs. [`FsError::CommittedUnaudited`] means the
+    /// opposite: the commit took effect and cannot be undone, and only its
+    /// final audit entry is missing.
     pub fn commit(mut self) -> Result<(), FsError> {
         if self.finalised { return Err(FsError::AlreadyFinalised); }
+        let tx_id = self.id.to_string();
+        let ops: Vec<FsOp> = self.pending.drain(..).collect();
+        let planned_ops: Vec<Operation> = ops.iter().map(FsOp::audit_operation).collect();
 
-        for op in self.pending.drain(..) {
-            match op {
-                FsOp::WriteFile { target, content } => {
-                    // Stage to a sibling temp file, then atomically rename.
-                    let temp = target.with_extension("tmp_fs_tx");
-                    if let Some(parent) = target.parent() {
-                        fs::create_dir_all(parent)?;
-                    }
-                    fs::write(&temp, &content)?;
-                    self.staged_temps.push(temp.clone());
-                    fs::rename(&temp, &target)?;
-                    // Rename succeeded — remove from rollback list.
-                    self.staged_temps.retain(|p| p != &temp);
-                }
-                FsOp::DeleteFile { target } => {
-                    if target.exists() {
-                        fs::remove_file(&target)?;
-                    }
-                }
-                FsOp::CreateDir { target } => {
-                    fs::create_dir_all(&target)?;
+        if let Some(dir) = &self.journal_dir {
+            self.journal = Some(Journal::create(dir, &tx_id, planned_ops.clone())?);
+        }
+        if let Err(e) = self.audit(Operation::TransactionBegin { tx_id: tx_id.clone(), planned_ops }, EntryMeta::default()) {
+            self.abort();
+            return Err(e);
+        }
+
+        let planned = ops.len() as u64;
+        let mut applied = 0;
+        let mut failure = None;
+        for op in ops {
+            let operation = op.audit_operation();
+            let meta = EntryMeta::default().with_tx_id(tx_id.clone());
+            let logged = match self.apply_audited(op) {
+                Ok(change) => self.audit(operation, EntryMeta { outcome: Some(Outcome::Success), change, ..meta }),
+                Err(e) => {
+                    let outcome = Outcome::Failure { error: e.to_string() };
+                    // The operation's own error is the one worth reporting.
+                    let _ = self.audit(operation, EntryMeta { outcome: Some(outcome), ..meta });
+                    Err(e)
                 }
+            };
+            if let Err(e) = logged.and_then(|()| self.journal(Record::Applied { index: applied as usize })) {
+                failure = Some(e);
+                break;
             }
+            applied += 1;
         }
+        let failure = failure.or_else(|| self.journal(Record::Committed).err());
 
+        if let Some(cause) = failure {
+            let report = self.abort();
+            let mut error = cause.to_string();
+            if !report.is_complete() {
+                error.push_str(&format!("; {} changes could not be undone", report.failed.len()));
+            }
+            // A log that cannot record the rollback still shows the
+            // transaction as incomplete, which is all it knows.
+            let _ = self.audit(Operation::TransactionRolledBack { tx_id, applied, error }, EntryMeta::default());
+            return Err(FsError::RolledBack { cause: Box::new(cause), report });
+        }
+
+        self.finalised = true;
+        self.discard_backups();
+        if let Some(journal) = self.journal.take() {
+            // A journal left behind records the commit as finished, and
+            // `recover` just deletes it again.
+            let _ = journal.remove();
+        }
+        self.audit(Operation::TransactionCommitted { tx_id, applied: planned }, EntryMeta::default())
+            .map_err(|e| match e {
+                FsError::Audit(e) => FsError::CommittedUnaudited(e),
+                other => other,
+            })
+    }
+
+    /// Remove staged temporary files, then undo every change applied so
+    /// far, most recent first.
+    fn rollback(&mut self) -> RollbackReport {
+        for temp in self.staged_temps.drain(..) {
+            let _ = fs::remove_file(temp);
+        }
+        let mut report = RollbackReport::default();
+        while let Some(step) = self.undo.pop() {
+            match step.undo() {
+                Ok(()) => report.undone.push(step),
+                Err(e) => report.failed.push((step, e)),
+            }
+        }
+        report
+    }
+
+    /// Roll back, then settle the journal if the rollback was complete; an
+    /// incomplete one is left for `recover` to retry. The transaction is
+    /// finalised either way, so dropping it cannot roll back again and,
+    /// finding nothing left to undo, settle a journal kept for `recover`.
+    fn abort(&mut self) -> RollbackReport {
         self.finalised = true;
+        let report = self.rollback();
+        if report.is_complete() {
+            if let Some(mut journal) = self.journal.take() {
+                // Recovery would only undo the same changes again.
+                let _ = journal.record(&Record::RolledBack).and_then(|()| journal.remove());
+            }
+        }
+        report
+    }
+
+    /// Append `record` to the journal, if there is one.
+    fn journal(&mut self, record: Record) -> Result<(), FsError> {
+        match &mut self.journal {
+            Some(journal) => journal.record(&record).map_err(FsError::from),
+            None => Ok(()),
+        }
+    }
+
+    /// Remove the backups of a committed transaction's originals.
+    fn discard_backups(&mut self) {
+        for step in self.undo.drain(..) {
+            if let UndoStep::Restore { backup, .. } = step {
+                let _ = fs::remove_file(backup);
+            }
+        }
+    }
+
+    /// Where the content `target` has before its next change is kept until
+    /// the commit finishes. Numbered by undo step, so a target changed twice
+    /// keeps both backups and rollback ends at the original.
+    fn backup_path(&self, target: &Path) -> PathBuf {
+        let name = target.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        target.with_file_name(format!(".{name}.{}.{}.bak_fs_tx", self.id.simple(), self.undo.len()))
+    }
+
+    /// Create `dir` and any missing ancestors, noting each one created.
+    fn create_dirs(&mut self, dir: &Path) -> Result<(), FsError> {
+        let missing: Vec<PathBuf> = dir
+            .ancestors()
+            .take_while(|d| !d.as_os_str().is_empty() && !d.exists())
+            .map(Path::to_path_buf)
+            .collect();
+        for created in missing.iter().rev() {
+            self.journal(Record::Undo { step: UndoStep::RemoveDir { target: created.clone() } })?;
+        }
+        fs::create_dir_all(dir)?;
+        for created in missing.into_iter().rev() {
+            self.undo.push(UndoStep::RemoveDir { target: created });
+        }
         Ok(())
     }
+
+    /// Copy `target` to `backup`, via a partial file so that `backup` only
+    /// ever exists complete.
+    fn save_copy(target: &Path, backup: &Path) -> io::Result<()> {
+        let partial = partial_path(backup);
+        let copied = fs::copy(target, &partial)
+            .and_then(|_| fs::File::open(&partial)?.sync_all())
+            .and_then(|()| fs::rename(&partial, backup));
+        if copied.is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+        copied
+    }
+
+    /// Apply one operation, capturing the target's content before and after
+    /// when the transaction is audited.
+    fn apply_audited(&mut self, op: FsOp) -> Result<Option<Change>, FsError> {
+        let target = match &op {
+            FsOp::WriteFile { target, .. } | FsOp::DeleteFile { target } if self.audit.is_some() => Some(target.clone()),
+            _ => None,
+        };
+        let before = target.as_deref().map(audit_log::content_hash).transpose()?;
+        self.apply(op)?;
+        let after = target.as_deref().map(audit_log::content_hash).transpose()?;
+        Ok(before.zip(after).map(|(before, after)| Change::Content { before, after }))
+    }
+
+    /// Apply one operation, first noting how to undo it.
+    fn apply(&mut self, op: FsOp) -> Result<(), FsError> {
+        match op {
+            FsOp::WriteFile { target, content } => {
+                // Stage to a sibling temp file, then atomically rename.
+                let temp = target.with_extension("tmp_fs_tx");
+                if let Some(parent) = target.parent() {
+                    self.create_dirs(parent)?;
+                }
+                self.journal(Record::Temp { path: temp.clone() })?;
+                fs::write(&temp, &content)?;
+                self.staged_temps.push(temp.clone());
+                let undo = if target.exists() {
+                    let backup = self.backup_path(&target);
+                    let undo = UndoStep::Restore { target: target.clone(), backup: backup.clone() };
+                    self.journal(Record::Undo { step: undo.clone() })?;
+                    Self::save_copy(&target, &backup)?;
+                    undo
+                } else {
+                    let undo = UndoStep::RemoveFile { target: target.clone() };
+                    self.journal(Record::Undo { step: undo.clone() })?;
+                    undo
+                };
+                self.undo.push(undo);
+                fs::rename(&temp, &target)?;
+                // Rename succeeded — remove from rollback list.
+                self.staged_temps.retain(|p| p != &temp);
+            }
+            FsOp::DeleteFile { target } => {
+                if target.exists() {
+                    // Keep the original until the commit finishes.
+                    let backup = self.backup_path(&target);
+                    let undo = UndoStep::Restore { target: target.clone(), backup: backup.clone() };
+                    self.journal(Record::Undo { step: undo.clone() })?;
+                    fs::rename(&target, &backup)?;
+                    self.undo.push(undo);
+                }
+            }
+            FsOp::CreateDir { target } => {
+                self.create_dirs(&target)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Append `operation` to the audit log, if there is one.
+    fn audit(&self, operation: Operation, meta: EntryMeta) -> Result<(), FsError> {
+        let Some(log) = &self.audit else { return Ok(()) };
+        let mut log = log.lock().unwrap_or_else(|e| e.into_inner());
+        log.append_with(operation, meta).map(|_| ()).map_err(FsError::Audit)
+    }
 }
 
 impl Default for FsTransaction {
@@ -128,12 +488,11 @@ fn default() -> Self {
 }
 
 impl Drop for FsTransaction {
-    /// Automatic rollback: remove any staged temporary files.
+    /// Automatic rollback: remove any staged temporary files and undo any
+    /// changes a commit interrupted part way had applied.
     fn drop(&mut self) {
         if !self.finalised {
-            for temp in &self.staged_temps {
-                let _ = fs::remove_file(temp);
-            }
+            self.abort();
         }
     }
 }
@@ -0,0 +1,410 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+// Integration tests for the `fs_ops` crate.
+// Covers: audited transaction commits, incomplete-transaction reporting,
+// and rollback of partially applied commits.
+
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use capability::audit_log::{self, AuditLog, AuditLogOptions, Change, Clock, ClockSkew, LogEntry, Operation, Outcome};
+use fs_ops::{recover, FsError, FsTransaction, Recovery, UndoStep};
+
+// ─── Helpers ────────────────────────────────────────────────────────────────
+
+fn scratch() -> tempfile::TempDir {
+    tempfile::tempdir().expect("create temp dir")
+}
+
+fn open_log(path: &std::path::Path) -> Arc<Mutex<AuditLog>> {
+    Arc::new(Mutex::new(AuditLog::open(path).expect("open audit log")))
+}
+
+fn read_log(path: &std::path::Path) -> Vec<LogEntry> {
+    AuditLog::entries(path).expect("read log").map(|e| e.expect("entry")).collect()
+}
+
+// ─── Audited commits ────────────────────────────────────────────────────────
+
+/// A committed transaction logs begin, one tagged entry per operation with
+/// content hashes, and a commit marker.
+#[test]
+fn audited_commit_logs_begin_ops_and_commit() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let doomed = tmp.path().join("doomed.txt");
+    fs::write(&doomed, "old bytes").expect("seed file");
+    let doomed_hash = audit_log::content_hash(&doomed).expect("hash");
+
+    let mut tx = FsTransaction::new().with_audit(open_log(&log_path));
+    let tx_id = tx.id().to_string();
+    tx.create_dir(tmp.path().join("out")).expect("enqueue");
+    tx.write_file(tmp.path().join("out/new.txt"), b"new bytes".to_vec()).expect("enqueue");
+    tx.delete_file(doomed.clone()).expect("enqueue");
+    tx.commit().expect("commit");
+
+    let entries = read_log(&log_path);
+    assert_eq!(entries.len(), 5);
+    match &entries[0].operation {
+        Operation::TransactionBegin { tx_id: id, planned_ops } => {
+            assert_eq!(id, &tx_id);
+            assert_eq!(planned_ops.len(), 3);
+        }
+        other => panic!("expected TransactionBegin, got {other:?}"),
+    }
+    for entry in &entries[1..4] {
+        assert_eq!(entry.tx_id.as_deref(), Some(tx_id.as_str()));
+        assert_eq!(entry.outcome, Some(Outcome::Success));
+    }
+    assert!(matches!(&entries[2].change, Some(Change::Content { before: None, after: Some(_) })));
+    assert_eq!(entries[3].change, Some(Change::Content { before: doomed_hash, after: None }));
+    assert!(matches!(&entries[4].operation, Operation::TransactionCommitted { applied: 3, .. }));
+    assert!(audit_log::incomplete_transactions(&log_path).expect("query").is_empty());
+    assert_eq!(AuditLog::verify(&log_path).expect("verify"), 5);
+}
+
+/// A failing operation is logged with its error, followed by a rollback
+/// marker that records how far the transaction got, and what it applied is
+/// undone.
+#[test]
+fn audited_commit_failure_logs_rollback() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let blocker = tmp.path().join("blocker");
+    fs::write(&blocker, "a file, not a directory").expect("seed file");
+
+    let mut tx = FsTransaction::new().with_audit(open_log(&log_path));
+    tx.write_file(tmp.path().join("ok.txt"), b"ok".to_vec()).expect("enqueue");
+    tx.create_dir(blocker.join("sub")).expect("enqueue");
+    match tx.commit() {
+        Err(FsError::RolledBack { cause, report }) => {
+            assert!(matches!(*cause, FsError::Io(_)));
+            assert!(report.is_complete());
+        }
+        other => panic!("expected RolledBack, got {other:?}"),
+    }
+    assert!(!tmp.path().join("ok.txt").exists(), "applied write is undone");
+
+    let entries = read_log(&log_path);
+    assert!(matches!(&entries[2].outcome, Some(Outcome::Failure { .. })));
+    assert!(matches!(&entries[3].operation, Operation::TransactionRolledBack { applied: 1, .. }));
+    assert!(audit_log::incomplete_transactions(&log_path).expect("query").is_empty());
+}
+
+/// A transaction whose commit never finished is reported as incomplete,
+/// with the operations it managed to apply.
+#[test]
+fn crash_mid_commit_leaves_incomplete_transaction() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let mut tx = FsTransaction::new().with_audit(open_log(&log_path));
+    tx.write_file(tmp.path().join("a.txt"), b"a".to_vec()).expect("enqueue");
+    tx.write_file(tmp.path().join("b.txt"), b"b".to_vec()).expect("enqueue");
+    let tx_id = tx.id().to_string();
+    tx.commit().expect("commit");
+
+    // Simulate a crash before the second write and the commit marker by
+    // keeping only the first two entries.
+    let entries = read_log(&log_path);
+    let kept: String = entries[..2].iter()
+        .map(|e| serde_json::to_string(e).expect("serialise") + "\n")
+        .collect();
+    fs::write(&log_path, kept).expect("truncate log");
+    fs::remove_file(tmp.path().join("audit.log.idx")).expect("drop index");
+
+    let incomplete = audit_log::incomplete_transactions(&log_path).expect("query");
+    assert_eq!(incomplete.len(), 1);
+    assert_eq!(incomplete[0].tx_id, tx_id);
+    assert_eq!((incomplete[0].planned_ops.len(), incomplete[0].applied.len()), (2, 1));
+}
+
+/// A clock that steps back a second from its `regress_from`th reading on.
+#[derive(Debug)]
+struct SteppingBackClock {
+    readings: AtomicUsize,
+    regress_from: usize,
+}
+
+impl Clock for SteppingBackClock {
+    fn now(&self) -> chrono::DateTime<chrono::Utc> {
+        let start = chrono::DateTime::from_timestamp(1_750_000_000, 0).expect("valid timestamp");
+        if self.readings.fetch_add(1, Ordering::SeqCst) < self.regress_from {
+            start
+        } else {
+            start - chrono::Duration::seconds(1)
+        }
+    }
+}
+
+/// A commit whose closing audit entry cannot be written has still taken
+/// effect, and says so instead of reporting an ordinary audit failure.
+#[test]
+fn commit_applied_but_unlogged_is_reported_as_committed() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let target = tmp.path().join("a.txt");
+    // Readings: the new log's header, TransactionBegin, the write, then
+    // TransactionCommitted.
+    let clock = SteppingBackClock { readings: AtomicUsize::new(0), regress_from: 3 };
+    let options = AuditLogOptions { clock: Some(Arc::new(clock)), clock_skew: ClockSkew::Reject, ..Default::default() };
+    let log = Arc::new(Mutex::new(AuditLog::open_with(&log_path, options).expect("open audit log")));
+
+    let mut tx = FsTransaction::new().with_audit(log);
+    let tx_id = tx.id().to_string();
+    tx.write_file(target.clone(), b"kept".to_vec()).expect("enqueue");
+    assert!(matches!(tx.commit(), Err(FsError::CommittedUnaudited(_))));
+
+    assert_eq!(fs::read_to_string(&target).expect("read"), "kept");
+    assert_eq!(listing(tmp.path()), ["a.txt", "audit.log", "audit.log.idx"]);
+    let incomplete = audit_log::incomplete_transactions(&log_path).expect("query");
+    assert_eq!((incomplete.len(), incomplete[0].tx_id.as_str()), (1, tx_id.as_str()));
+}
+
+// ─── Rollback ───────────────────────────────────────────────────────────────
+
+/// Names of the files left in `dir`, sorted.
+fn listing(dir: &std::path::Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir).expect("list dir")
+        .map(|e| e.expect("dir entry").file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+/// A failure after an overwrite, a delete and a write into a new directory
+/// restores the originals and removes what was created, newest first.
+#[test]
+fn failed_commit_restores_overwritten_and_deleted_files() {
+    let tmp = scratch();
+    let (kept, doomed) = (tmp.path().join("kept.txt"), tmp.path().join("doomed.txt"));
+    fs::write(&kept, "original").expect("seed file");
+    fs::write(&doomed, "precious").expect("seed file");
+    let blocker = tmp.path().join("blocker");
+    fs::write(&blocker, "a file, not a directory").expect("seed file");
+    let before = listing(tmp.path());
+
+    let mut tx = FsTransaction::new();
+    tx.write_file(kept.clone(), b"overwritten".to_vec()).expect("enqueue");
+    tx.delete_file(doomed.clone()).expect("enqueue");
+    tx.write_file(tmp.path().join("new/deep/file.txt"), b"new".to_vec()).expect("enqueue");
+    tx.create_dir(blocker.join("sub")).expect("enqueue");
+    let report = match tx.commit() {
+        Err(FsError::RolledBack { report, .. }) => report,
+        other => panic!("expected RolledBack, got {other:?}"),
+    };
+
+    assert_eq!(fs::read_to_string(&kept).expect("read"), "original");
+    assert_eq!(fs::read_to_string(&doomed).expect("read"), "precious");
+    assert_eq!(listing(tmp.path()), before, "no backups, temps or new directories remain");
+    assert!(report.is_complete());
+    let steps: Vec<&UndoStep> = report.undone.iter().collect();
+    assert_eq!(steps.len(), 5);
+    assert_eq!(steps[0], &UndoStep::RemoveFile { target: tmp.path().join("new/deep/file.txt") });
+    assert_eq!(steps[1], &UndoStep::RemoveDir { target: tmp.path().join("new/deep") });
+    assert_eq!(steps[2], &UndoStep::RemoveDir { target: tmp.path().join("new") });
+    assert!(matches!(steps[3], UndoStep::Restore { target, .. } if target == &doomed));
+    assert!(matches!(steps[4], UndoStep::Restore { target, .. } if target == &kept));
+}
+
+/// A file written twice before a failure gets its original back, not the
+/// first write.
+#[test]
+fn failed_commit_restores_original_of_file_written_twice() {
+    let tmp = scratch();
+    let target = tmp.path().join("target.txt");
+    fs::write(&target, "orig").expect("seed file");
+    let dir = tmp.path().join("dir");
+    fs::create_dir(&dir).expect("mkdir");
+
+    let mut tx = FsTransaction::new();
+    tx.write_file(target.clone(), b"v1".to_vec()).expect("enqueue");
+    tx.write_file(target.clone(), b"v2".to_vec()).expect("enqueue");
+    tx.write_file(dir, b"not a file".to_vec()).expect("enqueue");
+    let report = match tx.commit() {
+        Err(FsError::RolledBack { report, .. }) => report,
+        other => panic!("expected RolledBack, got {other:?}"),
+    };
+
+    assert!(report.is_complete(), "failed steps: {:?}", report.failed);
+    assert_eq!(report.undone.len(), 2);
+    assert_eq!(fs::read_to_string(&target).expect("read"), "orig");
+    assert_eq!(listing(tmp.path()), ["dir", "target.txt"]);
+}
+
+/// A successful commit removes the backups it took, and an audited commit
+/// whose rollback is complete is logged as rolled back with the files
+/// restored.
+#[test]
+fn commit_discards_backups_and_audited_rollback_restores_files() {
+    let tmp = scratch();
+    let log_path = tmp.path().join("audit.log");
+    let (a, b) = (tmp.path().join("a.txt"), tmp.path().join("b.txt"));
+    fs::write(&a, "a0").expect("seed file");
+    fs::write(&b, "b0").expect("seed file");
+    let dir = tmp.path().join("dir");
+    fs::create_dir(&dir).expect("mkdir");
+
+    let mut tx = FsTransaction::new().with_audit(open_log(&log_path));
+    tx.write_file(a.clone(), b"a1".to_vec()).expect("enqueue");
+    tx.delete_file(b.clone()).expect("enqueue");
+    tx.commit().expect("commit");
+    assert_eq!(listing(tmp.path()), ["a.txt", "audit.log", "audit.log.idx", "dir"]);
+
+    let mut tx = FsTransaction::new().with_audit(open_log(&log_path));
+    let tx_id = tx.id().to_string();
+    tx.write_file(a.clone(), b"a2".to_vec()).expect("enqueue");
+    tx.write_file(dir, b"not a file".to_vec()).expect("enqueue");
+    assert!(matches!(tx.commit(), Err(FsError::RolledBack { .. })));
+    assert_eq!(fs::read_to_string(&a).expect("read"), "a1");
+    assert_eq!(listing(tmp.path()), ["a.txt", "audit.log", "audit.log.idx", "dir"]);
+
+    let timeline = audit_log::Timeline::replay(&log_path).expect("replay");
+    assert_eq!(timeline.transaction_state(&tx_id), Some(audit_log::TxState::RolledBack));
+    let producer = timeline.current_producer(&a).expect("hash").expect("producer");
+    assert_ne!(producer.tx_id.as_deref(), Some(tx_id.as_str()), "the rolled-back write did not produce a.txt");
+}
+
+// ─── Crash recovery ─────────────────────────────────────────────────────────
+
+/// Write a journal as a commit interrupted after `records` would have left it.
+fn write_journal(dir: &std::path::Path, tx_id: &str, records: &[serde_json::Value]) {
+    fs::create_dir_all(dir).expect("mkdir");
+    let lines: Vec<String> = records.iter().map(|r| format!("{r}\n")).collect();
+    fs::write(dir.join(format!("{tx_id}.journal")), lines.concat()).expect("write journal");
+}
+
+/// Journalled commits leave no journal behind, and a commit interrupted
+/// part way is rolled back by `recover`, which can safely run again.
+#[test]
+fn recover_rolls_back_interrupted_commit_idempotently() {
+    let tmp = scratch();
+    let journals = tmp.path().join("journals");
+    let work = tmp.path().join("work");
+    fs::create_dir(&work).expect("mkdir");
+    let (kept, doomed) = (work.join("kept.txt"), work.join("doomed.txt"));
+    fs::write(&kept, "original").expect("seed file");
+    fs::write(&doomed, "precious").expect("seed file");
+
+    let mut tx = FsTransaction::new().with_journal(&journals);
+    tx.write_file(work.join("a.txt"), b"a".to_vec()).expect("enqueue");
+    tx.commit().expect("commit");
+    let mut tx = FsTransaction::new().with_journal(&journals);
+    tx.write_file(work.join("b.txt"), b"b".to_vec()).expect("enqueue");
+    tx.create_dir(kept.join("sub")).expect("enqueue");
+    assert!(matches!(tx.commit(), Err(FsError::RolledBack { .. })));
+    assert!(listing(&journals).is_empty(), "settled commits remove their journals");
+    fs::remove_file(work.join("a.txt")).expect("remove");
+
+    // The crash came after overwriting kept.txt, creating new/file.txt and
+    // moving doomed.txt to its backup, while staging another temp.
+    let kept_backup = work.join(".kept.txt.1.bak_fs_tx");
+    let doomed_backup = work.join(".doomed.txt.1.bak_fs_tx");
+    let new_dir = work.join("new");
+    let new_file = new_dir.join("file.txt");
+    let stray_temp = work.join("late.tmp_fs_tx");
+    fs::copy(&kept, &kept_backup).expect("backup");
+    fs::write(&kept, "overwritten").expect("overwrite");
+    fs::create_dir(&new_dir).expect("mkdir");
+    fs::write(&new_file, "new").expect("create");
+    fs::rename(&doomed, &doomed_backup).expect("delete");
+    fs::write(&stray_temp, "half").expect("temp");
+    write_journal(&journals, "tx-1", &[
+        serde_json::json!({"Begin": {"tx_id": "tx-1", "planned_ops": []}}),
+        serde_json::json!({"Temp": {"path": work.join("kept.tmp_fs_tx")}}),
+        serde_json::json!({"Undo": {"step": {"Restore": {"target": kept, "backup": kept_backup}}}}),
+        serde_json::json!({"Applied": {"index": 0}}),
+        serde_json::json!({"Undo": {"step": {"RemoveDir": {"target": new_dir}}}}),
+        serde_json::json!({"Temp": {"path": new_dir.join("file.tmp_fs_tx")}}),
+        serde_json::json!({"Undo": {"step": {"RemoveFile": {"target": new_file}}}}),
+        serde_json::json!({"Applied": {"index": 1}}),
+        serde_json::json!({"Undo": {"step": {"Restore": {"target": doomed, "backup": doomed_backup}}}}),
+        serde_json::json!({"Applied": {"index": 2}}),
+        serde_json::json!({"Temp": {"path": stray_temp}}),
+    ]);
+
+    let recovered = recover(&journals).expect("recover");
+    assert_eq!(recovered.len(), 1);
+    assert_eq!(recovered[0].tx_id, "tx-1");
+    assert_eq!(recovered[0].applied, 3);
+    let Recovery::RolledBack(report) = &recovered[0].outcome else { panic!("expected a rollback") };
+    assert!(report.is_complete());
+    assert_eq!(report.undone.len(), 4);
+    assert_eq!(fs::read_to_string(&kept).expect("read"), "original");
+    assert_eq!(fs::read_to_string(&doomed).expect("read"), "precious");
+    assert_eq!(listing(&work), ["doomed.txt", "kept.txt"]);
+    assert!(listing(&journals).is_empty());
+
+    // A recovery interrupted before deleting the journal runs again harmlessly.
+    write_journal(&journals, "tx-1", &[
+        serde_json::json!({"Undo": {"step": {"Restore": {"target": kept, "backup": kept_backup}}}}),
+        serde_json::json!({"Undo": {"step": {"RemoveFile": {"target": new_file}}}}),
+    ]);
+    let recovered = recover(&journals).expect("recover again");
+    assert!(matches!(&recovered[0].outcome, Recovery::RolledBack(report) if report.is_complete()));
+    assert_eq!(fs::read_to_string(&kept).expect("read"), "original");
+    assert!(recover(&journals).expect("nothing left").is_empty());
+    assert!(recover(tmp.path().join("missing")).expect("no directory").is_empty());
+}
+
+/// A journal kept because rollback was incomplete survives the failed
+/// transaction being dropped, and `recover` settles it.
+#[test]
+fn incomplete_rollback_keeps_journal_for_recover() {
+    let tmp = scratch();
+    let journals = tmp.path().join("journals");
+    let dir = tmp.path().join("dir");
+    fs::create_dir(&dir).expect("mkdir");
+
+    // Undoing `a/b/..` means removing `a` before `a/b`, which fails.
+    let mut tx = FsTransaction::new().with_journal(&journals);
+    tx.create_dir(tmp.path().join("a/b/..")).expect("enqueue");
+    tx.write_file(dir, b"not a file".to_vec()).expect("enqueue");
+    let report = match tx.commit() {
+        Err(FsError::RolledBack { report, .. }) => report,
+        other => panic!("expected RolledBack, got {other:?}"),
+    };
+    assert!(!report.is_complete());
+    assert_eq!(listing(&journals).len(), 1, "the journal outlives the transaction");
+
+    let recovered = recover(&journals).expect("recover");
+    assert!(matches!(&recovered[0].outcome, Recovery::RolledBack(report) if report.is_complete()));
+    assert!(listing(&journals).is_empty());
+    assert_eq!(listing(tmp.path()), ["dir", "journals"]);
+}
+
+/// A commit that crashed after committing only has its leftovers removed;
+/// a torn last record is ignored but a corrupt earlier one is an error.
+#[test]
+fn recover_finishes_committed_transaction_and_rejects_corrupt_journal() {
+    let tmp = scratch();
+    let journals = tmp.path().join("journals");
+    let target = tmp.path().join("target.txt");
+    let backup = tmp.path().join(".target.txt.2.bak_fs_tx");
+    fs::write(&target, "committed").expect("seed file");
+    fs::write(&backup, "original").expect("seed backup");
+    write_journal(&journals, "tx-2", &[
+        serde_json::json!({"Begin": {"tx_id": "tx-2", "planned_ops": [{"FileWrite": {"path": target}}]}}),
+        serde_json::json!({"Undo": {"step": {"Restore": {"target": target, "backup": backup}}}}),
+        serde_json::json!({"Applied": {"index": 0}}),
+        serde_json::json!("Committed"),
+    ]);
+    let path = journals.join("tx-2.journal");
+    let mut text = fs::read_to_string(&path).expect("read journal");
+    text.push_str("{\"Appl");
+    fs::write(&path, text).expect("tear journal");
+
+    let recovered = recover(&journals).expect("recover");
+    assert!(matches!(recovered[0].outcome, Recovery::Finished));
+    assert!(matches!(&recovered[0].planned_ops[..], [Operation::FileWrite { path }] if path == &target));
+    assert_eq!(fs::read_to_string(&target).expect("read"), "committed");
+    assert_eq!(listing(tmp.path()), ["journals", "target.txt"]);
+    assert!(listing(&journals).is_empty());
+
+    fs::write(journals.join("tx-3.journal"), "{\"Begin\"\n\"Committed\"\n").expect("write journal");
+    match recover(&journals) {
+        Err(FsError::Journal { line, .. }) => assert_eq!(line, 1),
+        other => panic!("expected a journal error, got {other:?}"),
+    }
+    assert_eq!(listing(&journals), ["tx-3.journal"], "an unreadable journal is left alone");
+}
@@ -0,0 +1,146 @@
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
+//
+//! ANCHORING: Audit log heads recorded in git history.
+//!
+//! Each anchor is a commit on a dedicated ref (by default
+//! [`AUDIT_ANCHOR_REF`]) whose tree holds one file, `anchor.json`, with the
+//! log's entry count and head hash. The commits form a linear history, so
+//! every anchor ever taken stays reachable, and rewriting the audit log
+//! undetectably would also mean rewriting this ref — which pushes, remotes
+//! and reflogs make visible. The ref can live in the repository being
+//! fixed or in a dedicated anchor repository.
+//!
+//! AUDITING:
+//! [`anchor_audit_log_audited`] also records each anchoring in an open
+//! audit log, as a `git_ops.log_anchored` custom entry carrying its
+//! `Outcome` and, once the ref has moved, a `Change::Git` with the ref's
+//! tip before and after. Anchoring a log into itself is fine: the entry
+//! lands after the head it anchors, and the next anchor covers it.
+
+use std::path::Path;
+use capability::{AuditLog, Change, EntryMeta, LogAnchor, Operation, Outcome};
+use git2::{Oid, Repository, Signature, Sort};
+
+use crate::GitError;
+
+/// Default ref anchors are committed to.
+pub const AUDIT_ANCHOR_REF: &str = "refs/notes/polysafe-audit";
+
+/// Name of the file holding the anchor in each anchor commit's tree.
+const ANCHOR_FILE: &str = "anchor.json";
+
+/// MUTATION: Verify the audit log at `log_path` and commit its current
+/// head to [`AUDIT_ANCHOR_REF`] in the repository at `repo_path`.
+pub fn anchor_audit_log<P: AsRef<Path>, Q: AsRef<Path>>(repo_path: P, log_path: Q) -> Result<LogAnchor, GitError> {
+    anchor_audit_log_to(repo_path, log_path, AUDIT_ANCHOR_REF)
+}
+
+/// MUTATION: Like [`anchor_audit_log`], committing to `refname`.
+///
+/// The new commit's parent is the ref's current tip; if the ref moves
+/// concurrently the update fails rather than dropping an anchor.
+pub fn anchor_audit_log_to<P: AsRef<Path>, Q: AsRef<Path>>(
+    repo_path: P,
+    log_path: Q,
+    refname: &str,
+) -> Result<LogAnchor, GitError> {
+    commit_anchor(repo_path.as_ref(), log_path.as_ref(), refname).map(|(anchor, _)| anchor)
+}
+
+/// MUTATION: Like [`anchor_audit_log_to`], recording the anchoring and
+/// its outcome in `audit`; see the module docs.
+///
+/// `audit` is flushed first, so entries it has buffered are anchored too.
+/// A failed anchoring is recorded as `Outcome::Failure` before its error
+/// is returned.
+pub fn anchor_audit_log_audited<P: AsRef<Path>, Q: AsRef<Path>>(
+    repo_path: P,
+    log_path: Q,
+    refname: &str,
+    audit: &mut AuditLog,
+) -> Result<LogAnchor, GitError> {
+    let (repo_path, log_path) = (repo_path.as_ref(), log_path.as_ref());
+    audit.flush()?;
+    let result = commit_anchor(repo_path, log_path, refname);
+    let mut details = serde_json::json!({
+        "repo_path": repo_path,
+        "log_path": log_path,
+        "refname": refname,
+    });
+    let meta = match &result {
+        Ok((anchor, change)) => {
+            details["entries"] = anchor.entries.into();
+            details["head_hash"] = anchor.head_hash.clone().into();
+            EntryMeta { outcome: Some(Outcome::Success), change: Some(change.clone()), ..Default::default() }
+        }
+        Err(e) => EntryMeta::outcome(Outcome::Failure { error: e.to_string() }),
+    };
+    audit.append_with(Operation::Custom { kind: "git_ops.log_anchored".into(), details }, meta)?;
+    result.map(|(anchor, _)| anchor)
+}
+
+/// Commit the head of the log at `log_path` to `refname`, returning the
+/// anchor and the ref's tip before and after.
+fn commit_anchor(repo_path: &Path, log_path: &Path, refname: &str) -> Result<(LogAnchor, Change), GitError> {
+    let repo = open(repo_path)?;
+    let anchor = LogAnchor::of(log_path)?;
+
+    let json = serde_json::to_vec_pretty(&anchor).map_err(std::io::Error::other)?;
+    let blob = repo.blob(&json)?;
+    let mut builder = repo.treebuilder(None)?;
+    builder.insert(ANCHOR_FILE, blob, git2::FileMode::Blob.into())?;
+    let tree = repo.find_tree(builder.write()?)?;
+
+    let parent = match repo.refname_to_id(refname) {
+        Ok(oid) => Some(repo.find_commit(oid)?),
+        Err(e) if e.code() == git2::ErrorCode::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let signature = repo.signature().or_else(|_| Signature::now("polysafe-audit", "polysafe-audit@localhost"))?;
+    let message = format!("polysafe-audit anchor: {} entries, head {}\n", anchor.entries, anchor.head_hash);
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+    let new_oid = repo.commit(Some(refname), &signature, &signature, &message, &tree, &parents)?;
+    let change = Change::Git {
+        old_oid: parent.map(|commit| commit.id().to_string()),
+        new_oid: Some(new_oid.to_string()),
+    };
+    Ok((anchor, change))
+}
+
+/// ANALYSIS: Every anchor committed to `refname`, oldest first.
+///
+/// Returns an empty list if the ref does not exist.
+pub fn audit_anchors<P: AsRef<Path>>(repo_path: P, refname: &str) -> Result<Vec<LogAnchor>, GitError> {
+    let repo = open(repo_path.as_ref())?;
+    let tip = match repo.refname_to_id(refname) {
+        Ok(oid) => oid,
+        Err(e) if e.code() == git2::ErrorCode::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    walk.push(tip)?;
+    walk.map(|oid| read_anchor(&repo, oid?)).collect()
+}
+
+/// ANALYSIS: Verify the audit log at `log_path` and check it against every
+/// anchor on [`AUDIT_ANCHOR_REF`] in the repository at `repo_path`.
+///
+/// Returns the number of entries verified.
+pub fn verify_anchored_audit_log<P: AsRef<Path>, Q: AsRef<Path>>(repo_path: P, log_path: Q) -> Result<usize, GitError> {
+    let anchors = audit_anchors(repo_path, AUDIT_ANCHOR_REF)?;
+    Ok(AuditLog::verify_anchored(log_path, &anchors)?)
+}
+
+fn open(path: &Path) -> Result<Repository, GitError> {
+    Repository::open(path).map_err(|_| GitError::NotARepository(path.display().to_string()))
+}
+
+fn read_anchor(repo: &Repository, oid: Oid) -> Result<LogAnchor, GitError> {
+    let malformed = |reason: &str| GitError::MalformedAnchor { commit: oid.to_string(), reason: reason.to_owned() };
+    let tree = repo.find_commit(oid)?.tree()?;
+    let entry = tree.get_name(ANCHOR_FILE).ok_or_else(|| malformed("no anchor.json"))?;
+    let blob = repo.find_blob(entry.id()).map_err(|_| malformed("anchor.json is not a file"))?;
+    serde_json::from_slice(blob.content()).map_err(|e| malformed(&e.to_string()))
+}
@@ -12,6 +12,9 @@
 //! 2. **Object Format Awareness**: Explicitly handle SHA-1 and SHA-256 repos.
 //! 3. **Bitemporal Logic**: Support tracking changes across both the Index
 //!    and the Working Tree.
+//!
+//! The one deliberate mutation is audit anchoring (see the `anchor`
+//! module), which only ever appends commits to its own ref.
 
 #![forbid(unsafe_code)]
 use std::path::Path;
@@ -19,6 +22,10 @@
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+mod anchor;
+
+pub use anchor::{anchor_audit_log, anchor_audit_log_audited, anchor_audit_log_to, audit_anchors, verify_anchored_audit_log, AUDIT_ANCHOR_REF};
+
 /// ERROR SPACE: Categorized failures for git operations.
 #[derive(Debug, Error)]
 pub enum GitError {
@@ -33,6 +40,14 @@ pub enum GitError {
     /// An I/O error during filesystem traversal.
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
+
+    /// The audit log failed verification or could not be read.
+    #[error("audit log error: {0}")]
+    Audit(#[from] capability::IntegrityError),
+
+    /// A commit on the anchor ref does not hold a readable anchor.
+    #[error("malformed audit anchor in commit {commit}: {reason}")]
+    MalformedAnchor { commit: String, reason: String },
 }
 
 /// The status of a single file in the working tree or index.
@@ -130,6 +145,23 @@ pub fn repo_status<P: AsRef<Path>>(path: P) -> Result<RepoStatus, GitError> {
     })
 }
 
+/// ANALYSIS: Full object id of the commit HEAD points at.
+///
+/// Returns `None` for a repository whose HEAD is unborn (no commits yet).
+/// Record the value before and after a git action as the `old_oid` and
+/// `new_oid` of a `capability::Change::Git` audit entry.
+pub fn head_oid<P: AsRef<Path>>(path: P) -> Result<Option<String>, GitError> {
+    let path_ref = path.as_ref();
+    let repo = Repository::open(path_ref)
+        .map_err(|_| GitError::NotARepository(path_ref.display().to_string()))?;
+    let head = match repo.head() {
+        Ok(head) => head,
+        Err(e) if e.code() == git2::ErrorCode::UnbornBranch => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(head.target().map(|oid| oid.to_string()))
+}
+
 /// DISCOVERY: Recursively searches for Git repositories within a directory tree.
 ///
 /// Returns a list of absolute path strings for each `.git`-bearing directory
@@ -3,11 +3,13 @@
 //
 // Integration tests for the `git_ops` crate.
 // Covers: valid repo detection, invalid path handling, repo status fields,
-// find_repos discovery, and graceful failure modes.
+// HEAD object ids, find_repos discovery, graceful failure modes, and audit
+// log anchoring, audited or not.
 
 use std::fs;
 use std::process::Command;
-use git_ops::{find_repos, repo_status, GitError};
+use capability::{AuditLog, Change, IntegrityError, LogEntry, Operation, Outcome};
+use git_ops::{anchor_audit_log, anchor_audit_log_audited, audit_anchors, find_repos, head_oid, repo_status, verify_anchored_audit_log, GitError, AUDIT_ANCHOR_REF};
 
 // ─── Helpers ────────────────────────────────────────────────────────────────
 
@@ -37,6 +39,12 @@ fn init_git_repo(dir: &std::path::Path) -> String {
     dir.display().to_string()
 }
 
+/// Full object id `rev` resolves to in `repo`.
+fn git_rev(repo: &std::path::Path, rev: &str) -> String {
+    let out = Command::new("git").args(["-C", repo.to_str().unwrap(), "rev-parse", rev]).output().expect("git rev-parse");
+    String::from_utf8_lossy(&out.stdout).trim().to_owned()
+}
+
 // ─── repo_status: valid repos ────────────────────────────────────────────────
 
 /// Calling `repo_status` on a freshly initialised repo must succeed.
@@ -81,6 +89,22 @@ fn repo_status_is_clean_consistent_with_entries() {
     );
 }
 
+/// `head_oid` is `None` before the first commit and the full object id after.
+#[test]
+fn head_oid_tracks_commits() {
+    let tmp = scratch();
+    let repo = init_git_repo(tmp.path());
+    assert_eq!(head_oid(tmp.path()).expect("head_oid on fresh repo"), None);
+
+    fs::write(tmp.path().join("a.txt"), "a").expect("write file");
+    for args in [&["add", "a.txt"][..], &["commit", "--quiet", "-m", "first"][..]] {
+        assert!(Command::new("git").arg("-C").arg(&repo).args(args).status().expect("git").success());
+    }
+    let out = Command::new("git").args(["-C", &repo, "rev-parse", "HEAD"]).output().expect("rev-parse");
+    let expected = String::from_utf8(out.stdout).expect("utf8").trim().to_owned();
+    assert_eq!(head_oid(tmp.path()).expect("head_oid"), Some(expected));
+}
+
 // ─── repo_status: invalid paths ──────────────────────────────────────────────
 
 /// A non-existent path must return `GitError::NotARepository`.
@@ -138,3 +162,95 @@ fn find_repos_empty_directory_returns_empty() {
     let found = find_repos(tmp.path(), 3).expect("find_repos on empty dir");
     assert!(found.is_empty(), "no repos in an empty directory");
 }
+
+// ─── Audit log anchoring ─────────────────────────────────────────────────────
+
+fn append_reads(log: &std::path::Path, names: &[&str]) {
+    let mut audit = AuditLog::open(log).expect("open audit log");
+    for name in names {
+        audit.append(Operation::FileRead { path: (*name).into() }).expect("append");
+    }
+}
+
+/// Anchors accumulate as commits on the anchor ref and a growing log keeps
+/// verifying against all of them.
+#[test]
+fn anchors_accumulate_and_verify() {
+    let tmp = scratch();
+    let repo = tmp.path().join("repo");
+    init_git_repo(&repo);
+    let log = tmp.path().join("audit.log");
+
+    append_reads(&log, &["a", "b"]);
+    let first = anchor_audit_log(&repo, &log).expect("anchor");
+    append_reads(&log, &["c"]);
+    let second = anchor_audit_log(&repo, &log).expect("anchor");
+    append_reads(&log, &["d"]);
+
+    let anchors = audit_anchors(&repo, AUDIT_ANCHOR_REF).expect("read anchors");
+    assert_eq!(anchors, [first, second]);
+    assert_eq!((anchors[0].entries, anchors[1].entries), (2, 3));
+    assert_eq!(verify_anchored_audit_log(&repo, &log).expect("verify"), 4);
+
+    let out = Command::new("git")
+        .args(["-C", repo.to_str().unwrap(), "rev-list", "--count", AUDIT_ANCHOR_REF])
+        .output()
+        .expect("git rev-list");
+    assert_eq!(String::from_utf8_lossy(&out.stdout).trim(), "2");
+}
+
+/// An audited anchoring records the anchor ref's move in the log it
+/// anchors, and a failed one records its failure.
+#[test]
+fn audited_anchor_records_ref_move() {
+    let tmp = scratch();
+    let repo = tmp.path().join("repo");
+    init_git_repo(&repo);
+    let log = tmp.path().join("audit.log");
+    append_reads(&log, &["a"]);
+
+    let mut audit = AuditLog::open(&log).expect("open audit log");
+    anchor_audit_log_audited(&repo, &log, AUDIT_ANCHOR_REF, &mut audit).expect("anchor");
+    let first_tip = git_rev(&repo, AUDIT_ANCHOR_REF);
+    let second = anchor_audit_log_audited(&repo, &log, AUDIT_ANCHOR_REF, &mut audit).expect("anchor");
+    let not_a_repo = tmp.path().join("missing");
+    assert!(anchor_audit_log_audited(&not_a_repo, &log, AUDIT_ANCHOR_REF, &mut audit).is_err());
+    drop(audit);
+
+    let entries: Vec<LogEntry> = AuditLog::entries(&log).expect("entries").map(|e| e.expect("entry")).collect();
+    let changes: Vec<_> = entries[1..].iter().map(|e| (e.outcome.clone(), e.change.clone())).collect();
+    assert_eq!(changes[..2], [
+        (Some(Outcome::Success), Some(Change::Git { old_oid: None, new_oid: Some(first_tip.clone()) })),
+        (Some(Outcome::Success), Some(Change::Git { old_oid: Some(first_tip), new_oid: Some(git_rev(&repo, AUDIT_ANCHOR_REF)) })),
+    ]);
+    assert!(matches!(&changes[2], (Some(Outcome::Failure { .. }), None)));
+    assert!(matches!(&entries[2].operation, Operation::Custom { kind, details }
+        if kind == "git_ops.log_anchored" && details["entries"] == second.entries));
+    assert_eq!(second.entries, 2, "the first anchoring's entry is covered by the second anchor");
+    assert_eq!(verify_anchored_audit_log(&repo, &log).expect("verify"), 4);
+}
+
+/// A log rewritten from scratch verifies on its own but not against the
+/// anchors taken before the rewrite.
+#[test]
+fn rewritten_log_fails_anchor_check() {
+    let tmp = scratch();
+    let repo = tmp.path().join("repo");
+    init_git_repo(&repo);
+    let log = tmp.path().join("audit.log");
+    append_reads(&log, &["a", "b", "c"]);
+    let anchor = anchor_audit_log(&repo, &log).expect("anchor");
+
+    fs::remove_file(&log).expect("remove log");
+    fs::remove_file(tmp.path().join("audit.log.idx")).expect("remove index");
+    append_reads(&log, &["a", "x", "c"]);
+    assert_eq!(AuditLog::verify(&log).expect("rewritten chain is intact"), 3);
+
+    match verify_anchored_audit_log(&repo, &log) {
+        Err(GitError::Audit(IntegrityError::AnchorMismatch { entries, expected, found })) => {
+            assert_eq!((entries, expected), (3, anchor.head_hash));
+            assert!(found.is_some());
+        }
+        other => panic!("expected AnchorMismatch, got {other:?}"),
+    }
+}
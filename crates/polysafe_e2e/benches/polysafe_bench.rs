@@ -2,15 +2,17 @@
 // Copyright (c) Jonathan D.A. Jewell <j.d.a.jewell@open.ac.uk>
 //
 // Criterion benchmarks for polysafe-gitfixer.
-// Three benchmarks:
+// Four benchmarks:
 //   1. bench_capability_resolve     — hot-path cost of sandbox path resolution.
 //   2. bench_fs_transaction_write   — cost of a single-file write transaction.
 //   3. bench_git_find_repos         — cost of find_repos on a small directory tree.
+//   4. bench_audit_log_append       — append throughput under each durability mode.
 
 use std::fs;
 use std::process::Command;
+use std::time::Duration;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use capability::{DirCapability, Permissions};
+use capability::{AuditLog, AuditLogOptions, DirCapability, Durability, Operation, Permissions};
 use fs_ops::FsTransaction;
 use git_ops::find_repos;
 
@@ -90,10 +92,41 @@ fn bench_git_find_repos(c: &mut Criterion) {
     });
 }
 
+// ─── Benchmark 4: audit log append per durability mode ──────────────────────
+
+/// Measures the cost of one `FileRead` append under each durability mode.
+/// `every_entry` is bounded by fsync latency; the other modes amortise it.
+fn bench_audit_log_append(c: &mut Criterion) {
+    let modes = [
+        ("every_entry", Durability::EveryEntry),
+        ("group_commit_64_5ms", Durability::GroupCommit { max_entries: 64, max_delay: Duration::from_millis(5) }),
+        ("on_flush", Durability::OnFlush),
+    ];
+
+    let mut group = c.benchmark_group("audit_log_append");
+    for (name, durability) in modes {
+        let tmp = scratch();
+        let options = AuditLogOptions { durability, ..Default::default() };
+        let mut log = AuditLog::open_with(tmp.path().join("audit.log"), options)
+            .expect("open audit log");
+        let path = tmp.path().join("scanned.txt");
+
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                log.append(black_box(Operation::FileRead { path: path.clone() }))
+                    .expect("append must succeed in benchmark");
+            });
+        });
+        log.flush().expect("flush audit log");
+    }
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_capability_resolve,
     bench_fs_transaction_write,
     bench_git_find_repos,
+    bench_audit_log_append,
 );
 criterion_main!(benches);
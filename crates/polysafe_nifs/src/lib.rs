@@ -135,15 +135,15 @@ fn open_audit_log<'a>(env: Env<'a>, path: String) -> NifResult<Term<'a>> {
 ///
 /// ## Parameters
 /// - `log_handle` - Path returned by `open_audit_log/1`
-/// - `operation`  - An Elixir map with keys `"type"` and `"details"` that is
-///                  serialised to a `capability::audit_log::Operation::Custom`
+/// - `operation`  - An Elixir term recorded as a
+///                  `capability::audit_log::Operation::Custom` of kind `nif.entry`
 ///
 /// ## Returns
 /// `:ok` on success, `{:error, reason}` on failure.
 ///
 /// Note: Currently records every entry as `Operation::Custom` with the
-/// `type` and `details` fields extracted from the Elixir term's string
-/// representation. A richer NIF-side decoder can be added later.
+/// Elixir term's string representation under `details.term`. A richer
+/// NIF-side decoder can map structured terms to their own kinds later.
 #[rustler::nif]
 fn append_audit_entry<'a>(
     env: Env<'a>,
@@ -159,12 +159,12 @@ fn append_audit_entry<'a>(
     // decode structured maps here, but for the initial bridge we capture
     // the term's debug representation as details.
     let op = capability::audit_log::Operation::Custom {
-        kind: "nif_entry".to_string(),
-        details: format!("{:?}", operation),
+        kind: "nif.entry".to_string(),
+        details: serde_json::json!({ "term": format!("{:?}", operation) }),
     };
 
     match log.append(op) {
-        Ok(()) => Ok(atoms::ok().encode(env)),
+        Ok(_ticket) => Ok(atoms::ok().encode(env)),
         Err(e) => Ok((atoms::error(), e.to_string()).encode(env)),
     }
 }